@@ -42,14 +42,18 @@ extern crate log;
 extern crate maidsafe_utilities;
 extern crate clap;
 extern crate config_file_handler;
+extern crate hex;
 extern crate safe_vault;
 #[macro_use]
 extern crate unwrap;
 
-use clap::{App, Arg};
-use safe_vault::Vault;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use hex::ToHex;
+use safe_vault::{ChunkProblem, StoreInspector, Vault};
 use std::ffi::OsString;
 use std::fs;
+use std::path::PathBuf;
+use std::process;
 
 /// Runs a SAFE Network vault.
 pub fn main() {
@@ -63,9 +67,39 @@ pub fn main() {
         .arg(Arg::with_name("first").short("f").long("first").help(
             "Run as the first Vault of a new network.",
         ))
+        .subcommand(store_subcommand(
+            "inspect-store",
+            "List the chunks in a chunk store.",
+        ))
+        .subcommand(store_subcommand(
+            "verify-store",
+            "Check that the chunks in a chunk store are readable and match their names.",
+        ))
+        .subcommand(store_subcommand(
+            "repair-store",
+            "Move the chunks failing verification out of a chunk store, into its quarantine \
+             directory.",
+        ))
+        .subcommand(archive_subcommand(
+            "export-store",
+            "Write the chunks in a chunk store to an archive file.",
+        ))
+        .subcommand(archive_subcommand(
+            "import-store",
+            "Put the chunks from an archive file into a chunk store, creating it if necessary.",
+        ))
         .version(env!("CARGO_PKG_VERSION"))
         .get_matches();
 
+    match matches.subcommand() {
+        ("inspect-store", Some(args)) => return run_store_command(StoreCommand::Inspect, args),
+        ("verify-store", Some(args)) => return run_store_command(StoreCommand::Verify, args),
+        ("repair-store", Some(args)) => return run_store_command(StoreCommand::Repair, args),
+        ("export-store", Some(args)) => return run_export_command(args),
+        ("import-store", Some(args)) => return run_import_command(args),
+        _ => (),
+    }
+
     let _ = maidsafe_utilities::log::init(false);
 
     let mut message = String::from("Running ");
@@ -86,3 +120,146 @@ pub fn main() {
         }
     }
 }
+
+#[derive(Clone, Copy, PartialEq)]
+enum StoreCommand {
+    Inspect,
+    Verify,
+    Repair,
+}
+
+fn store_subcommand<'a, 'b>(name: &'a str, about: &'b str) -> App<'a, 'b> {
+    SubCommand::with_name(name).about(about).arg(
+        Arg::with_name("dir").help(
+            "The chunk store directory.  Defaults to the persistent chunk store of the vault.",
+        ),
+    )
+}
+
+fn archive_subcommand<'a, 'b>(name: &'a str, about: &'b str) -> App<'a, 'b> {
+    SubCommand::with_name(name)
+        .about(about)
+        .arg(
+            Arg::with_name("archive")
+                .help("The archive file.")
+                .required(true)
+                .index(1),
+        )
+        .arg(Arg::with_name("dir").index(2).help(
+            "The chunk store directory.  Defaults to the persistent chunk store of the vault.",
+        ))
+}
+
+fn open_store(dir: Option<PathBuf>, create: bool) -> StoreInspector {
+    let result = if create {
+        StoreInspector::create(dir)
+    } else {
+        StoreInspector::open(dir)
+    };
+    match result {
+        Ok(inspector) => inspector,
+        Err(error) => {
+            println!("Cannot open chunk store: {}", error);
+            process::exit(1);
+        }
+    }
+}
+
+fn run_export_command(args: &ArgMatches) {
+    let inspector = open_store(args.value_of("dir").map(PathBuf::from), false);
+    let archive = unwrap!(args.value_of("archive"));
+    println!("Chunk store: {}", inspector.path().display());
+
+    match inspector.export(archive) {
+        Ok(summary) => {
+            println!(
+                "{} chunks exported to {}, {} unreadable chunks skipped.",
+                summary.exported,
+                archive,
+                summary.skipped
+            )
+        }
+        Err(error) => {
+            println!("Failed to export chunk store: {:?}", error);
+            process::exit(1);
+        }
+    }
+}
+
+fn run_import_command(args: &ArgMatches) {
+    let mut inspector = open_store(args.value_of("dir").map(PathBuf::from), true);
+    let archive = unwrap!(args.value_of("archive"));
+    println!("Chunk store: {}", inspector.path().display());
+
+    match inspector.import(archive) {
+        Ok(summary) => {
+            println!(
+                "{} chunks imported from {}, {} already present, {} rejected.",
+                summary.imported,
+                archive,
+                summary.skipped,
+                summary.rejected
+            );
+            if summary.rejected > 0 {
+                process::exit(1);
+            }
+        }
+        Err(error) => {
+            println!("Failed to import archive: {:?}", error);
+            process::exit(1);
+        }
+    }
+}
+
+// Runs one of the commands operating on a chunk store while the vault is not running.  Exits
+// with a non-zero status if the store can't be opened, or if problems remain in it.
+fn run_store_command(command: StoreCommand, args: &ArgMatches) {
+    let mut inspector = open_store(args.value_of("dir").map(PathBuf::from), false);
+
+    println!("Chunk store: {}", inspector.path().display());
+    let reports = inspector.inspect();
+    let mut problem_count = 0;
+
+    for report in &reports {
+        let problem = match report.problem {
+            Some(ref problem) => problem,
+            None => {
+                if command == StoreCommand::Inspect {
+                    let version = report.version.map_or_else(
+                        String::new,
+                        |version| format!(", version {}", version),
+                    );
+                    println!("{:?}: {} bytes{}", unwrap!(report.data_id), report.size, version);
+                }
+                continue;
+            }
+        };
+
+        let id = report.data_id.map_or_else(
+            || report.key.to_hex(),
+            |data_id| format!("{:?}", data_id),
+        );
+        println!("{}: {} bytes, {}", id, report.size, problem);
+
+        // Chunks failing to decrypt are most likely fine, but the configured key is wrong.
+        if command != StoreCommand::Repair {
+            problem_count += 1;
+        } else if let ChunkProblem::Undecryptable = *problem {
+            println!("  Not quarantined - check the configured key file.");
+            problem_count += 1;
+        } else {
+            match inspector.quarantine(report) {
+                Ok(path) => println!("  Quarantined as {}", path.display()),
+                Err(error) => {
+                    println!("  Failed to quarantine: {:?}", error);
+                    problem_count += 1;
+                }
+            }
+        }
+    }
+
+    println!("{} chunks, {} with problems remaining.", reports.len(), problem_count);
+    if problem_count > 0 {
+        process::exit(1);
+    }
+}
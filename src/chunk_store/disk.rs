@@ -0,0 +1,536 @@
+// Copyright 2017 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use super::{Backend, Error, VALUES_DIR};
+use config_handler::SyncPolicy;
+use fs2::{self, FileExt};
+use hex::{FromHex, ToHex};
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tempdir::TempDir;
+use tiny_keccak;
+use utils::Instant;
+
+const CHUNK_STORE_DIR: &str = "safe_vault_chunk_store";
+
+/// The max name length for a chunk file.
+const MAX_CHUNK_FILE_NAME_LENGTH: usize = 104;
+
+/// The subdirectory holding chunk files which are still being written.
+const TEMP_DIR: &str = "tmp";
+/// The subdirectory holding chunk files moved out of the store by `DiskBackend::quarantine`.
+const QUARANTINE_DIR: &str = "quarantine";
+/// The file locked by the backend using a persistent directory, so no other process uses it at the
+/// same time.
+const LOCK_FILE: &str = "lock";
+
+/// The number of nested shard directories chunk files are spread across.
+const SHARD_LEVELS: usize = 2;
+
+/// Backend storing each chunk as a file named after the hex-encoded key.
+///
+/// To keep directories small, the files are sharded into nested directories named after the hex
+/// prefix of the hash of the key, e.g. `ab/cd/<hex key>`.  The hash is used rather than the key
+/// itself since serialised keys tend to share the same leading bytes.
+///
+/// Chunks are first written to a file in a temporary subdirectory, synced, and only then renamed
+/// into place, so a crash never leaves a truncated chunk file or loses the previous value.  This
+/// holds for the default `SyncPolicy::Always` only: with other policies, chunks written shortly
+/// before a crash may be lost or truncated, which their checksums detect.
+///
+/// A persistent directory is locked while a backend uses it, so two vaults (or a vault and the
+/// store subcommands) never use the same one at the same time.
+pub struct DiskBackend {
+    root_dir: RootDir,
+    // The locked lock file, unlocked when closed.  `None` for temporary directories and backends
+    // sharing the lock of another.
+    _lock: Option<File>,
+    sync_policy: SyncPolicy,
+    // Chunk files and shard directories written but not synced yet.
+    unsynced_files: Vec<PathBuf>,
+    unsynced_dirs: BTreeSet<PathBuf>,
+    sync_time: Instant,
+}
+
+impl DiskBackend {
+    /// Creates a new `DiskBackend`.
+    ///
+    /// If `persistent` is false, the data is stored in a temporary directory which is deleted when
+    /// the process exits.  This directory is named as per `CHUNK_STORE_DIR` with a random suffix.
+    /// If `persistent` is true, the directory is named exactly `CHUNK_STORE_DIR` and is kept when
+    /// the backend is dropped.  If it already exists, it is reopened with its contents.
+    ///
+    /// If `root` is `Some`, then `root` is created and the directory is created inside it.  If
+    /// `root` is `None`, then the directory is created inside [`env::temp_dir()`][1].
+    ///
+    /// Any partially written chunk files left over by a previous crash are removed, and any chunk
+    /// files found directly in the directory (i.e. stored using the old, flat layout)
+    /// are moved into their shard directories.
+    ///
+    /// If the directory cannot be created, or if a test chunk cannot be created therein, the
+    /// function returns an error.  If `persistent` is true and the directory is in use by another
+    /// backend, it returns `Error::Locked`.
+    ///
+    /// [1]: https://doc.rust-lang.org/std/env/fn.temp_dir.html
+    pub fn new(root: Option<String>, persistent: bool) -> Result<Self, Error> {
+        let mut lock = None;
+        let root_dir = if persistent {
+            let path = Self::persistent_dir(root);
+            fs::create_dir_all(&path)?;
+            lock = Some(lock_dir(&path)?);
+            RootDir::Persistent(path)
+        } else {
+            RootDir::Temporary(match root {
+                Some(path) => {
+                    fs::create_dir_all(&path)?;
+                    TempDir::new_in(&path, CHUNK_STORE_DIR)
+                }
+                None => TempDir::new(CHUNK_STORE_DIR),
+            }?)
+        };
+
+        verify_file_name_length(root_dir.path())?;
+        info!("Using chunk store directory {}.", root_dir.path().display());
+
+        let mut backend = DiskBackend::with_root_dir(root_dir);
+        backend._lock = lock;
+        backend.sweep_temp_files()?;
+        backend.migrate_flat_layout()?;
+        Ok(backend)
+    }
+
+    /// Opens an existing chunk store directory as it is, e.g. for offline inspection.
+    ///
+    /// Unlike `new`, this neither creates the directory nor cleans up or migrates its contents.
+    /// The directory is kept when the backend is dropped.  Like `new`, it is locked, so this
+    /// returns `Error::Locked` if it is in use, e.g. by a running vault.
+    pub fn open(dir: PathBuf) -> Result<Self, Error> {
+        let _ = fs::read_dir(&dir)?;
+        let lock = lock_dir(&dir)?;
+        let mut backend = DiskBackend::with_root_dir(RootDir::Persistent(dir));
+        backend._lock = Some(lock);
+        Ok(backend)
+    }
+
+    /// Opens another backend for the same directory, e.g. to use it on another thread.  The new
+    /// backend shares the lock of this one, so it must not outlive it.
+    pub fn reopen(&self) -> Self {
+        let mut backend =
+            DiskBackend::with_root_dir(RootDir::Persistent(self.root_dir.path().to_path_buf()));
+        backend.sync_policy = self.sync_policy;
+        backend
+    }
+
+    /// Returns the directory a persistent backend created with the given `root` is kept in.
+    pub fn persistent_dir(root: Option<String>) -> PathBuf {
+        root.map_or_else(env::temp_dir, PathBuf::from).join(
+            CHUNK_STORE_DIR,
+        )
+    }
+
+    /// Creates (or reopens) the backend for values shared between the chunks of this backend.  It
+    /// is kept in the `values` subdirectory, and uses the same sync policy.
+    pub fn value_store(&self) -> Result<Self, Error> {
+        let dir = self.root_dir.path().join(VALUES_DIR);
+        fs::create_dir_all(&dir)?;
+        let mut backend = DiskBackend::with_root_dir(RootDir::Persistent(dir));
+        backend.sweep_temp_files()?;
+        backend.sync_policy = self.sync_policy;
+        Ok(backend)
+    }
+
+    /// Opens the existing backend for shared values as it is, e.g. for offline inspection.
+    /// Returns `None` if the chunks of this backend share no values.
+    pub fn open_value_store(&self) -> Option<Self> {
+        DiskBackend::open(self.root_dir.path().join(VALUES_DIR)).ok()
+    }
+
+    /// Sets when written chunk files are synced to disk.  Defaults to `SyncPolicy::Always`.
+    pub fn set_sync_policy(&mut self, sync_policy: SyncPolicy) {
+        self.sync_policy = sync_policy;
+    }
+
+    /// Returns when written chunk files are synced to disk.
+    pub fn sync_policy(&self) -> SyncPolicy {
+        self.sync_policy
+    }
+
+    /// Returns the directory holding the chunk files.
+    pub fn path(&self) -> &Path {
+        self.root_dir.path()
+    }
+
+    /// Moves the chunk file stored under `key` out of the store, into the `quarantine`
+    /// subdirectory.  Returns the new path of the file.
+    pub fn quarantine(&self, key: &[u8]) -> Result<PathBuf, Error> {
+        let quarantine_dir = self.root_dir.path().join(QUARANTINE_DIR);
+        fs::create_dir_all(&quarantine_dir)?;
+        let path = quarantine_dir.join(key.to_hex());
+        fs::rename(self.file_path(key), &path)?;
+        Ok(path)
+    }
+
+    fn with_root_dir(root_dir: RootDir) -> Self {
+        DiskBackend {
+            root_dir,
+            _lock: None,
+            sync_policy: SyncPolicy::Always,
+            unsynced_files: Vec::new(),
+            unsynced_dirs: BTreeSet::new(),
+            sync_time: Instant::now(),
+        }
+    }
+
+    // Syncs the unsynced chunk files once as many have been written, or as much time has passed,
+    // as the batched policy allows.
+    fn sync_if_due(&mut self) -> Result<(), Error> {
+        if let SyncPolicy::Batched { interval_ms, writes } = self.sync_policy {
+            if self.unsynced_files.len() >= writes ||
+                self.sync_time.elapsed() >= Duration::from_millis(interval_ms)
+            {
+                return self.sync();
+            }
+        }
+        Ok(())
+    }
+
+    fn temp_dir(&self) -> PathBuf {
+        self.root_dir.path().join(TEMP_DIR)
+    }
+
+    fn shard_dir(&self, key: &[u8]) -> PathBuf {
+        self.root_dir.path().join(shard_path(key))
+    }
+
+    fn file_path(&self, key: &[u8]) -> PathBuf {
+        self.shard_dir(key).join(key.to_hex())
+    }
+
+    // Removes chunk files which were never fully written, e.g. due to a crash.
+    fn sweep_temp_files(&self) -> Result<(), Error> {
+        let temp_dir = self.temp_dir();
+        if temp_dir.is_dir() {
+            let count = fs::read_dir(&temp_dir)?.count();
+            fs::remove_dir_all(&temp_dir)?;
+            if count > 0 {
+                info!("Removed {} partially written chunk files.", count);
+            }
+        }
+        fs::create_dir_all(temp_dir)?;
+        Ok(())
+    }
+
+    // Moves chunk files stored directly in the root directory into their shard directories.
+    fn migrate_flat_layout(&self) -> Result<(), Error> {
+        let mut count = 0;
+        for entry in fs::read_dir(self.root_dir.path())? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let key = match decode_file_name(&entry.file_name()) {
+                Some(key) => key,
+                None => continue,
+            };
+
+            let shard_dir = self.shard_dir(&key);
+            create_shard_dir(&shard_dir)?;
+            fs::rename(entry.path(), shard_dir.join(entry.file_name()))?;
+            count += 1;
+        }
+
+        if count > 0 {
+            info!("Moved {} chunk files into shard directories.", count);
+        }
+        Ok(())
+    }
+}
+
+impl Backend for DiskBackend {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let shard_dir = self.shard_dir(key);
+        create_shard_dir(&shard_dir)?;
+
+        let sync = self.sync_policy == SyncPolicy::Always;
+        let temp_path = self.temp_dir().join(key.to_hex());
+        let file_path = self.file_path(key);
+        let result = File::create(&temp_path)
+            .and_then(|mut file| {
+                file.write_all(value).and_then(
+                    |()| if sync { file.sync_all() } else { Ok(()) },
+                )
+            })
+            .and_then(|()| fs::rename(&temp_path, &file_path))
+            .and_then(|()| if sync { sync_dir(&shard_dir) } else { Ok(()) });
+        if result.is_err() {
+            let _ = fs::remove_file(temp_path);
+        }
+        result?;
+
+        if let SyncPolicy::Batched { .. } = self.sync_policy {
+            self.unsynced_files.push(file_path);
+            let _ = self.unsynced_dirs.insert(shard_dir);
+            self.sync_if_due()?;
+        }
+        Ok(())
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Vec<u8>, Error> {
+        match File::open(self.file_path(key)) {
+            Ok(mut file) => {
+                let mut contents = Vec::<u8>::new();
+                let _ = file.read_to_end(&mut contents)?;
+                Ok(contents)
+            }
+            Err(_) => Err(Error::NotFound),
+        }
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), Error> {
+        match fs::remove_file(self.file_path(key)) {
+            Ok(()) => Ok(()),
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(From::from(error)),
+        }
+    }
+
+    fn has(&self, key: &[u8]) -> bool {
+        self.size(key).is_some()
+    }
+
+    fn keys(&self) -> Vec<Vec<u8>> {
+        let mut keys = Vec::new();
+        collect_keys(self.root_dir.path(), SHARD_LEVELS, &mut keys);
+        keys
+    }
+
+    fn size(&self, key: &[u8]) -> Option<u64> {
+        fs::metadata(self.file_path(key)).ok().and_then(
+            |metadata| if metadata.is_file() {
+                Some(metadata.len())
+            } else {
+                None
+            },
+        )
+    }
+
+    fn allocated_size(&self, key: &[u8]) -> Option<u64> {
+        fs::metadata(self.file_path(key)).ok().and_then(
+            |metadata| if metadata.is_file() {
+                Some(allocated_size(&metadata))
+            } else {
+                None
+            },
+        )
+    }
+
+    fn available_space(&self) -> Option<u64> {
+        match fs2::available_space(self.root_dir.path()) {
+            Ok(space) => Some(space),
+            Err(error) => {
+                warn!("Failed to query free space for chunk store: {}", error);
+                None
+            }
+        }
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        self.sync_time = Instant::now();
+        for path in self.unsynced_files.drain(..) {
+            match File::open(&path).and_then(|file| file.sync_all()) {
+                Ok(()) => (),
+                // Deleted since it was written.
+                Err(ref error) if error.kind() == io::ErrorKind::NotFound => (),
+                Err(error) => return Err(From::from(error)),
+            }
+        }
+        for dir in &self.unsynced_dirs {
+            sync_dir(dir)?;
+        }
+        self.unsynced_dirs.clear();
+        Ok(())
+    }
+
+    fn time_until_sync(&self) -> Option<Duration> {
+        match self.sync_policy {
+            SyncPolicy::Batched { interval_ms, .. } if !self.unsynced_files.is_empty() => {
+                Some(
+                    Duration::from_millis(interval_ms)
+                        .checked_sub(self.sync_time.elapsed())
+                        .unwrap_or_else(|| Duration::from_secs(0)),
+                )
+            }
+            _ => None,
+        }
+    }
+
+    fn dir(&self) -> Option<&Path> {
+        Some(self.path())
+    }
+}
+
+impl Drop for DiskBackend {
+    fn drop(&mut self) {
+        if let Err(error) = self.sync() {
+            warn!("Failed to sync chunk store: {}", error);
+        }
+    }
+}
+
+// The directory holding the chunk files.
+enum RootDir {
+    // Deleted when dropped.
+    Temporary(TempDir),
+    // Kept when dropped, so it can be reopened later.
+    Persistent(PathBuf),
+}
+
+impl RootDir {
+    fn path(&self) -> &Path {
+        match *self {
+            RootDir::Temporary(ref dir) => dir.path(),
+            RootDir::Persistent(ref path) => path,
+        }
+    }
+}
+
+// Returns the space taken by the file on disk, i.e. rounded up to whole blocks.
+#[cfg(unix)]
+fn allocated_size(metadata: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    // `blocks` is always measured in 512-byte units.
+    metadata.blocks() * 512
+}
+
+#[cfg(not(unix))]
+fn allocated_size(metadata: &fs::Metadata) -> u64 {
+    metadata.len()
+}
+
+// Locks the lock file in `dir`, creating it if needed.  The lock is released when the returned
+// file is closed, including when the process exits.
+fn lock_dir(dir: &Path) -> Result<File, Error> {
+    let file = OpenOptions::new().write(true).create(true).open(
+        dir.join(LOCK_FILE),
+    )?;
+    file.try_lock_exclusive().map_err(|_| Error::Locked)?;
+    Ok(file)
+}
+
+// Verifies that chunk files with the longest expected name can be created in `dir`.
+fn verify_file_name_length(dir: &Path) -> Result<(), Error> {
+    let name: String = (0..MAX_CHUNK_FILE_NAME_LENGTH).map(|_| '0').collect();
+    let _ = File::create(dir.join(name.clone()))?;
+    let _ = fs::remove_file(dir.join(name));
+    Ok(())
+}
+
+/// Syncs the directory entries of `dir`, making a preceding rename or file creation durable.
+#[cfg(unix)]
+pub fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir).and_then(|dir| dir.sync_all())
+}
+
+/// Syncs the directory entries of `dir`, making a preceding rename or file creation durable.
+#[cfg(not(unix))]
+pub fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+// Hard links the chunk files stored under `keys` in the store directory `src_dir` into `dst_dir`,
+// laid out the same way, and syncs the created directories.  Chunk files are never modified in
+// place, only replaced by renaming, so the links keep the content at the time they're made.
+// Falls back to copying if `dst_dir` is on another filesystem.  Returns the keys of the linked
+// files, leaving out those deleted since the keys were listed.
+pub fn link_files(src_dir: &Path, keys: &[Vec<u8>], dst_dir: &Path) -> Result<Vec<Vec<u8>>, Error> {
+    fs::create_dir_all(dst_dir)?;
+    let mut shard_dirs = BTreeSet::new();
+    let mut linked = Vec::new();
+    for key in keys {
+        let shard_dir = dst_dir.join(shard_path(key));
+        if shard_dirs.insert(shard_dir.clone()) {
+            fs::create_dir_all(&shard_dir)?;
+        }
+        let src = src_dir.join(shard_path(key)).join(key.to_hex());
+        let dst = shard_dir.join(key.to_hex());
+
+        match fs::hard_link(&src, &dst).or_else(|_| fs::copy(&src, &dst).map(|_| ())) {
+            Ok(()) => linked.push(key.clone()),
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => (),
+            Err(error) => return Err(From::from(error)),
+        }
+    }
+
+    for shard_dir in &shard_dirs {
+        sync_dir(shard_dir)?;
+        if let Some(parent) = shard_dir.parent() {
+            sync_dir(parent)?;
+        }
+    }
+    sync_dir(dst_dir)?;
+    Ok(linked)
+}
+
+// Creates the shard directory if it doesn't exist yet.
+fn create_shard_dir(dir: &Path) -> Result<(), Error> {
+    if dir.is_dir() {
+        return Ok(());
+    }
+    fs::create_dir_all(dir)?;
+    verify_file_name_length(dir)
+}
+
+// Returns the path of the shard directory for `key`, relative to the root directory.
+fn shard_path(key: &[u8]) -> PathBuf {
+    let hash = tiny_keccak::sha3_256(key);
+    hash[..SHARD_LEVELS].iter().fold(PathBuf::new(), |path, byte| {
+        path.join(format!("{:02x}", byte))
+    })
+}
+
+fn decode_file_name(name: &OsStr) -> Option<Vec<u8>> {
+    name.to_str().and_then(|hex_name| Vec::from_hex(hex_name).ok())
+}
+
+fn is_shard_dir_name(name: &OsStr) -> bool {
+    name.to_str().map_or(false, |name| {
+        name.len() == 2 && Vec::<u8>::from_hex(name).is_ok()
+    })
+}
+
+// Collects the keys of all chunk files found `depth` shard levels below `dir`.
+fn collect_keys(dir: &Path, depth: usize, keys: &mut Vec<Vec<u8>>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.filter_map(Result::ok) {
+        if depth == 0 {
+            if let Some(key) = decode_file_name(&entry.file_name()) {
+                keys.push(key);
+            }
+        } else if is_shard_dir_name(&entry.file_name()) && entry.path().is_dir() {
+            collect_keys(&entry.path(), depth - 1, keys);
+        }
+    }
+}
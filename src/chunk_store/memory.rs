@@ -0,0 +1,60 @@
+// Copyright 2017 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use super::{Backend, Error};
+use std::collections::BTreeMap;
+
+/// Backend holding all chunks in memory.  Nothing is written to disk, so the chunks are lost when
+/// the backend is dropped.
+#[derive(Default)]
+pub struct MemoryBackend {
+    chunks: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let _ = self.chunks.insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Vec<u8>, Error> {
+        self.chunks.get(key).cloned().ok_or(Error::NotFound)
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), Error> {
+        let _ = self.chunks.remove(key);
+        Ok(())
+    }
+
+    fn has(&self, key: &[u8]) -> bool {
+        self.chunks.contains_key(key)
+    }
+
+    fn keys(&self) -> Vec<Vec<u8>> {
+        self.chunks.keys().cloned().collect()
+    }
+
+    fn size(&self, key: &[u8]) -> Option<u64> {
+        self.chunks.get(key).map(|value| value.len() as u64)
+    }
+}
@@ -16,27 +16,63 @@
 // relating to use of the SAFE Network Software.
 
 //! # Chunk Store
-//! A simple, non-persistent, disk-based key-value store.
+//! A simple key-value store with a pluggable storage backend, either on disk (optionally persisting
+//! across restarts) or in memory.
 
+mod disk;
+mod memory;
 #[cfg(test)]
 mod tests;
+mod threaded;
 
-use hex::{FromHex, ToHex};
+pub use self::disk::{DiskBackend, sync_dir};
+pub use self::memory::MemoryBackend;
+pub use self::threaded::ThreadedBackend;
+use config_handler::Compression;
+use hex::ToHex;
 use maidsafe_utilities::serialisation::{self, SerialisationError};
+use rust_sodium::crypto::secretbox;
 use serde::Serialize;
 use serde::de::DeserializeOwned;
 use std::cmp;
-use std::fs::{self, File};
-use std::io::{self, Read, Write};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read};
 use std::marker::PhantomData;
 use std::path::{Path, PathBuf};
-use tempdir::TempDir;
+use std::time::Duration;
+use tiny_keccak;
+use zstd;
 
-const CHUNK_STORE_DIR: &str = "safe_vault_chunk_store";
 const DEFAULT_MAX_CAPACITY: u64 = 2 * 1024 * 1024 * 1024;
+/// Version of the header stored in front of every value.
+const FORMAT_VERSION: u8 = 1;
+/// Length of the header: the format version, the codec, the cipher, the encryption nonce and the
+/// SHA3-256 checksum of the stored value.
+const HEADER_LEN: usize = 1 + 1 + 1 + secretbox::NONCEBYTES + 32;
+/// Codec of values stored uncompressed.
+const CODEC_NONE: u8 = 0;
+/// Codec of values compressed using zstd.
+const CODEC_ZSTD: u8 = 1;
+/// The zstd compression level.
+const ZSTD_LEVEL: i32 = 3;
+/// Cipher of values stored unencrypted.
+const CIPHER_NONE: u8 = 0;
+/// Cipher of values encrypted using `secretbox`.
+const CIPHER_SECRETBOX: u8 = 1;
+/// Flag set in the codec of chunks referring to shared values rather than holding them.
+const CODEC_SHARED_VALUES: u8 = 0x80;
+/// Minimum size in bytes of values shared between chunks.  Smaller ones are kept in the chunk.
+const MIN_SHARED_VALUE_LEN: usize = 1024;
+/// Prefix of the keys shared values are stored under in the value store.
+const VALUE_KEY_PREFIX: u8 = 0;
+/// Prefix of the keys the reference counts of shared values are stored under in the value store.
+const REFCOUNT_KEY_PREFIX: u8 = 1;
+/// Subdirectory of a disk chunk store (or of its snapshots) holding the shared values.
+const VALUES_DIR: &str = "values";
 
-/// The max name length for a chunk file.
-const MAX_CHUNK_FILE_NAME_LENGTH: usize = 104;
+/// Hash identifying a value shared between chunks.
+pub type ValueHash = [u8; 32];
 
 quick_error! {
     /// `ChunkStore` error.
@@ -61,16 +97,59 @@ quick_error! {
             description("Not enough space")
             display("Not enough space")
         }
+        /// The encryption key file doesn't hold a valid key.
+        InvalidKey {
+            description("Invalid encryption key")
+            display("Encryption key file doesn't hold a valid key")
+        }
+        /// The stored value can't be decrypted, e.g. because it was encrypted with a different
+        /// key.
+        Decryption {
+            description("Decryption failed")
+            display("Stored value can't be decrypted")
+        }
+        /// The chunk store directory is in use by another process.
+        Locked {
+            description("Chunk store locked")
+            display("Chunk store directory is in use by another process")
+        }
         /// Key, Value pair not found in `ChunkStore`.
         NotFound {
             description("Key, Value not found")
             display("Key, Value not found")
         }
+        /// The stored value doesn't match its checksum.
+        Corrupted {
+            description("Value corrupted")
+            display("Stored value doesn't match its checksum")
+        }
     }
 }
 
 pub trait Chunk<K>: Serialize + DeserializeOwned {
     type Id: ChunkId<K>;
+
+    /// Splits the chunk into a serialised remainder and the values of at least `min_len` bytes it
+    /// holds, so the values can be stored separately and shared between chunks.  The remainder
+    /// refers to the values by the hash computed by `hash`.
+    ///
+    /// Returns `None` if the chunk holds no such values, or doesn't support sharing them.
+    fn split_values(
+        &self,
+        _min_len: usize,
+        _hash: &Fn(&[u8]) -> ValueHash,
+    ) -> Option<(Vec<u8>, Vec<(ValueHash, Vec<u8>)>)> {
+        None
+    }
+
+    /// Restores a chunk from a remainder returned by `split_values`, calling `value` to look up
+    /// the values it refers to.
+    fn join_values(
+        _remainder: &[u8],
+        _value: &mut FnMut(&ValueHash) -> Result<Vec<u8>, Error>,
+    ) -> Result<Self, Error> {
+        Err(Error::Corrupted)
+    }
 }
 
 pub trait ChunkId<K>: Serialize {
@@ -78,141 +157,234 @@ pub trait ChunkId<K>: Serialize {
     fn to_key(&self) -> K;
 }
 
-/// `ChunkStore` is a store of data held as serialised files on disk, implementing a maximum disk
-/// usage to restrict storage.
+/// Storage backend of a `ChunkStore`.  Values are the serialised chunks, keyed by the serialised
+/// chunk keys.
+pub trait Backend {
+    /// Stores `value` under `key`, overwriting any existing value.  If this fails, the previous
+    /// value must be left intact.
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error>;
+    /// Returns the value stored under `key`, or `Error::NotFound`.
+    fn get(&self, key: &[u8]) -> Result<Vec<u8>, Error>;
+    /// Deletes the value stored under `key`.  Does nothing if there is no such value.
+    fn delete(&mut self, key: &[u8]) -> Result<(), Error>;
+    /// Tests if a value is stored under `key`.
+    fn has(&self, key: &[u8]) -> bool;
+    /// Lists all keys of currently stored values.
+    fn keys(&self) -> Vec<Vec<u8>>;
+    /// Returns the size in bytes of the value stored under `key`, if any.
+    fn size(&self, key: &[u8]) -> Option<u64>;
+    /// Returns the storage space in bytes actually taken by the value stored under `key`, if any,
+    /// including any overhead such as filesystem block rounding.
+    fn allocated_size(&self, key: &[u8]) -> Option<u64> {
+        self.size(key)
+    }
+    /// Returns the free space in bytes left in the underlying storage, if it is limited.
+    fn available_space(&self) -> Option<u64> {
+        None
+    }
+    /// Returns the directory holding the values, for backends storing each value in a file laid
+    /// out as by `DiskBackend`, which can be snapshotted by hard linking the files.
+    fn dir(&self) -> Option<&Path> {
+        None
+    }
+    /// Lists the keys of the values whose writes or deletions haven't completed yet.
+    fn writing_keys(&self) -> Vec<Vec<u8>> {
+        Vec::new()
+    }
+    /// Returns the keys and outcomes of the writes and deletions completed since the last call,
+    /// for backends completing them asynchronously.  Others complete them within `put` and
+    /// `delete`, so this returns nothing.
+    fn poll_completed(&mut self) -> Vec<(Vec<u8>, Result<(), Error>)> {
+        Vec::new()
+    }
+    /// Tests if a write or deletion of the value under `key` hasn't completed yet.
+    fn is_writing(&self, _key: &[u8]) -> bool {
+        false
+    }
+    /// Waits up to `timeout` until a write or deletion completes, if any is in progress.  Its
+    /// outcome is still returned by `poll_completed`.
+    fn wait_completed(&mut self, _timeout: Duration) {}
+    /// Makes all completed writes durable, for backends which don't always do so immediately.
+    fn sync(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+    /// Returns how long until the completed writes which aren't durable yet are due to be synced
+    /// with `sync`, or `None` if there are none, or if the backend syncs them on its own.
+    fn time_until_sync(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// `ChunkStore` is a store of data held as serialised values in a `Backend`, implementing a
+/// maximum usage to restrict storage.
+///
+/// Each value is stored, optionally compressed and encrypted, along with its checksum, which is
+/// verified whenever it is read back.  The used space accounts for the stored (i.e. compressed and
+/// encrypted) size of the values, but not for the header holding the codec, cipher and checksum.
 ///
-/// The data chunks are deleted when the `ChunkStore` goes out of scope.
+/// With a value store set, large values held by chunks (such as `MutableData` entry values) can be
+/// stored once in the value store and shared by all chunks holding them.  The chunks then refer to
+/// the values by hash, and the values are reference counted, so they are deleted along with the
+/// last chunk referring to them.
 pub struct ChunkStore<K> {
-    root_dir: TempDir,
+    backend: Box<Backend>,
+    values: Option<Box<Backend>>,
+    deduplicate: bool,
+    compression: Compression,
+    encryption_key: Option<secretbox::Key>,
+    allow_plaintext: bool,
     max_space: u64,
     used_space: u64,
+    disk_usage: u64,
     phantom: PhantomData<K>,
 }
 
 impl<K: DeserializeOwned + Serialize> ChunkStore<K> {
-    /// Creates a new `ChunkStore`.
+    /// Creates a new `ChunkStore` on top of `backend`.
     ///
     /// The maximum storage space is defined by `capacity`, or `DEFAULT_MAX_CAPACITY` if this is
-    /// `None`.
-    ///
-    /// The data is stored in a temporary directory which is deleted when the process exits.  This
-    /// directory is named as per `CHUNK_STORE_DIR` with a random suffix.  If `root` is `Some`, then
-    /// `root` is created and the directory is created inside it.  If `root` is `None`, then the
-    /// directory is created inside [`env::temp_dir()`][1].
+    /// `None`.  New values are stored using `compression`; existing ones are readable regardless of
+    /// how they were compressed.
     ///
-    /// If the directory cannot be created, or if a test chunk cannot be created therein, the
-    /// function returns an error.
+    /// If `encryption_key` is `Some`, new values are encrypted with it, and existing unencrypted
+    /// values can't be read unless allowed by `set_allow_plaintext`.
     ///
-    /// [1]: https://doc.rust-lang.org/std/env/fn.temp_dir.html
-    pub fn new(root: Option<String>, capacity: Option<u64>) -> Result<Self, Error> {
-        let root_dir = match root {
-            Some(path) => {
-                fs::create_dir_all(&path)?;
-                TempDir::new_in(&path, CHUNK_STORE_DIR)
-            }
-            None => TempDir::new(CHUNK_STORE_DIR),
-        }?;
-        let max_space = capacity.unwrap_or(DEFAULT_MAX_CAPACITY);
-
-        // Verify that chunk files can be created.
-        let name: String = (0..MAX_CHUNK_FILE_NAME_LENGTH).map(|_| '0').collect();
-        let _ = File::create(root_dir.path().join(name.clone()))?;
-        let _ = fs::remove_file(root_dir.path().join(name));
+    /// If the backend already holds data (e.g. a reopened persistent store), the used space is
+    /// rebuilt from it.  Stored values whose keys don't decode to a valid `K` are ignored.
+    pub fn new(
+        backend: Box<Backend>,
+        capacity: Option<u64>,
+        compression: Compression,
+        encryption_key: Option<secretbox::Key>,
+    ) -> Result<Self, Error> {
+        let (used_space, disk_usage) = measure_usage::<K>(&*backend);
+        let chunk_store = ChunkStore {
+            backend,
+            values: None,
+            deduplicate: false,
+            compression,
+            encryption_key,
+            allow_plaintext: false,
+            max_space: capacity.unwrap_or(DEFAULT_MAX_CAPACITY),
+            used_space,
+            disk_usage,
+            phantom: PhantomData,
+        };
 
         info!(
-            "Created chunk store at {} with capacity of {} bytes.",
-            root_dir.path().display(),
-            max_space
+            "Opened chunk store with capacity of {} bytes ({} bytes used, {} bytes on disk).",
+            chunk_store.max_space,
+            chunk_store.used_space,
+            chunk_store.disk_usage
         );
 
-        Ok(ChunkStore {
-            root_dir,
-            max_space,
-            used_space: 0,
-            phantom: PhantomData,
-        })
+        Ok(chunk_store)
     }
 
     /// Stores a new data chunk.
     ///
     /// If there is not enough storage space available, returns `Error::NotEnoughSpace`.  In case of
-    /// an IO error, it returns `Error::Io`.
+    /// a backend error, it returns that error.
     ///
     /// If a data with the same id already exists, it will be overwritten.
     pub fn put<T: Chunk<K>>(&mut self, id: &T::Id, value: &T) -> Result<(), Error> {
-        let serialised_value = serialisation::serialise(value)?;
-        if self.used_space + serialised_value.len() as u64 > self.max_space {
+        let (serialised_value, shared_values) = match self.split_values(value) {
+            Some((remainder, shared_values)) => {
+                let hashes: Vec<ValueHash> = shared_values.keys().cloned().collect();
+                (serialisation::serialise(&(hashes, remainder))?, shared_values)
+            }
+            None => (serialisation::serialise(value)?, BTreeMap::new()),
+        };
+        let flags = if shared_values.is_empty() {
+            0
+        } else {
+            CODEC_SHARED_VALUES
+        };
+        let (header, stored_value) = self.seal(serialised_value, flags)?;
+
+        // Shared values already stored take no additional space.
+        let new_values_len: u64 = shared_values
+            .iter()
+            .filter(|&(hash, _)| self.refcount(hash) == 0)
+            .map(|(_, value)| value.len() as u64)
+            .sum();
+        if self.used_space + stored_value.len() as u64 + new_values_len > self.max_space {
+            return Err(Error::NotEnoughSpace);
+        }
+
+        // If a chunk with the same id already exists, it is replaced by the backend, so only
+        // release its space once the new value has been stored.
+        let contents_len = (HEADER_LEN + stored_value.len()) as u64 + new_values_len;
+        if self.available_space().map_or(false, |available| available < contents_len) {
             return Err(Error::NotEnoughSpace);
         }
 
-        // If a file corresponding to the chunk id already exists, delete it.
-        let file_path = self.file_path(id)?;
-        let _ = self.do_delete(&file_path);
+        let key = serialisation::serialise(&id.to_key())?;
+        let old_size = value_size(&*self.backend, &key).unwrap_or(0);
+        let old_disk_usage = self.backend.allocated_size(&key).unwrap_or(0);
+        let old_hashes = self.shared_value_hashes(&key);
 
-        // Write the file.
-        File::create(&file_path)
-            .and_then(|mut file| {
-                file.write_all(&serialised_value)
-                    .and_then(|()| file.sync_all())
-                    .and_then(|()| file.metadata())
-                    .map(|metadata| {
-                        self.used_space += metadata.len();
-                    })
-            })
-            .map_err(From::from)
+        // The shared values are stored first, so they're never missing for a stored chunk.  If
+        // storing the chunk fails, or we crash before releasing the values of the previous one,
+        // some values may be kept longer than needed, but that's safe.
+        let new_hashes: Vec<ValueHash> = shared_values.keys().cloned().collect();
+        self.retain_values(shared_values)?;
+        if let Err(error) = self.backend.put(&key, &encode(&header, &stored_value)) {
+            self.release_values(&new_hashes);
+            return Err(error);
+        }
+        self.release_values(&old_hashes);
+
+        self.used_space -= cmp::min(old_size, self.used_space);
+        self.used_space += stored_value.len() as u64;
+        self.disk_usage -= cmp::min(old_disk_usage, self.disk_usage);
+        self.disk_usage += self.backend.allocated_size(&key).unwrap_or(0);
+        Ok(())
     }
 
     /// Deletes the data chunk stored under `id`.
     ///
-    /// If the data doesn't exist, it does nothing and returns `Ok`.  In the case of an IO error, it
-    /// returns `Error::Io`.
+    /// If the data doesn't exist, it does nothing and returns `Ok`.  In the case of a backend error,
+    /// it returns that error.
     pub fn delete<I: ChunkId<K>>(&mut self, id: &I) -> Result<(), Error> {
-        let file_path = self.file_path(id)?;
-        self.do_delete(&file_path)
+        let key = serialisation::serialise(&id.to_key())?;
+        self.do_delete(&key)
     }
 
     /// Returns a data chunk previously stored under `id`.
     ///
-    /// If the data file can't be accessed, it returns `Error::ChunkNotFound`.
+    /// If the data can't be accessed, it returns `Error::NotFound`.  If it doesn't match its
+    /// checksum, it returns `Error::Corrupted`, and if it can't be decrypted, `Error::Decryption`.
     pub fn get<I: ChunkId<K>>(&self, id: &I) -> Result<I::Chunk, Error> {
-        match File::open(self.file_path(id)?) {
-            Ok(mut file) => {
-                let mut contents = Vec::<u8>::new();
-                let _ = file.read_to_end(&mut contents)?;
-                Ok(serialisation::deserialise(&contents)?)
-            }
-            Err(_) => Err(Error::NotFound),
+        let key = serialisation::serialise(&id.to_key())?;
+        let contents = self.backend.get(&key)?;
+        let (flags, serialised_value) = self.open(&contents)?;
+        if flags & CODEC_SHARED_VALUES == 0 {
+            return Ok(serialisation::deserialise(&serialised_value)?);
         }
+
+        let (_, remainder): (Vec<ValueHash>, Vec<u8>) =
+            serialisation::deserialise(&serialised_value)?;
+        <I::Chunk as Chunk<K>>::join_values(&remainder, &mut |hash: &ValueHash| {
+            self.get_value(hash)
+        })
     }
 
     /// Tests if a data chunk has been previously stored under `id`.
     pub fn has<I: ChunkId<K>>(&self, id: &I) -> bool {
-        let file_path = if let Ok(path) = self.file_path(id) {
-            path
-        } else {
-            return false;
-        };
-        if let Ok(metadata) = fs::metadata(file_path) {
-            return metadata.is_file();
-        } else {
-            false
-        }
+        serialisation::serialise(&id.to_key()).ok().map_or(
+            false,
+            |key| self.backend.has(&key),
+        )
     }
 
     /// Lists all keys of currently stored data.
     pub fn keys(&self) -> Vec<K> {
-        fs::read_dir(self.root_dir.path())
-            .and_then(|dir_entries| {
-                let dir_entry_to_routing_name = |dir_entry: io::Result<fs::DirEntry>| {
-                    dir_entry
-                        .ok()
-                        .and_then(|entry| entry.file_name().into_string().ok())
-                        .and_then(|hex_name| Vec::from_hex(hex_name).ok())
-                        .and_then(|bytes| serialisation::deserialise(&*bytes).ok())
-                };
-                Ok(dir_entries.filter_map(dir_entry_to_routing_name).collect())
-            })
-            .unwrap_or_else(|_| Vec::new())
+        self.backend
+            .keys()
+            .into_iter()
+            .filter_map(|key| serialisation::deserialise(&key).ok())
+            .collect()
     }
 
     /// Returns the maximum amount of storage space available for this `ChunkStore`.
@@ -221,22 +393,552 @@ impl<K: DeserializeOwned + Serialize> ChunkStore<K> {
     }
 
     /// Returns the amount of storage space already used by this `ChunkStore`.
+    ///
+    /// This is the logical size of the stored values, which `max_space` applies to.
     pub fn used_space(&self) -> u64 {
         self.used_space
     }
 
-    fn do_delete(&mut self, file_path: &Path) -> Result<(), Error> {
-        if let Ok(metadata) = fs::metadata(file_path) {
-            self.used_space -= cmp::min(metadata.len(), self.used_space);
-            fs::remove_file(file_path).map_err(From::from)
+    /// Returns the storage space actually taken by this `ChunkStore`, including headers and any
+    /// overhead of the backend such as filesystem block rounding.
+    pub fn disk_usage(&self) -> u64 {
+        self.disk_usage
+    }
+
+    /// Returns the free space left in the storage underlying this `ChunkStore`, if it is limited.
+    pub fn available_space(&self) -> Option<u64> {
+        self.backend.available_space()
+    }
+
+    /// Sets the store for values shared between chunks.  Chunks referring to shared values are
+    /// only readable with the value store they were stored with.
+    ///
+    /// If `deduplicate` is true, large values of new chunks are moved to the value store, and
+    /// shared with any other chunks holding the same values.  Otherwise, new chunks hold all their
+    /// values, but existing chunks referring to shared values remain readable.
+    pub fn set_value_store(&mut self, values: Box<Backend>, deduplicate: bool) {
+        let (used_space, disk_usage) = measure_value_usage(&*values);
+        self.used_space += used_space;
+        self.disk_usage += disk_usage;
+        self.values = Some(values);
+        self.deduplicate = deduplicate;
+    }
+
+    /// Sets whether unencrypted values remain readable when an encryption key is set, to migrate
+    /// an existing chunk store to encryption.  Otherwise, reading them fails with
+    /// `Error::Decryption`, so that unencrypted values put in place of encrypted ones are rejected.
+    pub fn set_allow_plaintext(&mut self, allow_plaintext: bool) {
+        self.allow_plaintext = allow_plaintext;
+    }
+
+    /// Recomputes the used space and disk usage from the stored values, correcting any drift.
+    pub fn reconcile(&mut self) {
+        let (mut used_space, mut disk_usage) = measure_usage::<K>(&*self.backend);
+        if let Some(ref values) = self.values {
+            let (values_used_space, values_disk_usage) = measure_value_usage(&**values);
+            used_space += values_used_space;
+            disk_usage += values_disk_usage;
+        }
+        if used_space != self.used_space || disk_usage != self.disk_usage {
+            info!(
+                "Reconciled chunk store usage: {} bytes used (was {}), {} bytes on disk (was {}).",
+                used_space,
+                self.used_space,
+                disk_usage,
+                self.disk_usage
+            );
+            self.used_space = used_space;
+            self.disk_usage = disk_usage;
+        }
+    }
+
+    /// Returns the keys of the chunks whose writes or deletions failed in the background since
+    /// the last call, along with the errors.
+    ///
+    /// Only backends writing asynchronously (such as `ThreadedBackend`) complete writes in the
+    /// background.  As the space accounted for a failed write is unknown, the usage is reconciled.
+    pub fn poll_failed_writes(&mut self) -> Vec<(K, Error)> {
+        let failed: Vec<_> = self.backend
+            .poll_completed()
+            .into_iter()
+            .filter_map(|(key, result)| result.err().map(|error| (key, error)))
+            .collect();
+        if failed.is_empty() {
+            return Vec::new();
+        }
+
+        self.reconcile();
+        failed
+            .into_iter()
+            .filter_map(|(key, error)| {
+                serialisation::deserialise(&key).ok().map(|key| (key, error))
+            })
+            .collect()
+    }
+
+    /// Makes all completed writes durable, if the backend defers syncing them to disk.
+    pub fn sync(&mut self) -> Result<(), Error> {
+        self.backend.sync()?;
+        if let Some(ref mut values) = self.values {
+            values.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Returns how long until the writes the backend deferred syncing are due to be synced with
+    /// `sync_if_due`, or `None` if there are none.
+    pub fn time_until_sync(&self) -> Option<Duration> {
+        let values_time = self.values.as_ref().and_then(|values| values.time_until_sync());
+        match (self.backend.time_until_sync(), values_time) {
+            (Some(time), Some(values_time)) => Some(cmp::min(time, values_time)),
+            (time, values_time) => time.or(values_time),
+        }
+    }
+
+    /// Syncs the writes the backend deferred syncing if they are due, so they aren't left unsynced
+    /// for longer than the sync policy allows when no further writes trigger the sync.
+    pub fn sync_if_due(&mut self) -> Result<(), Error> {
+        if self.backend.time_until_sync() == Some(Duration::from_secs(0)) {
+            self.backend.sync()?;
+        }
+        if let Some(ref mut values) = self.values {
+            if values.time_until_sync() == Some(Duration::from_secs(0)) {
+                values.sync()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits up to `timeout` until a write or deletion in progress in the background completes.
+    /// Returns immediately if there are none, or if one already completed since the last call to
+    /// `poll_failed_writes`.
+    pub fn wait_for_writes(&mut self, timeout: Duration) {
+        self.backend.wait_completed(timeout)
+    }
+
+    /// Tests if a write or deletion of the chunk stored under `key` is still in progress in the
+    /// background.
+    pub fn is_writing(&self, key: &K) -> bool {
+        serialisation::serialise(key).ok().map_or(
+            false,
+            |key| self.backend.is_writing(&key),
+        )
+    }
+
+    /// Lists the stored data and the writes still in progress, so `SnapshotJob::save` can save a
+    /// copy of the data on another thread, without waiting for the writes.
+    ///
+    /// Returns an error if the backend doesn't store the data in files which can be hard linked.
+    pub fn prepare_snapshot(&self) -> Result<SnapshotJob<K>, Error> {
+        let dir = snapshot_dir(&*self.backend)?;
+        let values = match self.values {
+            Some(ref values) => Some((snapshot_dir(&**values)?, values.keys())),
+            None => None,
+        };
+        Ok(SnapshotJob {
+            dir,
+            keys: self.backend.keys(),
+            values,
+            writing: self.backend
+                .writing_keys()
+                .into_iter()
+                .filter_map(|key| serialisation::deserialise(&key).ok())
+                .collect(),
+        })
+    }
+
+    /// Takes the chunk stored under the raw `key` out of the store by calling `remove` with the
+    /// key, e.g. to move the chunk file aside rather than delete it, and releases the shared values
+    /// the chunk refers to.  Returns what `remove` returns.
+    ///
+    /// The references of a chunk which can't be read (e.g. because it is corrupted) are unknown,
+    /// so the values it refers to are kept.
+    pub fn remove_with<T, F>(&mut self, key: &[u8], remove: F) -> Result<T, Error>
+    where
+        F: FnOnce(&[u8]) -> Result<T, Error>,
+    {
+        let size = value_size(&*self.backend, key).unwrap_or(0);
+        let hashes = self.shared_value_hashes(key);
+        let disk_usage = self.backend.allocated_size(key).unwrap_or(0);
+        let result = remove(key)?;
+        self.used_space -= cmp::min(size, self.used_space);
+        self.disk_usage -= cmp::min(disk_usage, self.disk_usage);
+        self.release_values(&hashes);
+        Ok(result)
+    }
+
+    fn do_delete(&mut self, key: &[u8]) -> Result<(), Error> {
+        if let Some(size) = value_size(&*self.backend, key) {
+            let hashes = self.shared_value_hashes(key);
+            let disk_usage = self.backend.allocated_size(key).unwrap_or(0);
+            self.backend.delete(key)?;
+            self.used_space -= cmp::min(size, self.used_space);
+            self.disk_usage -= cmp::min(disk_usage, self.disk_usage);
+            self.release_values(&hashes);
+            Ok(())
         } else {
             Ok(())
         }
     }
 
-    fn file_path<I: ChunkId<K>>(&self, id: &I) -> Result<PathBuf, Error> {
-        let filename = serialisation::serialise(&id.to_key())?.to_hex();
-        let path_name = Path::new(&filename);
-        Ok(self.root_dir.path().join(path_name))
+    // Compresses and encrypts a serialised value for storing, marking the codec with `flags`.
+    fn seal(&self, serialised_value: Vec<u8>, flags: u8) -> Result<(Header, Vec<u8>), Error> {
+        let (codec, compressed_value) = compress(self.compression, serialised_value)?;
+        let (nonce, stored_value) = encrypt(self.encryption_key.as_ref(), compressed_value);
+        let header = Header {
+            codec: codec | flags,
+            nonce,
+        };
+        Ok((header, stored_value))
+    }
+
+    // Verifies, decrypts and decompresses stored `contents`.  Returns the flags of the codec, and
+    // the serialised value.
+    fn open(&self, contents: &[u8]) -> Result<(u8, Vec<u8>), Error> {
+        let (header, stored_value) = decode(contents)?;
+        let compressed_value = decrypt(
+            self.encryption_key.as_ref(),
+            header.nonce,
+            stored_value,
+            self.allow_plaintext,
+        )?;
+        let flags = header.codec & CODEC_SHARED_VALUES;
+        let serialised_value = decompress(header.codec & !flags, &compressed_value)?;
+        Ok((flags, serialised_value))
+    }
+
+    fn split_values<T: Chunk<K>>(
+        &self,
+        value: &T,
+    ) -> Option<(Vec<u8>, BTreeMap<ValueHash, Vec<u8>>)> {
+        if !self.deduplicate || self.values.is_none() {
+            return None;
+        }
+
+        let encryption_key = self.encryption_key.as_ref();
+        value
+            .split_values(MIN_SHARED_VALUE_LEN, &|value: &[u8]| {
+                value_hash(encryption_key, value)
+            })
+            .map(|(remainder, values)| (remainder, values.into_iter().collect()))
+    }
+
+    // Returns the hashes of the shared values the chunk stored under `key` refers to.
+    fn shared_value_hashes(&self, key: &[u8]) -> Vec<ValueHash> {
+        if self.values.is_none() {
+            return Vec::new();
+        }
+
+        let contents = match self.backend.get(key) {
+            Ok(contents) => contents,
+            Err(_) => return Vec::new(),
+        };
+        match self.open(&contents) {
+            Ok((flags, ref serialised_value)) if flags & CODEC_SHARED_VALUES != 0 => {
+                serialisation::deserialise::<(Vec<ValueHash>, Vec<u8>)>(serialised_value)
+                    .map(|(hashes, _)| hashes)
+                    .unwrap_or_else(|_| Vec::new())
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn get_value(&self, hash: &ValueHash) -> Result<Vec<u8>, Error> {
+        let values = self.values.as_ref().ok_or(Error::Corrupted)?;
+        // A chunk referring to a missing value is as good as corrupted.
+        let contents = values.get(&value_key(hash)).map_err(|_| Error::Corrupted)?;
+        self.open(&contents).map(|(_, value)| value)
+    }
+
+    fn refcount(&self, hash: &ValueHash) -> u64 {
+        self.values
+            .as_ref()
+            .and_then(|values| values.get(&refcount_key(hash)).ok())
+            .and_then(|contents| serialisation::deserialise(&contents).ok())
+            .unwrap_or(0)
+    }
+
+    fn set_refcount(&mut self, hash: &ValueHash, refcount: u64) -> Result<(), Error> {
+        let contents = serialisation::serialise(&refcount)?;
+        match self.values {
+            Some(ref mut values) => values.put(&refcount_key(hash), &contents),
+            None => Err(Error::NotFound),
+        }
+    }
+
+    // Stores the shared `values` a new chunk refers to, or counts the new reference to them if
+    // they're already stored.
+    fn retain_values(&mut self, values: BTreeMap<ValueHash, Vec<u8>>) -> Result<(), Error> {
+        for (hash, value) in values {
+            let refcount = self.refcount(&hash);
+            if refcount == 0 {
+                let (header, stored_value) = self.seal(value, 0)?;
+                let key = value_key(&hash);
+                let disk_usage = match self.values {
+                    Some(ref mut values) => {
+                        values.put(&key, &encode(&header, &stored_value))?;
+                        values.allocated_size(&key).unwrap_or(0)
+                    }
+                    None => return Err(Error::NotFound),
+                };
+                self.used_space += stored_value.len() as u64;
+                self.disk_usage += disk_usage;
+            }
+            self.set_refcount(&hash, refcount + 1)?;
+        }
+        Ok(())
+    }
+
+    // Releases a reference to each of the shared values, deleting those no longer referred to.
+    fn release_values(&mut self, hashes: &[ValueHash]) {
+        for hash in hashes {
+            if let Err(error) = self.release_value(hash) {
+                warn!("Failed to release shared value {}: {}", hash[..].to_hex(), error);
+            }
+        }
+    }
+
+    fn release_value(&mut self, hash: &ValueHash) -> Result<(), Error> {
+        let refcount = self.refcount(hash);
+        if refcount > 1 {
+            return self.set_refcount(hash, refcount - 1);
+        }
+
+        let key = value_key(hash);
+        let (size, disk_usage) = match self.values {
+            Some(ref mut values) => {
+                let size = value_size(&**values, &key).unwrap_or(0);
+                let disk_usage = values.allocated_size(&key).unwrap_or(0);
+                values.delete(&key)?;
+                values.delete(&refcount_key(hash))?;
+                (size, disk_usage)
+            }
+            None => return Ok(()),
+        };
+        self.used_space -= cmp::min(size, self.used_space);
+        self.disk_usage -= cmp::min(disk_usage, self.disk_usage);
+        Ok(())
+    }
+}
+
+/// The files of a `ChunkStore` to save a copy of, listed by `ChunkStore::prepare_snapshot`.
+pub struct SnapshotJob<K> {
+    dir: PathBuf,
+    keys: Vec<Vec<u8>>,
+    // The directory and keys of the shared values, if any.
+    values: Option<(PathBuf, Vec<Vec<u8>>)>,
+    writing: Vec<K>,
+}
+
+impl<K: DeserializeOwned> SnapshotJob<K> {
+    /// Returns the data whose writes or deletions were still in progress when the snapshot was
+    /// prepared.  Their saved copies may hold either the old or the new content, or be missing.
+    pub fn writing(&self) -> &[K] {
+        &self.writing
+    }
+
+    /// Saves a copy of the listed data into the empty directory `dir`, which can then be opened
+    /// with `DiskBackend::open`, and syncs the directory.  Returns the keys of the saved data, i.e.
+    /// those not deleted since being listed.
+    pub fn save(self, dir: &Path) -> Result<Vec<K>, Error> {
+        let keys = disk::link_files(&self.dir, &self.keys, dir)?;
+        if let Some((values_dir, value_keys)) = self.values {
+            let _ = disk::link_files(&values_dir, &value_keys, &dir.join(VALUES_DIR))?;
+            sync_dir(dir)?;
+        }
+
+        Ok(
+            keys.into_iter()
+                .filter_map(|key| serialisation::deserialise(&key).ok())
+                .collect(),
+        )
+    }
+}
+
+/// Reads a `secretbox` key for encrypting chunks from `path`.  The file must hold exactly the raw
+/// key bytes.
+pub fn read_key_file<P: AsRef<Path>>(path: P) -> Result<secretbox::Key, Error> {
+    let mut contents = Vec::new();
+    let _ = File::open(path)?.read_to_end(&mut contents)?;
+    secretbox::Key::from_slice(&contents).ok_or(Error::InvalidKey)
+}
+
+// The header stored in front of every value.
+struct Header {
+    codec: u8,
+    // `Some` if the value is encrypted.
+    nonce: Option<secretbox::Nonce>,
+}
+
+fn snapshot_dir(backend: &Backend) -> Result<PathBuf, Error> {
+    backend.dir().map(Path::to_path_buf).ok_or_else(|| {
+        Error::Io(io::Error::new(
+            io::ErrorKind::Other,
+            "snapshots not supported by this backend",
+        ))
+    })
+}
+
+// Returns the used space and disk usage of all values in `backend` whose keys decode to a valid
+// `K`.
+fn measure_usage<K: DeserializeOwned>(backend: &Backend) -> (u64, u64) {
+    let mut used_space = 0;
+    let mut disk_usage = 0;
+    for key in backend.keys() {
+        if serialisation::deserialise::<K>(&key).is_ok() {
+            used_space += value_size(backend, &key).unwrap_or(0);
+            disk_usage += backend.allocated_size(&key).unwrap_or(0);
+        } else {
+            warn!("Ignoring unrecognised chunk {} in chunk store.", key.to_hex());
+        }
+    }
+    (used_space, disk_usage)
+}
+
+// Returns the used space and disk usage of the shared values in the value store `values`.
+fn measure_value_usage(values: &Backend) -> (u64, u64) {
+    let mut used_space = 0;
+    let mut disk_usage = 0;
+    for key in values.keys() {
+        if key.first() == Some(&VALUE_KEY_PREFIX) {
+            used_space += value_size(values, &key).unwrap_or(0);
+            disk_usage += values.allocated_size(&key).unwrap_or(0);
+        }
+    }
+    (used_space, disk_usage)
+}
+
+// Returns the hash identifying a shared value.  If chunks are encrypted, the hash is keyed, so the
+// names of the stored values don't reveal which values they are.
+fn value_hash(key: Option<&secretbox::Key>, value: &[u8]) -> ValueHash {
+    match key {
+        Some(key) => {
+            let mut keyed_value = key.0.to_vec();
+            keyed_value.extend_from_slice(value);
+            tiny_keccak::sha3_256(&keyed_value)
+        }
+        None => tiny_keccak::sha3_256(value),
+    }
+}
+
+fn value_key(hash: &ValueHash) -> Vec<u8> {
+    let mut key = vec![VALUE_KEY_PREFIX];
+    key.extend_from_slice(hash);
+    key
+}
+
+fn refcount_key(hash: &ValueHash) -> Vec<u8> {
+    let mut key = vec![REFCOUNT_KEY_PREFIX];
+    key.extend_from_slice(hash);
+    key
+}
+
+// Returns the size of the value stored under `key`, excluding the header.
+fn value_size(backend: &Backend, key: &[u8]) -> Option<u64> {
+    backend.size(key).map(|size| size.saturating_sub(HEADER_LEN as u64))
+}
+
+// Compresses `value`, returning the codec used.  If compression doesn't make the value smaller, it
+// is kept as it is.
+fn compress(compression: Compression, value: Vec<u8>) -> Result<(u8, Vec<u8>), Error> {
+    match compression {
+        Compression::None => Ok((CODEC_NONE, value)),
+        Compression::Zstd => {
+            let compressed = zstd::encode_all(&value[..], ZSTD_LEVEL)?;
+            if compressed.len() < value.len() {
+                Ok((CODEC_ZSTD, compressed))
+            } else {
+                Ok((CODEC_NONE, value))
+            }
+        }
+    }
+}
+
+fn decompress(codec: u8, value: &[u8]) -> Result<Vec<u8>, Error> {
+    match codec {
+        CODEC_NONE => Ok(value.to_vec()),
+        CODEC_ZSTD => zstd::decode_all(value).map_err(|_| Error::Corrupted),
+        _ => Err(Error::Corrupted),
+    }
+}
+
+// Encrypts `value` if there is a `key`, returning the nonce used.
+fn encrypt(key: Option<&secretbox::Key>, value: Vec<u8>) -> (Option<secretbox::Nonce>, Vec<u8>) {
+    match key {
+        Some(key) => {
+            let nonce = secretbox::gen_nonce();
+            let encrypted = secretbox::seal(&value, &nonce, key);
+            (Some(nonce), encrypted)
+        }
+        None => (None, value),
+    }
+}
+
+fn decrypt(
+    key: Option<&secretbox::Key>,
+    nonce: Option<secretbox::Nonce>,
+    value: &[u8],
+    allow_plaintext: bool,
+) -> Result<Vec<u8>, Error> {
+    match (key, nonce) {
+        (None, None) => Ok(value.to_vec()),
+        (Some(_), None) => {
+            if allow_plaintext {
+                Ok(value.to_vec())
+            } else {
+                Err(Error::Decryption)
+            }
+        }
+        (Some(key), Some(nonce)) => {
+            secretbox::open(value, &nonce, key).map_err(|()| Error::Decryption)
+        }
+        (None, Some(_)) => Err(Error::Decryption),
+    }
+}
+
+// Prepends the header to the stored `value`.
+fn encode(header: &Header, value: &[u8]) -> Vec<u8> {
+    let mut contents = Vec::with_capacity(HEADER_LEN + value.len());
+    contents.push(FORMAT_VERSION);
+    contents.push(header.codec);
+    match header.nonce {
+        Some(ref nonce) => {
+            contents.push(CIPHER_SECRETBOX);
+            contents.extend_from_slice(&nonce.0);
+        }
+        None => {
+            contents.push(CIPHER_NONE);
+            contents.extend_from_slice(&[0; secretbox::NONCEBYTES]);
+        }
+    }
+    contents.extend_from_slice(&tiny_keccak::sha3_256(value));
+    contents.extend_from_slice(value);
+    contents
+}
+
+// Strips the header from `contents`, verifying the checksum.
+fn decode(contents: &[u8]) -> Result<(Header, &[u8]), Error> {
+    if contents.len() < HEADER_LEN || contents[0] != FORMAT_VERSION {
+        return Err(Error::Corrupted);
+    }
+
+    let (header, value) = contents.split_at(HEADER_LEN);
+    let (nonce, checksum) = header[3..].split_at(secretbox::NONCEBYTES);
+    if *checksum != tiny_keccak::sha3_256(value)[..] {
+        return Err(Error::Corrupted);
     }
+
+    let nonce = match header[2] {
+        CIPHER_NONE => None,
+        CIPHER_SECRETBOX => Some(secretbox::Nonce::from_slice(nonce).ok_or(Error::Corrupted)?),
+        _ => return Err(Error::Corrupted),
+    };
+
+    Ok((
+        Header {
+            codec: header[1],
+            nonce,
+        },
+        value,
+    ))
 }
@@ -24,9 +24,19 @@ macro_rules! assert_err {
     }
 }
 
-use chunk_store::{Chunk, ChunkId, ChunkStore, Error};
+use chunk_store::{CODEC_NONE, Chunk, ChunkId, ChunkStore, DiskBackend, Error, Header,
+                  MIN_SHARED_VALUE_LEN, MemoryBackend, ThreadedBackend, ValueHash, encode,
+                  read_key_file};
+use config_handler::{Compression, SyncPolicy};
+use fake_clock::FakeClock;
+use hex::ToHex;
 use maidsafe_utilities::{SeededRng, serialisation};
 use rand::Rng;
+use rust_sodium::crypto::secretbox;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tempdir::TempDir;
 
 struct Chunks {
@@ -51,6 +61,61 @@ impl ChunkId<Id> for Id {
     }
 }
 
+// Chunk holding values which can be shared with other chunks.
+#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+struct Values(Vec<Vec<u8>>);
+
+#[derive(Serialize)]
+struct ValuesId(u64);
+
+impl Chunk<Id> for Values {
+    type Id = ValuesId;
+
+    fn split_values(
+        &self,
+        min_len: usize,
+        hash: &Fn(&[u8]) -> ValueHash,
+    ) -> Option<(Vec<u8>, Vec<(ValueHash, Vec<u8>)>)> {
+        let mut values = Vec::new();
+        let remainder: Vec<(Option<ValueHash>, Vec<u8>)> = self.0
+            .iter()
+            .map(|value| if value.len() >= min_len {
+                values.push((hash(value), value.clone()));
+                (Some(hash(value)), Vec::new())
+            } else {
+                (None, value.clone())
+            })
+            .collect();
+        if values.is_empty() {
+            None
+        } else {
+            Some((unwrap!(serialisation::serialise(&remainder)), values))
+        }
+    }
+
+    fn join_values(
+        remainder: &[u8],
+        value: &mut FnMut(&ValueHash) -> Result<Vec<u8>, Error>,
+    ) -> Result<Self, Error> {
+        let remainder: Vec<(Option<ValueHash>, Vec<u8>)> = serialisation::deserialise(remainder)?;
+        let mut values = Vec::new();
+        for (hash, inline_value) in remainder {
+            match hash {
+                Some(hash) => values.push(value(&hash)?),
+                None => values.push(inline_value),
+            }
+        }
+        Ok(Values(values))
+    }
+}
+
+impl ChunkId<Id> for ValuesId {
+    type Chunk = Values;
+    fn to_key(&self) -> Id {
+        Id(self.0)
+    }
+}
+
 // Construct random amount of randomly-sized chunks, keeping track of the total size of all
 // chunks when serialised.
 fn generate_random_chunks() -> Chunks {
@@ -70,6 +135,28 @@ fn generate_random_chunks() -> Chunks {
     chunks
 }
 
+// Returns the paths of all files in the shard directories under `dir`.
+fn chunk_files(dir: &Path) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for entry in unwrap!(fs::read_dir(dir)) {
+        let entry = unwrap!(entry);
+        if entry.file_name().to_str() == Some("tmp") || entry.file_name().to_str() == Some("lock") {
+            continue;
+        }
+        if unwrap!(entry.file_type()).is_dir() {
+            paths.extend(chunk_files(&entry.path()));
+        } else {
+            paths.push(entry.path());
+        }
+    }
+    paths
+}
+
+fn new_chunk_store(capacity: u64) -> ChunkStore<Id> {
+    let backend = unwrap!(DiskBackend::new(None, false));
+    unwrap!(ChunkStore::new(Box::new(backend), Some(capacity), Compression::None, None))
+}
+
 #[test]
 fn create_multiple_instances_in_the_same_root() {
     let test_dir = "safe_vault_test";
@@ -77,17 +164,17 @@ fn create_multiple_instances_in_the_same_root() {
     {
         let temp_dir = unwrap!(TempDir::new(test_dir));
         let root = unwrap!(temp_dir.path().to_str()).to_string();
-        let chunk_store1 = unwrap!(ChunkStore::<Id>::new(Some(root.clone()), Some(64)));
-        let chunk_store2 = unwrap!(ChunkStore::<Id>::new(Some(root), Some(64)));
-        assert_ne!(chunk_store1.root_dir.path(), chunk_store2.root_dir.path());
+        let backend1 = unwrap!(DiskBackend::new(Some(root.clone()), false));
+        let backend2 = unwrap!(DiskBackend::new(Some(root), false));
+        assert_ne!(backend1.path(), backend2.path());
     }
     // root doesn't exist yet
     {
         let temp_dir = unwrap!(TempDir::new(test_dir));
         let root = unwrap!(temp_dir.path().join("foo").join("bar").to_str()).to_string();
-        let chunk_store1 = unwrap!(ChunkStore::<Id>::new(Some(root.clone()), Some(64)));
-        let chunk_store2 = unwrap!(ChunkStore::<Id>::new(Some(root), Some(64)));
-        assert_ne!(chunk_store1.root_dir.path(), chunk_store2.root_dir.path());
+        let backend1 = unwrap!(DiskBackend::new(Some(root.clone()), false));
+        let backend2 = unwrap!(DiskBackend::new(Some(root), false));
+        assert_ne!(backend1.path(), backend2.path());
     }
 }
 
@@ -95,18 +182,180 @@ fn create_multiple_instances_in_the_same_root() {
 fn store_dir_should_cleanup() {
     let store_dir;
     {
-        let mut chunk_store = unwrap!(ChunkStore::new(None, Some(64)));
-        store_dir = chunk_store.root_dir.path().to_path_buf();
+        let backend = unwrap!(DiskBackend::new(None, false));
+        store_dir = backend.path().to_path_buf();
+        let mut chunk_store = unwrap!(ChunkStore::new(
+            Box::new(backend),
+            Some(64),
+            Compression::None,
+            None,
+        ));
         unwrap!(chunk_store.put(&Id(0), &Data(vec![0; 10])));
         assert!(store_dir.exists());
     }
     assert!(!store_dir.exists());
 }
 
+#[test]
+fn persistent_store_should_survive_reopening() {
+    let temp_dir = unwrap!(TempDir::new("safe_vault_test"));
+    let root = unwrap!(temp_dir.path().to_str()).to_string();
+    let chunks = generate_random_chunks();
+
+    let store_dir;
+    {
+        let backend = unwrap!(DiskBackend::new(Some(root.clone()), true));
+        store_dir = backend.path().to_path_buf();
+        let mut chunk_store = unwrap!(ChunkStore::new(
+            Box::new(backend),
+            Some(chunks.total_size),
+            Compression::None,
+            None,
+        ));
+        for (index, &(ref data, _)) in chunks.data_and_sizes.iter().enumerate() {
+            unwrap!(chunk_store.put(&Id(index as u64), &Data(data.clone())));
+        }
+    }
+    assert!(store_dir.exists());
+
+    // Files which aren't named after a valid key are ignored.
+    unwrap!(unwrap!(File::create(store_dir.join("not_a_chunk"))).write_all(&[0; 10]));
+
+    let backend = unwrap!(DiskBackend::new(Some(root), true));
+    assert_eq!(backend.path(), store_dir.as_path());
+    let chunk_store = unwrap!(ChunkStore::<Id>::new(
+        Box::new(backend),
+        Some(chunks.total_size),
+        Compression::None,
+        None,
+    ));
+    assert_eq!(chunk_store.used_space(), chunks.total_size);
+    assert_eq!(chunk_store.keys().len(), chunks.data_and_sizes.len());
+    for (index, &(ref data, _)) in chunks.data_and_sizes.iter().enumerate() {
+        let retrieved_value = unwrap!(chunk_store.get(&Id(index as u64)));
+        assert_eq!(*data, retrieved_value.0);
+    }
+}
+
+#[test]
+fn partially_written_chunks_should_be_swept() {
+    let temp_dir = unwrap!(TempDir::new("safe_vault_test"));
+    let root = unwrap!(temp_dir.path().to_str()).to_string();
+
+    let store_dir;
+    {
+        let backend = unwrap!(DiskBackend::new(Some(root.clone()), true));
+        store_dir = backend.path().to_path_buf();
+        let mut chunk_store = unwrap!(ChunkStore::new(
+            Box::new(backend),
+            None,
+            Compression::None,
+            None,
+        ));
+        unwrap!(chunk_store.put(&Id(0), &Data(vec![0; 10])));
+    }
+
+    // Simulate a crash while writing a new version of the chunk.
+    let key = unwrap!(serialisation::serialise(&Id(0)));
+    let temp_path = store_dir.join("tmp").join(key.to_hex());
+    unwrap!(unwrap!(File::create(&temp_path)).write_all(&[1; 5]));
+
+    let backend = unwrap!(DiskBackend::new(Some(root), true));
+    assert!(!temp_path.exists());
+    let chunk_store = unwrap!(ChunkStore::<Id>::new(
+        Box::new(backend),
+        None,
+        Compression::None,
+        None,
+    ));
+    assert_eq!(chunk_store.keys(), vec![Id(0)]);
+    assert_eq!(unwrap!(chunk_store.get(&Id(0))), Data(vec![0; 10]));
+}
+
+#[test]
+fn flat_layout_should_be_migrated() {
+    let temp_dir = unwrap!(TempDir::new("safe_vault_test"));
+    let root = unwrap!(temp_dir.path().to_str()).to_string();
+    let chunks = generate_random_chunks();
+
+    // Write the chunks the way older versions did: directly into the store directory.
+    let store_dir = temp_dir.path().join("safe_vault_chunk_store");
+    unwrap!(fs::create_dir_all(&store_dir));
+    let mut flat_paths = Vec::new();
+    for (index, &(ref data, _)) in chunks.data_and_sizes.iter().enumerate() {
+        let key = unwrap!(serialisation::serialise(&Id(index as u64)));
+        let serialised_value = unwrap!(serialisation::serialise(&Data(data.clone())));
+        let header = Header {
+            codec: CODEC_NONE,
+            nonce: None,
+        };
+        let value = encode(&header, &serialised_value);
+        let path = store_dir.join(key.to_hex());
+        unwrap!(unwrap!(File::create(&path)).write_all(&value));
+        flat_paths.push(path);
+    }
+
+    let backend = unwrap!(DiskBackend::new(Some(root), true));
+    assert!(flat_paths.iter().all(|path| !path.exists()));
+    let chunk_store = unwrap!(ChunkStore::<Id>::new(
+        Box::new(backend),
+        Some(chunks.total_size),
+        Compression::None,
+        None,
+    ));
+    assert_eq!(chunk_store.used_space(), chunks.total_size);
+    assert_eq!(chunk_store.keys().len(), chunks.data_and_sizes.len());
+    for (index, &(ref data, _)) in chunks.data_and_sizes.iter().enumerate() {
+        let retrieved_value = unwrap!(chunk_store.get(&Id(index as u64)));
+        assert_eq!(*data, retrieved_value.0);
+    }
+}
+
+#[test]
+fn corrupted_chunk_should_be_detected() {
+    let temp_dir = unwrap!(TempDir::new("safe_vault_test"));
+    let root = unwrap!(temp_dir.path().to_str()).to_string();
+    let backend = unwrap!(DiskBackend::new(Some(root), false));
+    let store_dir = backend.path().to_path_buf();
+    let mut chunk_store = unwrap!(ChunkStore::new(
+        Box::new(backend),
+        None,
+        Compression::None,
+        None,
+    ));
+    unwrap!(chunk_store.put(&Id(0), &Data(vec![0; 10])));
+
+    let paths = chunk_files(&store_dir);
+    assert_eq!(paths.len(), 1);
+    let mut file = unwrap!(fs::OpenOptions::new().append(true).open(&paths[0]));
+    unwrap!(file.write_all(&[1]));
+
+    assert_err!(chunk_store.get(&Id(0)), Error::Corrupted);
+}
+
+#[test]
+fn persistent_store_should_be_locked() {
+    let temp_dir = unwrap!(TempDir::new("safe_vault_test"));
+    let root = unwrap!(temp_dir.path().to_str()).to_string();
+
+    {
+        let backend = unwrap!(DiskBackend::new(Some(root.clone()), true));
+        assert_err!(DiskBackend::new(Some(root.clone()), true).map(|_| ()), Error::Locked);
+        assert_err!(DiskBackend::open(backend.path().to_path_buf()).map(|_| ()), Error::Locked);
+
+        // Backends sharing the lock can use the directory.
+        let shared = backend.reopen();
+        assert_eq!(shared.path(), backend.path());
+    }
+
+    // The lock is released when the backend is dropped.
+    let _ = unwrap!(DiskBackend::new(Some(root), true));
+}
+
 #[test]
 fn successful_put() {
     let chunks = generate_random_chunks();
-    let mut chunk_store = unwrap!(ChunkStore::new(None, Some(chunks.total_size)));
+    let mut chunk_store = new_chunk_store(chunks.total_size);
     {
         let mut put = |id, data, size| {
             let size_before_insert = chunk_store.used_space();
@@ -136,7 +385,7 @@ fn successful_put() {
 #[test]
 fn failed_put_when_not_enough_space() {
     let disk_size = 32;
-    let mut chunk_store = unwrap!(ChunkStore::new(None, Some(disk_size)));
+    let mut chunk_store = new_chunk_store(disk_size);
     let mut rng = SeededRng::thread_rng();
     let id = Id(rng.gen());
     let data = Data(rng.gen_iter().take((disk_size + 1) as usize).collect());
@@ -146,7 +395,7 @@ fn failed_put_when_not_enough_space() {
 #[test]
 fn delete() {
     let chunks = generate_random_chunks();
-    let mut chunk_store = unwrap!(ChunkStore::new(None, Some(chunks.total_size)));
+    let mut chunk_store = new_chunk_store(chunks.total_size);
     let mut put_and_delete = |id, value, size| {
         unwrap!(chunk_store.put(&id, &value));
         assert_eq!(chunk_store.used_space(), size);
@@ -164,7 +413,7 @@ fn delete() {
 #[test]
 fn put_and_get_value_should_be_same() {
     let chunks = generate_random_chunks();
-    let mut chunk_store = unwrap!(ChunkStore::new(None, Some(chunks.total_size)));
+    let mut chunk_store = new_chunk_store(chunks.total_size);
     for (index, &(ref data, _)) in chunks.data_and_sizes.iter().enumerate() {
         unwrap!(chunk_store.put(&Id(index as u64), &Data(data.clone())));
     }
@@ -177,7 +426,7 @@ fn put_and_get_value_should_be_same() {
 #[test]
 fn overwrite_value() {
     let chunks = generate_random_chunks();
-    let mut chunk_store = unwrap!(ChunkStore::new(None, Some(chunks.total_size)));
+    let mut chunk_store = new_chunk_store(chunks.total_size);
     for (ref data, ref size) in chunks.data_and_sizes {
         unwrap!(chunk_store.put(&Id(0), &Data(data.clone())));
         assert_eq!(chunk_store.used_space(), *size);
@@ -188,7 +437,7 @@ fn overwrite_value() {
 
 #[test]
 fn get_fails_when_key_does_not_exist() {
-    let chunk_store = unwrap!(ChunkStore::new(None, Some(64)));
+    let chunk_store = new_chunk_store(64);
     let id = Id(SeededRng::thread_rng().gen());
     assert_err!(chunk_store.get(&id), Error::NotFound);
 }
@@ -196,7 +445,7 @@ fn get_fails_when_key_does_not_exist() {
 #[test]
 fn keys() {
     let chunks = generate_random_chunks();
-    let mut chunk_store = unwrap!(ChunkStore::new(None, Some(chunks.total_size)));
+    let mut chunk_store = new_chunk_store(chunks.total_size);
 
     for (index, &(ref data, _)) in chunks.data_and_sizes.iter().enumerate() {
         let id = Id(index as u64);
@@ -218,3 +467,393 @@ fn keys() {
         );
     }
 }
+
+#[test]
+fn compression() {
+    let temp_dir = unwrap!(TempDir::new("safe_vault_test"));
+    let root = unwrap!(temp_dir.path().to_str()).to_string();
+    let compressible = Data(vec![7; 1000]);
+    let incompressible = Data(SeededRng::thread_rng().gen_iter().take(1000).collect());
+    let serialised_size = unwrap!(serialisation::serialise(&compressible)).len() as u64;
+
+    {
+        let backend = unwrap!(DiskBackend::new(Some(root.clone()), true));
+        let mut chunk_store = unwrap!(ChunkStore::new(
+            Box::new(backend),
+            None,
+            Compression::Zstd,
+            None,
+        ));
+
+        // Used space accounts for the compressed size.
+        unwrap!(chunk_store.put(&Id(0), &compressible));
+        assert!(chunk_store.used_space() < serialised_size);
+        assert_eq!(unwrap!(chunk_store.get(&Id(0))), compressible);
+
+        // Values which don't compress well are stored as they are.
+        let used_space = chunk_store.used_space();
+        unwrap!(chunk_store.put(&Id(1), &incompressible));
+        assert_eq!(chunk_store.used_space(), used_space + serialised_size);
+        assert_eq!(unwrap!(chunk_store.get(&Id(1))), incompressible);
+    }
+
+    // Compressed values remain readable after compression is disabled.
+    let backend = unwrap!(DiskBackend::new(Some(root), true));
+    let mut chunk_store = unwrap!(ChunkStore::new(
+        Box::new(backend),
+        None,
+        Compression::None,
+        None,
+    ));
+    let used_space = chunk_store.used_space();
+    assert_eq!(unwrap!(chunk_store.get(&Id(0))), compressible);
+    unwrap!(chunk_store.put(&Id(2), &compressible));
+    assert_eq!(chunk_store.used_space(), used_space + serialised_size);
+    assert_eq!(unwrap!(chunk_store.get(&Id(2))), compressible);
+}
+
+#[test]
+fn encryption() {
+    let temp_dir = unwrap!(TempDir::new("safe_vault_test"));
+    let root = unwrap!(temp_dir.path().to_str()).to_string();
+    let key_path = temp_dir.path().join("key");
+    let data = Data(b"plaintext which must not be found on disk".to_vec());
+
+    // Key files must hold exactly the raw key bytes.
+    unwrap!(unwrap!(File::create(&key_path)).write_all(&[1; 10]));
+    assert_err!(read_key_file(&key_path), Error::InvalidKey);
+    let key = secretbox::gen_key();
+    unwrap!(unwrap!(File::create(&key_path)).write_all(&key.0));
+    let key = unwrap!(read_key_file(&key_path));
+
+    let store_dir;
+    {
+        let backend = unwrap!(DiskBackend::new(Some(root.clone()), true));
+        store_dir = backend.path().to_path_buf();
+        let mut chunk_store = unwrap!(ChunkStore::new(
+            Box::new(backend),
+            None,
+            Compression::None,
+            Some(key.clone()),
+        ));
+        unwrap!(chunk_store.put(&Id(0), &data));
+        assert_eq!(unwrap!(chunk_store.get(&Id(0))), data);
+    }
+
+    let paths = chunk_files(&store_dir);
+    assert_eq!(paths.len(), 1);
+    let mut contents = Vec::new();
+    let _ = unwrap!(unwrap!(File::open(&paths[0])).read_to_end(&mut contents));
+    assert!(!contents.windows(data.0.len()).any(|window| window == &data.0[..]));
+
+    // Reading fails without the right key.
+    let open_store = |key| {
+        let backend = unwrap!(DiskBackend::new(Some(root.clone()), true));
+        unwrap!(ChunkStore::<Id>::new(Box::new(backend), None, Compression::None, key))
+    };
+    assert_err!(open_store(None).get(&Id(0)), Error::Decryption);
+    assert_err!(open_store(Some(secretbox::gen_key())).get(&Id(0)), Error::Decryption);
+    assert_eq!(unwrap!(open_store(Some(key.clone())).get(&Id(0))), data);
+
+    // Unencrypted values can't be read with a key set, unless migrating to encryption.
+    unwrap!(open_store(None).put(&Id(1), &data));
+    let mut chunk_store = open_store(Some(key));
+    assert_err!(chunk_store.get(&Id(1)), Error::Decryption);
+    chunk_store.set_allow_plaintext(true);
+    assert_eq!(unwrap!(chunk_store.get(&Id(1))), data);
+    assert_eq!(unwrap!(chunk_store.get(&Id(0))), data);
+}
+
+#[test]
+fn usage_should_be_reconciled() {
+    let temp_dir = unwrap!(TempDir::new("safe_vault_test"));
+    let root = unwrap!(temp_dir.path().to_str()).to_string();
+    let chunks = generate_random_chunks();
+
+    let backend = unwrap!(DiskBackend::new(Some(root.clone()), true));
+    let mut chunk_store = unwrap!(ChunkStore::<Id>::new(
+        Box::new(backend),
+        None,
+        Compression::None,
+        None,
+    ));
+    assert!(chunk_store.available_space().is_some());
+
+    // Store the chunks via another instance, which the first one doesn't know about.
+    let backend = unwrap!(DiskBackend::new(Some(root), true));
+    let mut other_chunk_store = unwrap!(ChunkStore::new(
+        Box::new(backend),
+        None,
+        Compression::None,
+        None,
+    ));
+    for (index, &(ref data, _)) in chunks.data_and_sizes.iter().enumerate() {
+        unwrap!(other_chunk_store.put(&Id(index as u64), &Data(data.clone())));
+    }
+    assert_eq!(other_chunk_store.used_space(), chunks.total_size);
+    assert_eq!(chunk_store.used_space(), 0);
+    assert_eq!(chunk_store.disk_usage(), 0);
+
+    chunk_store.reconcile();
+    assert_eq!(chunk_store.used_space(), chunks.total_size);
+    assert_eq!(chunk_store.disk_usage(), other_chunk_store.disk_usage());
+}
+
+#[test]
+fn snapshot_should_keep_point_in_time_content() {
+    let temp_dir = unwrap!(TempDir::new("safe_vault_test"));
+    let snapshot_dir = temp_dir.path().join("snapshot");
+    let mut chunk_store = new_chunk_store(1000);
+    unwrap!(chunk_store.put(&Id(0), &Data(vec![0; 10])));
+    unwrap!(chunk_store.put(&Id(1), &Data(vec![1; 10])));
+
+    let job = unwrap!(chunk_store.prepare_snapshot());
+    assert!(job.writing().is_empty());
+    let mut keys = unwrap!(job.save(&snapshot_dir));
+    keys.sort();
+    assert_eq!(keys, vec![Id(0), Id(1)]);
+
+    // Changes after the snapshot don't affect it.
+    unwrap!(chunk_store.put(&Id(0), &Data(vec![2; 10])));
+    unwrap!(chunk_store.delete(&Id(1)));
+    unwrap!(chunk_store.put(&Id(2), &Data(vec![2; 10])));
+
+    let backend = unwrap!(DiskBackend::open(snapshot_dir));
+    let snapshot = unwrap!(ChunkStore::<Id>::new(Box::new(backend), None, Compression::None, None));
+    assert_eq!(unwrap!(snapshot.get(&Id(0))), Data(vec![0; 10]));
+    assert_eq!(unwrap!(snapshot.get(&Id(1))), Data(vec![1; 10]));
+    assert!(!snapshot.has(&Id(2)));
+
+    let mut chunk_store = unwrap!(ChunkStore::<Id>::new(
+        Box::new(MemoryBackend::new()),
+        None,
+        Compression::None,
+        None,
+    ));
+    assert_err!(chunk_store.prepare_snapshot().map(|_| ()), Error::Io(_));
+}
+
+#[test]
+fn shared_values() {
+    let big_value = vec![1; MIN_SHARED_VALUE_LEN];
+    let other_big_value = vec![2; MIN_SHARED_VALUE_LEN];
+    let small_value = vec![3; 10];
+    let mut chunk_store = unwrap!(ChunkStore::new(
+        Box::new(MemoryBackend::new()),
+        None,
+        Compression::None,
+        None,
+    ));
+    chunk_store.set_value_store(Box::new(MemoryBackend::new()), true);
+
+    let chunk0 = Values(vec![big_value.clone(), small_value.clone()]);
+    let chunk1 = Values(vec![big_value, other_big_value]);
+    unwrap!(chunk_store.put(&ValuesId(0), &chunk0));
+    let used_space = chunk_store.used_space();
+    assert!(used_space > MIN_SHARED_VALUE_LEN as u64);
+
+    // Only the value not stored yet takes additional space.
+    unwrap!(chunk_store.put(&ValuesId(1), &chunk1));
+    assert!(chunk_store.used_space() - used_space < 2 * MIN_SHARED_VALUE_LEN as u64);
+    assert_eq!(unwrap!(chunk_store.get(&ValuesId(0))), chunk0);
+    assert_eq!(unwrap!(chunk_store.get(&ValuesId(1))), chunk1);
+
+    // The shared value is kept while any chunk still refers to it.
+    unwrap!(chunk_store.delete(&ValuesId(0)));
+    assert_eq!(unwrap!(chunk_store.get(&ValuesId(1))), chunk1);
+
+    let used_space = chunk_store.used_space();
+    chunk_store.reconcile();
+    assert_eq!(chunk_store.used_space(), used_space);
+
+    // Values no longer referred to are deleted.
+    unwrap!(chunk_store.put(&ValuesId(1), &Values(vec![small_value])));
+    unwrap!(chunk_store.delete(&ValuesId(1)));
+    assert_eq!(chunk_store.used_space(), 0);
+    chunk_store.reconcile();
+    assert_eq!(chunk_store.used_space(), 0);
+
+    // Chunks stored with shared values remain readable once deduplication is disabled.
+    let temp_dir = unwrap!(TempDir::new("safe_vault_test"));
+    let root = unwrap!(temp_dir.path().to_str()).to_string();
+    {
+        let backend = unwrap!(DiskBackend::new(Some(root.clone()), true));
+        let values = unwrap!(backend.value_store());
+        let mut chunk_store = unwrap!(ChunkStore::new(
+            Box::new(backend),
+            None,
+            Compression::None,
+            None,
+        ));
+        chunk_store.set_value_store(Box::new(values), true);
+        unwrap!(chunk_store.put(&ValuesId(0), &chunk0));
+    }
+
+    let backend = unwrap!(DiskBackend::new(Some(root), true));
+    let values = unwrap!(backend.value_store());
+    let mut chunk_store = unwrap!(ChunkStore::new(
+        Box::new(backend),
+        None,
+        Compression::None,
+        None,
+    ));
+    chunk_store.set_value_store(Box::new(values), false);
+    assert_eq!(chunk_store.keys(), vec![Id(0)]);
+    assert_eq!(unwrap!(chunk_store.get(&ValuesId(0))), chunk0);
+
+    let used_space = chunk_store.used_space();
+    unwrap!(chunk_store.put(&ValuesId(1), &chunk1));
+    assert!(chunk_store.used_space() - used_space > 2 * MIN_SHARED_VALUE_LEN as u64);
+    unwrap!(chunk_store.delete(&ValuesId(0)));
+    assert_eq!(unwrap!(chunk_store.get(&ValuesId(1))), chunk1);
+}
+
+#[test]
+fn threaded_backend() {
+    let temp_dir = unwrap!(TempDir::new("safe_vault_test"));
+    let root = unwrap!(temp_dir.path().to_str()).to_string();
+    let used_space;
+
+    {
+        let backend = unwrap!(DiskBackend::new(Some(root.clone()), true));
+        let backend = unwrap!(ThreadedBackend::new(backend, 4));
+        let mut chunk_store = unwrap!(ChunkStore::new(
+            Box::new(backend),
+            None,
+            Compression::None,
+            None,
+        ));
+
+        // The chunks are readable before the workers have written them.
+        for index in 0..20 {
+            unwrap!(chunk_store.put(&Id(index), &Data(vec![index as u8; 100])));
+            assert_eq!(unwrap!(chunk_store.get(&Id(index))), Data(vec![index as u8; 100]));
+        }
+        unwrap!(chunk_store.put(&Id(0), &Data(vec![20; 100])));
+        unwrap!(chunk_store.delete(&Id(1)));
+        assert_eq!(unwrap!(chunk_store.get(&Id(0))), Data(vec![20; 100]));
+        assert!(!chunk_store.has(&Id(1)));
+        assert_eq!(chunk_store.keys().len(), 19);
+
+        // The snapshot doesn't wait for the writes, but lists those it may have missed.
+        let snapshot_dir = temp_dir.path().join("snapshot");
+        let job = unwrap!(chunk_store.prepare_snapshot());
+        let writing = job.writing().to_vec();
+        let saved = unwrap!(job.save(&snapshot_dir));
+        for key in chunk_store.keys() {
+            assert!(saved.contains(&key) || writing.contains(&key));
+        }
+        assert!(!saved.contains(&Id(1)) || writing.contains(&Id(1)));
+
+        while chunk_store.is_writing(&Id(0)) {
+            chunk_store.wait_for_writes(Duration::from_millis(10));
+            assert!(chunk_store.poll_failed_writes().is_empty());
+        }
+        assert!(chunk_store.poll_failed_writes().is_empty());
+        used_space = chunk_store.used_space();
+    }
+
+    // Dropping the store waits for the workers, so everything is on disk now.
+    let backend = unwrap!(DiskBackend::new(Some(root), true));
+    let chunk_store = unwrap!(ChunkStore::<Id>::new(
+        Box::new(backend),
+        None,
+        Compression::None,
+        None,
+    ));
+    assert_eq!(chunk_store.used_space(), used_space);
+    assert_eq!(unwrap!(chunk_store.get(&Id(0))), Data(vec![20; 100]));
+    assert!(!chunk_store.has(&Id(1)));
+    for index in 2..20 {
+        assert_eq!(unwrap!(chunk_store.get(&Id(index))), Data(vec![index as u8; 100]));
+    }
+}
+
+#[test]
+fn sync_policies() {
+    let policies = [
+        SyncPolicy::Always,
+        SyncPolicy::Batched {
+            interval_ms: 1000,
+            writes: 3,
+        },
+        SyncPolicy::Never,
+    ];
+
+    for policy in &policies {
+        let temp_dir = unwrap!(TempDir::new("safe_vault_test"));
+        let root = unwrap!(temp_dir.path().to_str()).to_string();
+
+        {
+            let mut backend = unwrap!(DiskBackend::new(Some(root.clone()), true));
+            backend.set_sync_policy(*policy);
+            let mut chunk_store = unwrap!(ChunkStore::new(
+                Box::new(backend),
+                None,
+                Compression::None,
+                None,
+            ));
+            for index in 0..10 {
+                unwrap!(chunk_store.put(&Id(index), &Data(vec![index as u8; 100])));
+            }
+            unwrap!(chunk_store.delete(&Id(9)));
+
+            // The last write isn't synced by reaching the write count, but once the interval
+            // expires.
+            if let SyncPolicy::Batched { interval_ms, .. } = *policy {
+                let time_until_sync = unwrap!(chunk_store.time_until_sync());
+                assert!(time_until_sync <= Duration::from_millis(interval_ms));
+                unwrap!(chunk_store.sync_if_due());
+                assert!(chunk_store.time_until_sync().is_some());
+                FakeClock::advance_time(interval_ms);
+                assert_eq!(chunk_store.time_until_sync(), Some(Duration::from_secs(0)));
+                unwrap!(chunk_store.sync_if_due());
+            }
+            assert_eq!(chunk_store.time_until_sync(), None);
+            unwrap!(chunk_store.sync());
+        }
+
+        let backend = unwrap!(DiskBackend::new(Some(root), true));
+        let chunk_store = unwrap!(ChunkStore::<Id>::new(
+            Box::new(backend),
+            None,
+            Compression::None,
+            None,
+        ));
+        assert_eq!(chunk_store.keys().len(), 9);
+        for index in 0..9 {
+            assert_eq!(unwrap!(chunk_store.get(&Id(index))), Data(vec![index as u8; 100]));
+        }
+    }
+}
+
+#[test]
+fn memory_backend() {
+    let chunks = generate_random_chunks();
+    let mut chunk_store = unwrap!(ChunkStore::new(
+        Box::new(MemoryBackend::new()),
+        Some(chunks.total_size),
+        Compression::None,
+        None,
+    ));
+
+    for (index, &(ref data, ref size)) in chunks.data_and_sizes.iter().enumerate() {
+        let id = Id(index as u64);
+        let size_before_insert = chunk_store.used_space();
+        assert!(!chunk_store.has(&id));
+        unwrap!(chunk_store.put(&id, &Data(data.clone())));
+        assert!(chunk_store.has(&id));
+        assert_eq!(chunk_store.used_space(), size + size_before_insert);
+        assert_eq!(*data, unwrap!(chunk_store.get(&id)).0);
+    }
+    assert_eq!(chunk_store.keys().len(), chunks.data_and_sizes.len());
+
+    for (index, _) in chunks.data_and_sizes.iter().enumerate() {
+        let id = Id(index as u64);
+        unwrap!(chunk_store.delete(&id));
+        assert!(!chunk_store.has(&id));
+        assert_err!(chunk_store.get(&id), Error::NotFound);
+    }
+    assert_eq!(chunk_store.used_space(), 0);
+    assert!(chunk_store.keys().is_empty());
+}
@@ -0,0 +1,271 @@
+// Copyright 2017 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use super::{Backend, DiskBackend, Error};
+use maidsafe_utilities::thread::{self, Joiner};
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+use tiny_keccak;
+
+enum Job {
+    Put(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+    Sync,
+}
+
+type Completion = (Vec<u8>, Result<(), Error>);
+
+struct Worker {
+    jobs: Sender<Job>,
+    // Joins the thread when dropped, after `jobs` is closed.
+    _joiner: Joiner,
+}
+
+// A value queued for writing (`Some`) or deletion (`None`), and the number of queued jobs for its
+// key.
+struct InFlight {
+    value: Option<Vec<u8>>,
+    count: usize,
+}
+
+/// Backend writing and deleting chunk files on a pool of worker threads, so the caller doesn't
+/// wait for the disk.
+///
+/// Until the workers are done with a key, reads of it are served from memory.  All jobs for the
+/// same key are handled by the same worker, so they are applied in order.  The outcome of each job
+/// is returned by `poll_completed`, and `wait_completed` lets the caller sleep until one is ready.
+///
+/// Other reads are still done on the calling thread: the personas answer requests straight from
+/// the data read, so moving reads to the pool would mean suspending every request handler which
+/// reads a chunk until the read completes.  Reading a chunk file is a single small read, usually
+/// served by the page cache, unlike writes, which are synced to disk.
+pub struct ThreadedBackend {
+    // Must be dropped before `backend`, so all queued jobs are done when its directory is removed.
+    workers: Vec<Worker>,
+    // Used for reads, and by the workers' own instances for writes.
+    backend: DiskBackend,
+    completions: Receiver<Completion>,
+    in_flight: HashMap<Vec<u8>, InFlight>,
+    // Completions already received by `wait_completed`, but not returned by `poll_completed` yet.
+    completed: Vec<Completion>,
+}
+
+impl ThreadedBackend {
+    /// Creates a backend with `thread_count` workers (at least one), each writing to the
+    /// directory of `backend`.
+    pub fn new(backend: DiskBackend, thread_count: usize) -> Result<Self, Error> {
+        let thread_count = cmp::max(thread_count, 1);
+        let (completion_tx, completion_rx) = mpsc::channel();
+        let mut workers = Vec::with_capacity(thread_count);
+        for index in 0..thread_count {
+            let worker_backend = backend.reopen();
+            let (jobs_tx, jobs_rx) = mpsc::channel();
+            let completion_tx = completion_tx.clone();
+            let joiner = thread::named(format!("ChunkStoreWorker{}", index), move || {
+                run_worker(worker_backend, jobs_rx, completion_tx)
+            });
+            workers.push(Worker {
+                jobs: jobs_tx,
+                _joiner: joiner,
+            });
+        }
+
+        Ok(ThreadedBackend {
+            workers,
+            backend,
+            completions: completion_rx,
+            in_flight: HashMap::new(),
+            completed: Vec::new(),
+        })
+    }
+
+    fn submit(&mut self, key: &[u8], value: Option<&[u8]>) -> Result<(), Error> {
+        let job = match value {
+            Some(value) => Job::Put(key.to_vec(), value.to_vec()),
+            None => Job::Delete(key.to_vec()),
+        };
+        let index = tiny_keccak::sha3_256(key)[0] as usize % self.workers.len();
+        self.workers[index].jobs.send(job).map_err(|_| {
+            Error::Io(io::Error::new(io::ErrorKind::Other, "chunk store worker stopped"))
+        })?;
+
+        let in_flight = self.in_flight.entry(key.to_vec()).or_insert(InFlight {
+            value: None,
+            count: 0,
+        });
+        in_flight.value = value.map(|value| value.to_vec());
+        in_flight.count += 1;
+        Ok(())
+    }
+
+    fn complete(&mut self, completion: &Completion) {
+        let done = match self.in_flight.get_mut(&completion.0) {
+            Some(in_flight) => {
+                in_flight.count -= 1;
+                in_flight.count == 0
+            }
+            None => false,
+        };
+        if done {
+            let _ = self.in_flight.remove(&completion.0);
+        }
+    }
+}
+
+impl Backend for ThreadedBackend {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        self.submit(key, Some(value))
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Vec<u8>, Error> {
+        match self.in_flight.get(key) {
+            Some(&InFlight { value: Some(ref value), .. }) => Ok(value.clone()),
+            Some(&InFlight { value: None, .. }) => Err(Error::NotFound),
+            None => self.backend.get(key),
+        }
+    }
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), Error> {
+        self.submit(key, None)
+    }
+
+    fn has(&self, key: &[u8]) -> bool {
+        match self.in_flight.get(key) {
+            Some(in_flight) => in_flight.value.is_some(),
+            None => self.backend.has(key),
+        }
+    }
+
+    fn keys(&self) -> Vec<Vec<u8>> {
+        let mut keys: HashSet<_> = self.backend
+            .keys()
+            .into_iter()
+            .filter(|key| !self.in_flight.contains_key(key))
+            .collect();
+        keys.extend(self.in_flight.iter().filter_map(
+            |(key, in_flight)| if in_flight.value.is_some() {
+                Some(key.clone())
+            } else {
+                None
+            },
+        ));
+        keys.into_iter().collect()
+    }
+
+    fn size(&self, key: &[u8]) -> Option<u64> {
+        match self.in_flight.get(key) {
+            Some(in_flight) => in_flight.value.as_ref().map(|value| value.len() as u64),
+            None => self.backend.size(key),
+        }
+    }
+
+    // The allocated size of values still in flight isn't known yet, so their size is used instead.
+    fn allocated_size(&self, key: &[u8]) -> Option<u64> {
+        match self.in_flight.get(key) {
+            Some(in_flight) => in_flight.value.as_ref().map(|value| value.len() as u64),
+            None => self.backend.allocated_size(key),
+        }
+    }
+
+    fn available_space(&self) -> Option<u64> {
+        self.backend.available_space()
+    }
+
+    // The files of keys still in flight may not be written yet, or still be written later.
+    fn dir(&self) -> Option<&Path> {
+        Some(self.backend.path())
+    }
+
+    fn writing_keys(&self) -> Vec<Vec<u8>> {
+        self.in_flight.keys().cloned().collect()
+    }
+
+    fn poll_completed(&mut self) -> Vec<(Vec<u8>, Result<(), Error>)> {
+        let mut completed: Vec<_> = self.completed.drain(..).collect();
+        while let Ok(completion) = self.completions.try_recv() {
+            self.complete(&completion);
+            completed.push(completion);
+        }
+        completed
+    }
+
+    fn is_writing(&self, key: &[u8]) -> bool {
+        self.in_flight.contains_key(key)
+    }
+
+    // Each worker syncs its own writes.
+    fn sync(&mut self) -> Result<(), Error> {
+        for worker in &self.workers {
+            let _ = worker.jobs.send(Job::Sync);
+        }
+        Ok(())
+    }
+
+    fn wait_completed(&mut self, timeout: Duration) {
+        if self.in_flight.is_empty() || !self.completed.is_empty() {
+            return;
+        }
+        if let Ok(completion) = self.completions.recv_timeout(timeout) {
+            self.complete(&completion);
+            self.completed.push(completion);
+        }
+    }
+}
+
+fn run_worker(mut backend: DiskBackend, jobs: Receiver<Job>, completions: Sender<Completion>) {
+    loop {
+        // Batched writes are synced once their interval expires, even if no further jobs arrive.
+        let job = match backend.time_until_sync() {
+            Some(timeout) => {
+                match jobs.recv_timeout(timeout) {
+                    Ok(job) => job,
+                    Err(RecvTimeoutError::Timeout) => Job::Sync,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            None => {
+                match jobs.recv() {
+                    Ok(job) => job,
+                    Err(_) => break,
+                }
+            }
+        };
+        let completion = match job {
+            Job::Sync => {
+                if let Err(error) = backend.sync() {
+                    warn!("Failed to sync chunk store: {}", error);
+                }
+                continue;
+            }
+            Job::Put(key, value) => {
+                let result = backend.put(&key, &value);
+                (key, result)
+            }
+            Job::Delete(key) => {
+                let result = backend.delete(&key);
+                (key, result)
+            }
+        };
+        if completions.send(completion).is_err() {
+            break;
+        }
+    }
+}
@@ -19,6 +19,7 @@ use config_file_handler::{self, FileHandler};
 use error::InternalError;
 use routing::XorName;
 use rust_sodium::crypto::sign;
+use std::collections::BTreeMap;
 use std::ffi::OsString;
 
 /// Lets a vault configure a wallet address and storage limit.
@@ -28,14 +29,123 @@ pub struct Config {
     pub wallet_address: Option<XorName>,
     /// Upper limit for allowed network storage on this vault.
     pub max_capacity: Option<u64>, // measured by Bytes
+    /// Percentage of `max_capacity` above which new data put by clients is refused with
+    /// `NetworkFull`.  Defaults to 50.
+    pub full_percent: Option<u64>,
+    /// Space in bytes at the end of `max_capacity` which is only used for chunks fetched from other
+    /// vaults during churn, never for new data put by clients.  Defaults to 0.
+    pub replication_reserve: Option<u64>,
+    /// Free space in bytes to keep on the filesystem holding the chunk store.  Below it, new data
+    /// put by clients is refused with `NetworkFull`, and chunks fetched from other vaults aren't
+    /// stored.  Defaults to 100 MiB.
+    pub min_free_space: Option<u64>,
+    /// Time in seconds after which chunks this vault is no longer responsible for are deleted if
+    /// nobody fetched them, even if there's space left.  If not set, they are only deleted when
+    /// space is needed.
+    pub unneeded_chunk_max_age_secs: Option<u64>,
     /// Root directory for chunk_store directories.
     pub chunk_store_root: Option<String>,
-    /// Key that is allowed to put mutable data for account creation invitations.
+    /// Whether to keep the chunk store on disk and reopen it when the vault restarts.
+    pub chunk_store_persistent: Option<bool>,
+    /// Storage backend for the chunk store.  Defaults to `ChunkStoreType::Disk`.
+    pub chunk_store_type: Option<ChunkStoreType>,
+    /// Compression applied to chunks before they are stored.  Defaults to `Compression::None`.
+    pub chunk_store_compression: Option<Compression>,
+    /// Path of the file holding the raw `secretbox` key used to encrypt stored chunks.  If not
+    /// set, chunks are stored unencrypted.  The operator must generate the key file, e.g. with
+    /// `head -c 32 /dev/urandom > chunkstore.key`, and keep it private to the vault.
+    pub chunk_store_key_file: Option<String>,
+    /// Whether chunks stored unencrypted remain readable when `chunk_store_key_file` is set, to
+    /// migrate an existing chunk store to encryption.  If not set, they are rejected.
+    pub chunk_store_allow_plaintext: Option<bool>,
+    /// Number of threads writing chunks to a `ChunkStoreType::Disk` chunk store in the
+    /// background.  If not set or zero, chunks are written on the thread handling routing events,
+    /// which then waits for the disk.
+    pub chunk_store_io_threads: Option<usize>,
+    /// When chunks written to a `ChunkStoreType::Disk` chunk store are synced to disk.  Defaults
+    /// to `SyncPolicy::Always`.
+    pub chunk_store_sync: Option<SyncPolicy>,
+    /// If true, large `MutableData` entry values are stored only once in the chunk store, and
+    /// shared by all chunks holding them.  Defaults to false.
+    pub chunk_store_dedup: Option<bool>,
+    /// Directory the vault persists the client accounts it manages into, so they survive restarts.
+    /// If not set, the accounts are only kept in memory.
+    pub account_db_dir: Option<String>,
+    /// Directory the vault saves snapshots of its chunk store and account state into, each in a
+    /// subdirectory named after its creation time.  The three most recent snapshots are kept.  If
+    /// not set, no snapshots are taken.
+    pub snapshot_dir: Option<String>,
+    /// Time in seconds between snapshots.  Defaults to one day.
+    pub snapshot_interval_secs: Option<u64>,
+    /// Key of the root admin, which has all the admin roles, e.g. may put mutable data for account
+    /// creation invitations, and can grant them to other accounts.
     pub invite_key: Option<[u8; sign::PUBLICKEYBYTES]>,
+    /// Number of mutations each client account may perform, unless its tier allows otherwise.
+    /// Defaults to `DEFAULT_MAX_OPS_COUNT`.
+    pub max_ops_count: Option<u64>,
+    /// Number of bytes of data each client account may store, unless its tier allows otherwise.
+    /// Defaults to `DEFAULT_MAX_BYTES`.
+    pub max_bytes: Option<u64>,
+    /// Named account tiers, which the admins with the `AccountTierManager` role can assign to
+    /// accounts to override their limits.  Should be the same on all vaults.
+    pub account_tiers: Option<BTreeMap<String, AccountTier>>,
     /// Developer options.
     pub dev: Option<DevConfig>,
 }
 
+/// Storage backend for the chunk store.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ChunkStoreType {
+    /// Chunks are held as files under `chunk_store_root`.
+    Disk,
+    /// Chunks are held in memory and lost when the vault stops.  Intended for tests.
+    Memory,
+}
+
+/// Compression applied to stored chunks.
+///
+/// Chunks stored with a different compression remain readable, so this can be changed for an
+/// existing chunk store.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Compression {
+    /// Chunks are stored as they are.
+    None,
+    /// Chunks are compressed using zstd.
+    Zstd,
+}
+
+/// When chunk files are synced to disk.
+///
+/// Syncing makes a written chunk survive a crash or power loss, but takes a disk round trip per
+/// chunk.  Chunks which are lost or truncated because they weren't synced are detected by their
+/// checksums and fetched again from the group.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum SyncPolicy {
+    /// Every chunk is synced as it is written.
+    Always,
+    /// Chunks are synced together, once `writes` chunks have been written or `interval_ms`
+    /// milliseconds have passed since the previous sync, whichever comes first.
+    Batched {
+        /// The maximum time between syncs, in milliseconds.
+        interval_ms: u64,
+        /// The maximum number of chunks written between syncs.
+        writes: usize,
+    },
+    /// Chunks are never explicitly synced, leaving it to the operating system.  Intended for tests
+    /// and development.
+    Never,
+}
+
+/// Limits of the client accounts assigned a tier.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AccountTier {
+    /// Number of mutations each account of the tier may perform.
+    pub max_ops_count: u64,
+    /// Number of bytes of data each account of the tier may store.  Defaults to the `max_bytes`
+    /// of the config.
+    pub max_bytes: Option<u64>,
+}
+
 /// Extra configuration options intended for developers
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct DevConfig {
@@ -113,16 +223,106 @@ mod test {
             "{} is missing `max_capacity` field.",
             path.display()
         );
+        assert!(
+            config.full_percent.is_some(),
+            "{} is missing `full_percent` field.",
+            path.display()
+        );
+        assert!(
+            config.replication_reserve.is_some(),
+            "{} is missing `replication_reserve` field.",
+            path.display()
+        );
+        assert!(
+            config.min_free_space.is_some(),
+            "{} is missing `min_free_space` field.",
+            path.display()
+        );
+        assert!(
+            config.unneeded_chunk_max_age_secs.is_some(),
+            "{} is missing `unneeded_chunk_max_age_secs` field.",
+            path.display()
+        );
         assert!(
             config.chunk_store_root.is_some(),
             "{} is missing `chunk_store_root` field.",
             path.display()
         );
+        assert!(
+            config.chunk_store_persistent.is_some(),
+            "{} is missing `chunk_store_persistent` field.",
+            path.display()
+        );
+        assert!(
+            config.chunk_store_type.is_some(),
+            "{} is missing `chunk_store_type` field.",
+            path.display()
+        );
+        assert!(
+            config.chunk_store_compression.is_some(),
+            "{} is missing `chunk_store_compression` field.",
+            path.display()
+        );
+        assert!(
+            config.chunk_store_key_file.is_none(),
+            "{} must not set `chunk_store_key_file`: operators generate their own key.",
+            path.display()
+        );
+        assert!(
+            config.chunk_store_allow_plaintext.is_some(),
+            "{} is missing `chunk_store_allow_plaintext` field.",
+            path.display()
+        );
+        assert!(
+            config.chunk_store_io_threads.is_some(),
+            "{} is missing `chunk_store_io_threads` field.",
+            path.display()
+        );
+        assert!(
+            config.chunk_store_sync.is_some(),
+            "{} is missing `chunk_store_sync` field.",
+            path.display()
+        );
+        assert!(
+            config.chunk_store_dedup.is_some(),
+            "{} is missing `chunk_store_dedup` field.",
+            path.display()
+        );
+        assert!(
+            config.account_db_dir.is_some(),
+            "{} is missing `account_db_dir` field.",
+            path.display()
+        );
+        assert!(
+            config.snapshot_dir.is_some(),
+            "{} is missing `snapshot_dir` field.",
+            path.display()
+        );
+        assert!(
+            config.snapshot_interval_secs.is_some(),
+            "{} is missing `snapshot_interval_secs` field.",
+            path.display()
+        );
         assert!(
             config.invite_key.is_some(),
             "{} is missing `invite_key` field.",
             path.display()
         );
+        assert!(
+            config.max_ops_count.is_some(),
+            "{} is missing `max_ops_count` field.",
+            path.display()
+        );
+        assert!(
+            config.max_bytes.is_some(),
+            "{} is missing `max_bytes` field.",
+            path.display()
+        );
+        assert!(
+            config.account_tiers.is_some(),
+            "{} is missing `account_tiers` field.",
+            path.display()
+        );
         assert!(
             config.dev.is_some(),
             "{} is missing `dev` field.",
@@ -29,6 +29,7 @@ quick_error! {
     #[derive(Debug)]
     pub enum InternalError {
         ChunkStore(error: chunk_store::Error) {
+            display("Chunk store error: {}", error)
             from()
         }
         FailedToFindCachedRequest(message_id: MessageId)
@@ -59,6 +60,7 @@ quick_error! {
         UnknownRequestType(request: Request)
         UnknownResponseType(response: Response)
         InvalidMessage
+        InvalidArchive
         NoSuchAccount
     }
 }
@@ -215,6 +215,7 @@
 extern crate accumulator;
 #[cfg(any(test, feature = "use-mock-crust", feature = "use-mock-routing"))]
 extern crate fake_clock;
+extern crate fs2;
 extern crate hex;
 #[cfg(feature = "use-mock-crust")]
 extern crate itertools;
@@ -239,6 +240,7 @@ extern crate tempdir;
 extern crate tiny_keccak;
 #[macro_use]
 extern crate unwrap;
+extern crate zstd;
 
 /// For unit and integration tests only
 #[cfg(any(feature = "use-mock-crust", feature = "use-mock-routing"))]
@@ -257,17 +259,38 @@ mod error;
 #[cfg(all(test, feature = "use-mock-routing"))]
 mod mock_routing;
 mod personas;
+mod snapshot;
+mod store_archive;
+mod store_inspector;
 mod utils;
 mod vault;
 
-pub use config_handler::Config;
+pub use config_handler::{AccountTier, ChunkStoreType, Compression, Config, SyncPolicy};
 pub use personas::data_manager::DataId;
 #[cfg(feature = "use-mock-crust")]
 pub use personas::data_manager::PENDING_WRITE_TIMEOUT_SECS;
-pub use personas::maid_manager::DEFAULT_MAX_OPS_COUNT;
+pub use personas::maid_manager::{ACCOUNT_SUSPENDED_ERROR, AccountOp, AdminRole, DEFAULT_MAX_BYTES,
+                                 DEFAULT_MAX_OPS_COUNT, DataOpType, Invite,
+                                 KEY_EXPIRY_MARGIN_SECS, KeyScope, MAX_INVITE_CLAIMS,
+                                 STORAGE_LIMIT_ERROR};
 #[cfg(feature = "use-mock-crypto")]
 use routing::mock_crypto::rust_sodium;
+pub use store_archive::{ExportSummary, ImportSummary};
+pub use store_inspector::{ChunkProblem, ChunkReport, StoreInspector};
 pub use vault::Vault;
 
-/// The type tag of invitations to create an account.
+/// The type tag of invitations to create an account.  An invitation is claimed by inserting, or
+/// for `Invite`s allowing more than one claim updating, its `claimed` entry.
 pub const TYPE_TAG_INVITE: u64 = 8;
+/// The type tag of `MutableData` the admin puts to assign a tier to a client account.  The data is
+/// named after the account, and holds the name of the tier under the `tier` key, or no such entry
+/// to reset the account to the default limits.  It is not stored.
+pub const TYPE_TAG_ACCOUNT_TIER: u64 = 9;
+/// The type tag of `MutableData` the root admin puts to grant admin roles to a client account.
+/// The data is named after the account, and holds an entry keyed by the name of each role granted,
+/// replacing any the account had.  It is not stored.
+pub const TYPE_TAG_ADMIN_ROLES: u64 = 10;
+/// The type tag of `MutableData` an admin puts to suspend, resume or delete a client account.  The
+/// data is named after the account, and holds the name of the `AccountOp` under the `op` key.  It
+/// is not stored.
+pub const TYPE_TAG_ACCOUNT_OP: u64 = 11;
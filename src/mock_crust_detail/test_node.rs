@@ -76,11 +76,8 @@ impl TestNode {
             }
             None => {
                 Config {
-                    wallet_address: None,
-                    max_capacity: None,
                     chunk_store_root: Some(format!("{}", chunk_store_root.display())),
-                    invite_key: None,
-                    dev: None,
+                    ..Default::default()
                 }
             }
         };
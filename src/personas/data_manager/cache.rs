@@ -21,7 +21,7 @@ use super::mutation::{self, Mutation};
 use maidsafe_utilities::serialisation::serialised_size;
 use routing::{Authority, MAX_MUTABLE_DATA_ENTRIES, MAX_MUTABLE_DATA_SIZE_IN_BYTES, MessageId,
               MutableData, QUORUM_DENOMINATOR, QUORUM_NUMERATOR, RoutingTable, Value, XorName};
-use std::collections::VecDeque;
+use std::collections::BTreeMap;
 use std::collections::hash_map::Entry;
 use std::iter;
 use std::time::Duration;
@@ -467,6 +467,23 @@ impl Cache {
         }
     }
 
+    /// Returns a record of every pending write.
+    pub fn pending_write_records(&self) -> Vec<PendingWriteRecord> {
+        self.pending_writes
+            .iter()
+            .flat_map(|(data_id, writes)| {
+                writes.iter().map(move |write| {
+                    PendingWriteRecord {
+                        data_id: *data_id,
+                        hash: write.hash,
+                        message_id: write.message_id,
+                        rejected: write.rejected,
+                    }
+                })
+            })
+            .collect()
+    }
+
     /// Removes and returns all timed out pending writes.
     pub fn remove_expired_pending_writes(&mut self) -> Vec<PendingWrite> {
         let timeout = Duration::from_secs(PENDING_WRITE_TIMEOUT_SECS);
@@ -494,10 +511,24 @@ impl Cache {
         expired_writes
     }
 
+    /// Removes and returns the least recently accessed unneeded chunk.
     pub fn pop_unneeded_chunk(&mut self) -> Option<ImmutableDataId> {
         self.unneeded_immutable_chunks.pop().map(ImmutableDataId)
     }
 
+    /// Removes and returns the least recently accessed unneeded chunk if it hasn't been accessed
+    /// for longer than `max_age`.
+    pub fn pop_expired_unneeded_chunk(&mut self, max_age: Duration) -> Option<ImmutableDataId> {
+        self.unneeded_immutable_chunks.pop_expired(max_age).map(
+            ImmutableDataId,
+        )
+    }
+
+    /// Records that the given chunk has been accessed, if it is unneeded.
+    pub fn touch_unneeded_chunk(&mut self, data_id: &ImmutableDataId) {
+        self.unneeded_immutable_chunks.touch(data_id.name());
+    }
+
     /// Validate that the new mutation can be applied to the given data together with
     /// all the pending mutations for that data.
     pub fn validate_concurrent_mutations(
@@ -638,6 +669,15 @@ pub struct PendingWrite {
     pub rejected: bool,
 }
 
+/// A pending write as recorded in a snapshot of the vault state.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct PendingWriteRecord {
+    pub data_id: DataId,
+    pub hash: SecureHash,
+    pub message_id: MessageId,
+    pub rejected: bool,
+}
+
 #[derive(Clone, Copy, Debug, Deserialize, Serialize)]
 pub struct MutationVote {
     pub data_id: DataId,
@@ -864,59 +904,111 @@ impl ChunkRequest {
     }
 }
 
-// Structure that holds data chunk IDs in order but also allows efficient
-// lookup.
+// Structure that holds data chunk IDs ordered by the time they were last accessed (least
+// recently accessed first), but also allows efficient lookup.
 struct UnneededChunks {
-    queue: VecDeque<XorName>,
-    set: HashSet<XorName>,
+    // Maps access sequence numbers to chunk names.
+    order: BTreeMap<u64, XorName>,
+    entries: HashMap<XorName, UnneededChunk>,
+    next_seq: u64,
+}
+
+struct UnneededChunk {
+    seq: u64,
+    last_access: Instant,
 }
 
 impl UnneededChunks {
     fn new() -> Self {
         UnneededChunks {
-            queue: VecDeque::new(),
-            set: HashSet::default(),
+            order: BTreeMap::new(),
+            entries: HashMap::default(),
+            next_seq: 0,
         }
     }
 
     fn len(&self) -> usize {
-        self.queue.len()
+        self.entries.len()
     }
 
     fn contains(&self, name: &XorName) -> bool {
-        self.set.contains(name)
+        self.entries.contains_key(name)
     }
 
     fn push(&mut self, name: XorName) {
-        if self.set.insert(name) {
-            self.queue.push_back(name);
+        if !self.contains(&name) {
+            self.insert(name);
         }
     }
 
+    // Marks the chunk as the most recently accessed one, if present.
+    fn touch(&mut self, name: &XorName) {
+        if let Some(chunk) = self.entries.remove(name) {
+            let _ = self.order.remove(&chunk.seq);
+            self.insert(*name);
+        }
+    }
+
+    // Removes and returns the least recently accessed chunk.
     fn pop(&mut self) -> Option<XorName> {
-        if let Some(name) = self.queue.pop_front() {
-            let _ = self.set.remove(&name);
-            Some(name)
+        let seq = match self.order.keys().next() {
+            Some(seq) => *seq,
+            None => return None,
+        };
+        let name = self.order.remove(&seq);
+        if let Some(ref name) = name {
+            let _ = self.entries.remove(name);
+        }
+        name
+    }
+
+    // Removes and returns the least recently accessed chunk if it hasn't been accessed for longer
+    // than `max_age`.
+    fn pop_expired(&mut self, max_age: Duration) -> Option<XorName> {
+        let expired = self.order
+            .values()
+            .next()
+            .and_then(|name| self.entries.get(name))
+            .map_or(false, |chunk| chunk.last_access.elapsed() > max_age);
+        if expired {
+            self.pop()
         } else {
             None
         }
     }
 
-    fn retain<F>(&mut self, f: F)
+    fn retain<F>(&mut self, mut f: F)
     where
         F: FnMut(&XorName) -> bool,
     {
-        let (retained, removed) = self.queue.drain(..).partition(f);
-        self.queue = retained;
-        for name in removed {
-            let _ = self.set.remove(&name);
+        let removed: Vec<_> = self.order
+            .iter()
+            .filter(|&(_, name)| !f(name))
+            .map(|(seq, name)| (*seq, *name))
+            .collect();
+        for (seq, name) in removed {
+            let _ = self.order.remove(&seq);
+            let _ = self.entries.remove(&name);
         }
     }
 
     #[cfg(all(test, feature = "use-mock-routing"))]
     fn clear(&mut self) {
-        self.queue.clear();
-        self.set.clear();
+        self.order.clear();
+        self.entries.clear();
+    }
+
+    fn insert(&mut self, name: XorName) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let _ = self.order.insert(seq, name);
+        let _ = self.entries.insert(
+            name,
+            UnneededChunk {
+                seq,
+                last_access: Instant::now(),
+            },
+        );
     }
 }
 
@@ -1077,6 +1169,36 @@ mod tests {
         assert_eq!(cache.fragment_index.len(), 0);
     }
 
+    #[test]
+    fn unneeded_chunks_eviction() {
+        let mut rng = rand::thread_rng();
+        let mut cache = Cache::new(8);
+
+        let data_id0 = ImmutableDataId(rng.gen());
+        let data_id1 = ImmutableDataId(rng.gen());
+        let data_id2 = ImmutableDataId(rng.gen());
+
+        cache.add_as_unneeded(data_id0);
+        FakeClock::advance_time(1000);
+        cache.add_as_unneeded(data_id1);
+        FakeClock::advance_time(1000);
+        cache.add_as_unneeded(data_id2);
+
+        // Accessing a chunk makes it the last one to be evicted.
+        cache.touch_unneeded_chunk(&data_id0);
+
+        // Only chunks which haven't been accessed for longer than the max age expire.
+        let max_age = Duration::from_millis(1500);
+        assert_eq!(cache.pop_expired_unneeded_chunk(max_age), None);
+        FakeClock::advance_time(1000);
+        assert_eq!(cache.pop_expired_unneeded_chunk(max_age), Some(data_id1));
+        assert_eq!(cache.pop_expired_unneeded_chunk(max_age), None);
+
+        assert_eq!(cache.pop_unneeded_chunk(), Some(data_id2));
+        assert_eq!(cache.pop_unneeded_chunk(), Some(data_id0));
+        assert_eq!(cache.pop_unneeded_chunk(), None);
+    }
+
     fn first<I: IntoIterator<Item = T>, T>(i: I) -> T {
         unwrap!(i.into_iter().next())
     }
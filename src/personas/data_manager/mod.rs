@@ -8,34 +8,46 @@ mod tests;
 use self::cache::{Cache, FragmentInfo, MutationVote, PendingWrite};
 #[cfg(feature = "use-mock-crust")]
 pub use self::cache::PENDING_WRITE_TIMEOUT_SECS;
+pub use self::cache::PendingWriteRecord;
 pub use self::data::{Data, DataId, ImmutableDataId, MutableDataId};
 use self::mutable_data_cache::MutableDataCache;
 use self::mutation::{Mutation, MutationType};
 use accumulator::Accumulator;
 use authority::ClientManagerAuthority;
-use chunk_store::{Chunk, ChunkId, ChunkStore};
-#[cfg(feature = "use-mock-crust")]
+use chunk_store::{self, Backend, Chunk, ChunkId, ChunkStore, DiskBackend, MemoryBackend,
+                  SnapshotJob, ThreadedBackend, ValueHash};
 use chunk_store::Error as ChunkStoreError;
+use config_handler::{ChunkStoreType, Compression, Config, SyncPolicy};
 use error::InternalError;
 use maidsafe_utilities::serialisation;
-use routing::{Authority, ClientError, EntryAction, ImmutableData, MessageId, MutableData,
-              PermissionSet, QUORUM_DENOMINATOR, QUORUM_NUMERATOR, RoutingTable,
-              TYPE_TAG_SESSION_PACKET, User, Value, XorName};
+use routing::{Authority, ClientError, EntryAction, ImmutableData, MAX_MUTABLE_DATA_ENTRIES,
+              MAX_MUTABLE_DATA_SIZE_IN_BYTES, MessageId, MutableData, PermissionSet,
+              QUORUM_DENOMINATOR, QUORUM_NUMERATOR, RoutingTable, TYPE_TAG_SESSION_PACKET, User,
+              Value, XorName};
 use rust_sodium::crypto::sign;
-use std::collections::{BTreeMap, BTreeSet};
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::convert::From;
 use std::fmt::{self, Debug, Formatter};
+use std::mem;
 use std::time::Duration;
 use tiny_keccak;
 use utils::{self, HashMap, HashSet, Instant};
 use vault::Refresh as VaultRefresh;
 use vault::RoutingNode;
 
-const MAX_FULL_PERCENT: u64 = 50;
+/// The default percentage of the chunk store capacity above which new data is refused.
+const DEFAULT_FULL_PERCENT: u64 = 50;
 /// The timeout for accumulating refresh messages.
 const ACCUMULATOR_TIMEOUT_SECS: u64 = 180;
 /// The interval for print status log.
 const STATUS_LOG_INTERVAL: u64 = 120;
+/// The number of chunks verified by the scrubber on each tick.
+const SCRUB_BATCH_SIZE: usize = 10;
+/// The interval for reconciling the chunk store usage with the actually stored chunks.
+const RECONCILE_INTERVAL_SECS: u64 = 600;
+/// The default free space to keep on the filesystem holding the chunk store.
+const DEFAULT_MIN_FREE_SPACE: u64 = 100 * 1024 * 1024;
 
 macro_rules! log_status {
     ($dm:expr) => {
@@ -59,6 +71,112 @@ impl ChunkId<DataId> for ImmutableDataId {
 
 impl Chunk<DataId> for MutableData {
     type Id = MutableDataId;
+
+    fn split_values(
+        &self,
+        min_len: usize,
+        hash: &Fn(&[u8]) -> ValueHash,
+    ) -> Option<(Vec<u8>, Vec<(ValueHash, Vec<u8>)>)> {
+        // The data is rebuilt using `MutableData::new`, so only split data it accepts.
+        if !self.entries().values().any(
+            |value| value.content.len() >= min_len,
+        ) || (self.version() > 0 && self.owners().len() != 1) ||
+            self.entries().len() as u64 > MAX_MUTABLE_DATA_ENTRIES ||
+            serialisation::serialised_size(self) > MAX_MUTABLE_DATA_SIZE_IN_BYTES
+        {
+            return None;
+        }
+
+        let mut values = Vec::new();
+        let entries = self.entries()
+            .iter()
+            .map(|(key, value)| {
+                let stored_value = if value.content.len() >= min_len {
+                    let value_hash = hash(&value.content);
+                    values.push((value_hash, value.content.clone()));
+                    StoredValue::Shared {
+                        hash: value_hash,
+                        entry_version: value.entry_version,
+                    }
+                } else {
+                    StoredValue::Inline(value.clone())
+                };
+                (key.clone(), stored_value)
+            })
+            .collect();
+        let stored_data = StoredMutableData {
+            name: *self.name(),
+            tag: self.tag(),
+            version: self.version(),
+            owners: self.owners().clone(),
+            permissions: self.permissions().clone(),
+            entries,
+        };
+
+        match serialisation::serialise(&stored_data) {
+            Ok(remainder) => Some((remainder, values)),
+            Err(_) => None,
+        }
+    }
+
+    fn join_values(
+        remainder: &[u8],
+        value: &mut FnMut(&ValueHash) -> Result<Vec<u8>, ChunkStoreError>,
+    ) -> Result<Self, ChunkStoreError> {
+        let stored_data: StoredMutableData = serialisation::deserialise(remainder)?;
+        let mut entries = BTreeMap::new();
+        for (key, stored_value) in stored_data.entries {
+            let entry_value = match stored_value {
+                StoredValue::Inline(entry_value) => entry_value,
+                StoredValue::Shared {
+                    hash,
+                    entry_version,
+                } => Value {
+                    content: value(&hash)?,
+                    entry_version,
+                },
+            };
+            let _ = entries.insert(key, entry_value);
+        }
+
+        let owner = stored_data.owners.iter().next().cloned();
+        let mut data = MutableData::new(
+            stored_data.name,
+            stored_data.tag,
+            stored_data.permissions,
+            entries,
+            stored_data.owners,
+        ).map_err(|_| ChunkStoreError::Corrupted)?;
+        if stored_data.version > 0 {
+            let changed = owner.map_or(false, |owner| {
+                data.change_owner_without_validation(owner, stored_data.version)
+            });
+            if !changed {
+                return Err(ChunkStoreError::Corrupted);
+            }
+        }
+        Ok(data)
+    }
+}
+
+/// `MutableData` as stored in the chunk store with its large entry values shared.
+#[derive(Serialize, Deserialize)]
+struct StoredMutableData {
+    name: XorName,
+    tag: u64,
+    version: u64,
+    owners: BTreeSet<sign::PublicKey>,
+    permissions: BTreeMap<User, PermissionSet>,
+    entries: BTreeMap<Vec<u8>, StoredValue>,
+}
+
+/// Entry value of `StoredMutableData`.
+#[derive(Serialize, Deserialize)]
+enum StoredValue {
+    /// The value is held by the chunk itself.
+    Inline(Value),
+    /// The value is shared, and stored under `hash` in the value store.
+    Shared { hash: ValueHash, entry_version: u64 },
 }
 
 impl ChunkId<DataId> for MutableDataId {
@@ -79,21 +197,81 @@ pub struct DataManager {
     mutable_data_count: u64,
     client_get_requests: u64,
     logging_time: Instant,
+    /// The percentage of the chunk store capacity above which new data is refused.
+    full_percent: u64,
+    /// Space at the end of the chunk store capacity reserved for chunks fetched during churn.
+    replication_reserve: u64,
+    /// The free space to keep on the filesystem holding the chunk store.
+    min_free_space: u64,
+    /// The time after which unneeded chunks nobody accessed are removed, even if there's space.
+    unneeded_chunk_max_age: Option<Duration>,
+    /// Chunks still to be verified in the current scrubbing pass.
+    scrub_queue: VecDeque<DataId>,
+    reconcile_time: Instant,
+    /// Committed mutations waiting for their chunk store writes to complete.
+    pending_commits: Vec<PendingCommit>,
     // This is only used in tests as a place to temporarily hold incoming group refresh messages in
     // order to delay handling them.
     _delayed_group_refresh_cache: Option<BTreeSet<Vec<u8>>>,
 }
 
 impl DataManager {
-    pub fn new(
-        group_size: usize,
-        chunk_store_root: Option<String>,
-        capacity: Option<u64>,
-    ) -> Result<DataManager, InternalError> {
+    pub fn new(group_size: usize, config: &Config) -> Result<DataManager, InternalError> {
         let quorum = ((group_size * QUORUM_NUMERATOR) / QUORUM_DENOMINATOR) + 1;
-        let chunk_store = ChunkStore::new(chunk_store_root, capacity)?;
+        let deduplicate = config.chunk_store_dedup.unwrap_or(false);
+        // Chunks stored with deduplication enabled keep referring to the shared values, so a disk
+        // chunk store always needs its value store, even once deduplication is disabled.
+        let (backend, values): (Box<Backend>, Option<Box<Backend>>) =
+            match config.chunk_store_type.unwrap_or(ChunkStoreType::Disk) {
+                ChunkStoreType::Disk => {
+                    let mut backend = DiskBackend::new(
+                        config.chunk_store_root.clone(),
+                        config.chunk_store_persistent.unwrap_or(false),
+                    )?;
+                    backend.set_sync_policy(config.chunk_store_sync.unwrap_or(SyncPolicy::Always));
+                    let values = Box::new(backend.value_store()?);
+                    let backend: Box<Backend> = match config.chunk_store_io_threads {
+                        Some(thread_count) if thread_count > 0 => {
+                            Box::new(ThreadedBackend::new(backend, thread_count)?)
+                        }
+                        _ => Box::new(backend),
+                    };
+                    (backend, Some(values))
+                }
+                ChunkStoreType::Memory => {
+                    let values: Option<Box<Backend>> = if deduplicate {
+                        Some(Box::new(MemoryBackend::new()))
+                    } else {
+                        None
+                    };
+                    (Box::new(MemoryBackend::new()), values)
+                }
+            };
+        let encryption_key = match config.chunk_store_key_file {
+            Some(ref path) => Some(chunk_store::read_key_file(path)?),
+            None => None,
+        };
+        let mut chunk_store = ChunkStore::new(
+            backend,
+            config.max_capacity,
+            config.chunk_store_compression.unwrap_or(Compression::None),
+            encryption_key,
+        )?;
+        if let Some(values) = values {
+            chunk_store.set_value_store(values, deduplicate);
+        }
+        chunk_store.set_allow_plaintext(config.chunk_store_allow_plaintext.unwrap_or(false));
         let accumulator_duration = Duration::from_secs(ACCUMULATOR_TIMEOUT_SECS);
 
+        // A persistent chunk store may already hold chunks from before a restart.
+        let (immutable_data_count, mutable_data_count) = chunk_store.keys().into_iter().fold(
+            (0, 0),
+            |(immutable, mutable), data_id| match data_id {
+                DataId::Immutable(_) => (immutable + 1, mutable),
+                DataId::Mutable(_) => (immutable, mutable + 1),
+            },
+        );
+
         Ok(DataManager {
             group_size,
             chunk_store,
@@ -101,10 +279,17 @@ impl DataManager {
             fragment_refresh_accumulator: Accumulator::with_duration(quorum, accumulator_duration),
             cache: Cache::new(group_size),
             mdata_cache: MutableDataCache::new(group_size),
-            immutable_data_count: 0,
-            mutable_data_count: 0,
+            immutable_data_count,
+            mutable_data_count,
             client_get_requests: 0,
             logging_time: Instant::now(),
+            full_percent: cmp::min(config.full_percent.unwrap_or(DEFAULT_FULL_PERCENT), 100),
+            replication_reserve: config.replication_reserve.unwrap_or(0),
+            min_free_space: config.min_free_space.unwrap_or(DEFAULT_MIN_FREE_SPACE),
+            unneeded_chunk_max_age: config.unneeded_chunk_max_age_secs.map(Duration::from_secs),
+            scrub_queue: VecDeque::new(),
+            reconcile_time: Instant::now(),
+            pending_commits: Vec::new(),
             // TODO: Once https://github.com/rust-lang/rust/issues/41681 is in stable we can
             // initialise this field under #[cfg(feature = "use-mock-crust")] and exclude the
             // member variable from the struct for production builds altogether.
@@ -178,17 +363,112 @@ impl DataManager {
             ..
         } = write;
 
-        let mutation_type = mutation.mutation_type();
-        let fragments = self.commit_pending_mutation(
+        self.commit_pending_mutation(routing_node, src, dst, mutation, message_id)?;
+        self.handle_completed_writes(routing_node)
+    }
+
+    /// Tests if any committed mutations are waiting for their chunk store writes to complete
+    /// before being responded to.
+    pub fn has_pending_commits(&self) -> bool {
+        !self.pending_commits.is_empty()
+    }
+
+    /// Waits up to `timeout` until a chunk store write completes, so `handle_completed_writes` can
+    /// respond to the mutations waiting for it.
+    pub fn wait_for_writes(&mut self, timeout: Duration) {
+        self.chunk_store.wait_for_writes(timeout)
+    }
+
+    /// Returns how long until chunk store writes not synced to disk yet are due to be synced with
+    /// `sync_if_due`, or `None` if there are none.
+    pub fn time_until_sync(&self) -> Option<Duration> {
+        self.chunk_store.time_until_sync()
+    }
+
+    /// Syncs the chunk store writes not synced to disk yet if they are due.
+    pub fn sync_if_due(&mut self) {
+        if let Err(error) = self.chunk_store.sync_if_due() {
+            warn!("Failed to sync chunk store: {:?}", error);
+        }
+    }
+
+    /// Responds to the committed mutations whose chunk store writes completed, and refreshes the
+    /// group with them.  When the chunk store writes in the background, this is called after
+    /// every event, and whenever the event loop is woken by a completed write.
+    pub fn handle_completed_writes(
+        &mut self,
+        routing_node: &mut RoutingNode,
+    ) -> Result<(), InternalError> {
+        let failed: HashSet<DataId> = self.chunk_store
+            .poll_failed_writes()
+            .into_iter()
+            .map(|(data_id, error)| {
+                warn!("Failed to write {:?} to chunk store: {:?}", data_id, error);
+                data_id
+            })
+            .collect();
+        if self.pending_commits.is_empty() {
+            return Ok(());
+        }
+
+        let (done, waiting): (Vec<_>, Vec<_>) =
+            mem::replace(&mut self.pending_commits, Vec::new())
+                .into_iter()
+                .partition(|commit| {
+                    failed.contains(&commit.data_id) ||
+                        !self.chunk_store.is_writing(&commit.data_id)
+                });
+        self.pending_commits = waiting;
+
+        for commit in done {
+            let res = if failed.contains(&commit.data_id) {
+                Err(ClientError::from("Failed to store chunk"))
+            } else {
+                Ok(())
+            };
+            self.finish_commit(routing_node, commit, res)?;
+        }
+        Ok(())
+    }
+
+    // Responds to a committed mutation and, if it was successful, refreshes the group with it.
+    fn finish_commit(
+        &mut self,
+        routing_node: &mut RoutingNode,
+        commit: PendingCommit,
+        res: Result<(), ClientError>,
+    ) -> Result<(), InternalError> {
+        let PendingCommit {
+            src,
+            dst,
+            mutation_type,
+            data_id,
+            msg_id,
+            fragments,
+            bytes_change,
+        } = commit;
+
+        let success = res.is_ok();
+        self.send_mutation_response(
             routing_node,
             src,
             dst,
-            mutation,
-            message_id,
+            mutation_type,
+            data_id,
+            res,
+            msg_id,
         )?;
 
-        if fragments.is_empty() {
-            // The commit wasn't successful.
+        // The client managers can't tell how much updating or deleting entries changes the size
+        // of the account's data, so they're told.
+        if let Authority::ClientManager(_) = src {
+            if success && bytes_change != 0 {
+                let payload = serialisation::serialise(&bytes_change)?;
+                routing_node.send_refresh_request(dst, src, payload, msg_id)?;
+            }
+        }
+
+        if !success || fragments.is_empty() {
             return Ok(());
         }
 
@@ -231,7 +511,9 @@ impl DataManager {
     ) -> Result<(), InternalError> {
         self.update_request_stats(&src);
 
-        if let Ok(data) = self.chunk_store.get(&ImmutableDataId(name)) {
+        let data_id = ImmutableDataId(name);
+        if let Ok(data) = self.chunk_store.get(&data_id) {
+            self.cache.touch_unneeded_chunk(&data_id);
             trace!("As {:?} sending data {:?} to {:?}", dst, data, src);
             routing_node.send_get_idata_response(
                 dst,
@@ -285,8 +567,7 @@ impl DataManager {
             return Ok(()); // data is already there.
         }
 
-        self.clean_chunk_store();
-        self.chunk_store.put(&data_id, &data)?;
+        self.store_replica(&data_id, &data)?;
 
         self.immutable_data_count += 1;
         log_status!(self);
@@ -425,8 +706,7 @@ impl DataManager {
 
         if let Some(mut data) = data {
             merge_mdata_entries(&mut data, entries);
-            self.clean_chunk_store();
-            self.chunk_store.put(&data.id(), &data)?;
+            self.store_replica(&data.id(), &data)?;
         } else {
             self.mdata_cache.insert_entries(data_id, entries);
         }
@@ -573,8 +853,7 @@ impl DataManager {
             }
         };
 
-        self.clean_chunk_store();
-        self.chunk_store.put(&data_id, &shell)?;
+        self.store_replica(&data_id, &shell)?;
 
         if new {
             self.mutable_data_count += 1;
@@ -749,8 +1028,7 @@ impl DataManager {
         match self.chunk_store.get(&data_id) {
             Ok(mut data) => {
                 if data.mutate_entry_without_validation(key, value) {
-                    self.clean_chunk_store();
-                    self.chunk_store.put(&data_id, &data)?;
+                    self.store_replica(&data_id, &data)?;
                 }
             }
             Err(_) => {
@@ -1049,6 +1327,62 @@ impl DataManager {
         let _ = self.request_needed_data(routing_node);
     }
 
+    /// Performs the periodic maintenance of the chunk store.
+    pub fn handle_tick(&mut self, routing_node: &mut RoutingNode) -> Result<(), InternalError> {
+        if self.reconcile_time.elapsed().as_secs() > RECONCILE_INTERVAL_SECS {
+            self.reconcile_time = Instant::now();
+            self.chunk_store.reconcile();
+        }
+
+        if let Err(error) = self.chunk_store.sync() {
+            warn!("Failed to sync chunk store: {:?}", error);
+        }
+
+        self.remove_expired_unneeded_chunks();
+        self.scrub_chunk_store(routing_node)
+    }
+
+    /// Lists the chunks to save a point-in-time copy of, without waiting for the chunk store
+    /// writes in progress.  Returns the listing, to be saved on another thread, and a record of
+    /// the pending writes, which are not applied to the chunk store yet.
+    pub fn prepare_snapshot(
+        &self,
+    ) -> Result<(SnapshotJob<DataId>, Vec<PendingWriteRecord>), InternalError> {
+        let job = self.chunk_store.prepare_snapshot()?;
+        Ok((job, self.cache.pending_write_records()))
+    }
+
+    /// Verifies the next few chunks in the chunk store.  Corrupt chunks are deleted and fetched
+    /// again from the other members of their group.
+    fn scrub_chunk_store(
+        &mut self,
+        routing_node: &mut RoutingNode,
+    ) -> Result<(), InternalError> {
+        if self.scrub_queue.is_empty() {
+            self.scrub_queue = self.chunk_store.keys().into_iter().collect();
+        }
+
+        let mut found_corrupt_chunks = false;
+        for _ in 0..SCRUB_BATCH_SIZE {
+            let data_id = match self.scrub_queue.pop_front() {
+                Some(data_id) => data_id,
+                None => break,
+            };
+
+            if self.is_chunk_corrupt(&data_id) {
+                self.handle_corrupt_chunk(routing_node, data_id)?;
+                found_corrupt_chunks = true;
+            }
+        }
+
+        if found_corrupt_chunks {
+            log_status!(self);
+            self.request_needed_data(routing_node)?;
+        }
+
+        Ok(())
+    }
+
     fn fetch_mdata(&self, name: XorName, tag: u64) -> Result<MutableData, ClientError> {
         let data_id = MutableDataId(name, tag);
         if let Ok(data) = self.chunk_store.get(&data_id) {
@@ -1163,9 +1497,10 @@ impl DataManager {
         dst: Authority<XorName>,
         mutation: Mutation,
         msg_id: MessageId,
-    ) -> Result<Vec<FragmentInfo>, InternalError> {
+    ) -> Result<(), InternalError> {
         let mutation_type = mutation.mutation_type();
         let data_id = mutation.data_id();
+        let mut bytes_change = 0;
 
         let res = match mutation {
             Mutation::PutIData(data) => {
@@ -1179,7 +1514,9 @@ impl DataManager {
             Mutation::MutateMDataEntries { name, tag, actions } => {
                 self.with_mdata(name, tag, |data| {
                     let keys: Vec<_> = actions.keys().cloned().collect();
+                    let old_size = entries_size(data, &keys);
                     data.mutate_entries_without_validation(actions);
+                    bytes_change = entries_size(data, &keys) as i64 - old_size as i64;
                     keys.into_iter()
                         .filter_map(|key| {
                             data.get(&key).map(|value| {
@@ -1228,21 +1565,26 @@ impl DataManager {
             }
         };
 
-        let (res, fragments) = match res {
-            Ok(fragments) => (Ok(()), fragments),
-            Err(error) => (Err(error), Vec::new()),
-        };
-
-        self.send_mutation_response(
-            routing_node,
+        let commit = PendingCommit {
             src,
             dst,
             mutation_type,
             data_id,
-            res,
             msg_id,
-        )?;
-        Ok(fragments)
+            fragments: Vec::new(),
+            bytes_change,
+        };
+        match res {
+            // The response is sent once the chunk store write completes.
+            Ok(fragments) => {
+                self.pending_commits.push(PendingCommit {
+                    fragments,
+                    ..commit
+                })
+            }
+            Err(error) => self.finish_commit(routing_node, commit, Err(error))?,
+        }
+        Ok(())
     }
 
     #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
@@ -1327,30 +1669,98 @@ impl DataManager {
         Ok(())
     }
 
-    /// Returns whether our data uses more than `MAX_FULL_PERCENT` percent of available space.
+    /// Returns whether our data uses more than `full_percent` percent of available space or cuts
+    /// into the replication reserve, or the filesystem holding it is low on space.
     fn chunk_store_full(&self) -> bool {
-        self.chunk_store.used_space() > (self.chunk_store.max_space() / 100) * MAX_FULL_PERCENT
+        let used_space = self.chunk_store.used_space();
+        let max_space = self.chunk_store.max_space();
+        used_space > (max_space / 100) * self.full_percent ||
+            used_space + self.replication_reserve > max_space || self.disk_space_low()
+    }
+
+    /// Returns whether the filesystem holding the chunk store has less than `min_free_space` left.
+    fn disk_space_low(&self) -> bool {
+        self.chunk_store.available_space().map_or(
+            false,
+            |available| available < self.min_free_space,
+        )
     }
 
     /// Removes data chunks we are no longer responsible for until the chunk store is not full
     /// anymore.
     fn clean_chunk_store(&mut self) {
         while self.chunk_store_full() {
-            if let Some(data_id) = self.cache.pop_unneeded_chunk() {
-                if let Err(error) = self.chunk_store.delete(&data_id) {
-                    warn!(
-                        "DM failed to delete unneeded chunk {:?}: {:?}",
-                        data_id,
-                        error
-                    );
-                    break;
-                }
-            } else {
+            if !self.remove_unneeded_chunk() {
                 break;
             }
         }
     }
 
+    /// Removes the next data chunk we are no longer responsible for.  Returns whether one was
+    /// removed.
+    fn remove_unneeded_chunk(&mut self) -> bool {
+        if let Some(data_id) = self.cache.pop_unneeded_chunk() {
+            if let Err(error) = self.chunk_store.delete(&data_id) {
+                warn!(
+                    "DM failed to delete unneeded chunk {:?}: {:?}",
+                    data_id,
+                    error
+                );
+                false
+            } else {
+                true
+            }
+        } else {
+            false
+        }
+    }
+
+    /// Removes data chunks we are no longer responsible for, which haven't been accessed for longer
+    /// than `unneeded_chunk_max_age`.
+    fn remove_expired_unneeded_chunks(&mut self) {
+        let max_age = match self.unneeded_chunk_max_age {
+            Some(max_age) => max_age,
+            None => return,
+        };
+
+        while let Some(data_id) = self.cache.pop_expired_unneeded_chunk(max_age) {
+            trace!("DM removing expired unneeded chunk {:?}", data_id);
+            if let Err(error) = self.chunk_store.delete(&data_id) {
+                warn!(
+                    "DM failed to delete unneeded chunk {:?}: {:?}",
+                    data_id,
+                    error
+                );
+            }
+        }
+    }
+
+    /// Stores a chunk we are responsible for, fetched from the other members of its group.
+    ///
+    /// Unlike data put by clients, this is not limited by `full_percent` and may use the
+    /// replication reserve, but it still keeps `min_free_space` on the filesystem.  If necessary,
+    /// unneeded chunks are removed to make room for it.
+    fn store_replica<T: Chunk<DataId>>(
+        &mut self,
+        data_id: &T::Id,
+        data: &T,
+    ) -> Result<(), InternalError> {
+        self.clean_chunk_store();
+        if self.disk_space_low() {
+            return Err(From::from(ChunkStoreError::NotEnoughSpace));
+        }
+        loop {
+            match self.chunk_store.put(data_id, data) {
+                Err(ChunkStoreError::NotEnoughSpace) => {
+                    if !self.remove_unneeded_chunk() {
+                        return Err(From::from(ChunkStoreError::NotEnoughSpace));
+                    }
+                }
+                result => return Ok(result?),
+            }
+        }
+    }
+
     fn handle_fragment_refresh(
         &mut self,
         src: XorName,
@@ -1463,6 +1873,62 @@ impl DataManager {
         Ok(())
     }
 
+    fn is_chunk_corrupt(&self, data_id: &DataId) -> bool {
+        let result = match *data_id {
+            DataId::Immutable(idata_id) => {
+                self.chunk_store.get(&idata_id).map(|data| {
+                    recompute_idata_name(&data) != idata_id.0
+                })
+            }
+            DataId::Mutable(mdata_id) => self.chunk_store.get(&mdata_id).map(|_| false),
+        };
+
+        match result {
+            Ok(corrupt) => corrupt,
+            Err(ChunkStoreError::Corrupted) |
+            Err(ChunkStoreError::Serialisation(_)) => true,
+            // E.g. the chunk has been deleted since the scrubbing pass started.  Chunks which fail
+            // to decrypt are kept too, as that's most likely due to a misconfigured key.
+            Err(_) => false,
+        }
+    }
+
+    // Deletes the corrupt chunk and, if we are still responsible for it, marks it as needed.
+    fn handle_corrupt_chunk(
+        &mut self,
+        routing_node: &mut RoutingNode,
+        data_id: DataId,
+    ) -> Result<(), InternalError> {
+        warn!("DM found corrupt chunk {:?} - deleting it.", data_id);
+
+        match data_id {
+            DataId::Immutable(idata_id) => {
+                self.chunk_store.delete(&idata_id)?;
+                if self.cache.is_in_unneeded(&idata_id) {
+                    return Ok(());
+                }
+                self.immutable_data_count -= 1;
+
+                let our_name = *routing_node.id()?.name();
+                if let Some(group) = routing_node.close_group(idata_id.0, self.group_size) {
+                    for holder in group.into_iter().filter(|holder| *holder != our_name) {
+                        self.cache.insert_needed_fragment(
+                            FragmentInfo::ImmutableData(idata_id.0),
+                            holder,
+                        );
+                    }
+                }
+            }
+            DataId::Mutable(mdata_id) => {
+                self.chunk_store.delete(&mdata_id)?;
+                self.mutable_data_count -= 1;
+                self.cache.insert_needed_mutable_chunk(mdata_id);
+            }
+        }
+
+        Ok(())
+    }
+
     fn request_needed_data(&mut self, routing_node: &mut RoutingNode) -> Result<(), InternalError> {
         self.request_needed_chunks(routing_node)?;
         self.request_needed_fragments(routing_node)?;
@@ -1697,11 +2163,12 @@ impl Debug for DataManager {
         write!(
             formatter,
             "This vault has received {} Client Get requests. Chunks stored: Immutable: {}, \
-                Mutable: {}. Total stored: {} bytes.",
+                Mutable: {}. Total stored: {} bytes ({} bytes on disk).",
             self.client_get_requests,
             self.immutable_data_count,
             self.mutable_data_count,
-            self.chunk_store.used_space()
+            self.chunk_store.used_space(),
+            self.chunk_store.disk_usage()
         )
     }
 }
@@ -1723,6 +2190,28 @@ impl Refresh {
     }
 }
 
+// A mutation applied to the chunk store, waiting for the write to complete before the client is
+// answered and the group refreshed.
+struct PendingCommit {
+    src: Authority<XorName>,
+    dst: Authority<XorName>,
+    mutation_type: MutationType,
+    data_id: DataId,
+    msg_id: MessageId,
+    fragments: Vec<FragmentInfo>,
+    // The change in the size of the mutated entries, reported to the client managers.
+    bytes_change: i64,
+}
+
+// Returns the total size of the entries of `data` under `keys`, as charged to the owner's account.
+fn entries_size(data: &MutableData, keys: &[Vec<u8>]) -> u64 {
+    keys.iter()
+        .filter_map(|key| {
+            data.get(key).map(|value| utils::mdata_entry_size(key, value))
+        })
+        .sum()
+}
+
 fn close_to_address(routing_node: &mut RoutingNode, address: &XorName) -> bool {
     routing_node
         .close_group(*address, routing_node.min_section_size())
@@ -21,6 +21,8 @@ use maidsafe_utilities::serialisation::{deserialise, serialise};
 use mock_routing::RequestWrapper;
 use rand::{self, Rng};
 use routing::{Action, EntryActions, MAX_MUTABLE_DATA_ENTRIES, Request, Response, User};
+use std::u64;
+use tempdir::TempDir;
 use test_utils;
 use vault::Refresh as VaultRefresh;
 
@@ -40,7 +42,7 @@ fn idata_basics() {
     let nae_manager = Authority::NaeManager(*data.name());
 
     let mut node = test_utils::new_routing_node(GROUP_SIZE);
-    let mut dm = unwrap!(DataManager::new(GROUP_SIZE, None, CHUNK_STORE_CAPACITY));
+    let mut dm = new_data_manager();
 
     // Get non-existent data fails.
     let msg_id = MessageId::new();
@@ -96,7 +98,7 @@ fn mdata_basics() {
     let nae_manager = Authority::NaeManager(data_name);
 
     let mut node = test_utils::new_routing_node(GROUP_SIZE);
-    let mut dm = unwrap!(DataManager::new(GROUP_SIZE, None, CHUNK_STORE_CAPACITY));
+    let mut dm = new_data_manager();
 
     // Attempt to list entries of non-existent data fails.
     let msg_id = MessageId::new();
@@ -158,7 +160,7 @@ fn mdata_mutations() {
     let nae_manager = Authority::NaeManager(data_name);
 
     let mut node = test_utils::new_routing_node(GROUP_SIZE);
-    let mut dm = unwrap!(DataManager::new(GROUP_SIZE, None, CHUNK_STORE_CAPACITY));
+    let mut dm = new_data_manager();
 
     // Put the data.
     dm.put_into_chunk_store(data);
@@ -205,6 +207,7 @@ fn mdata_mutations() {
 
     let message = unwrap!(node.sent_responses.remove(&msg_id));
     assert_match!(message.response, Response::MutateMDataEntries { res: Ok(()), .. });
+    let inserted = take_bytes_change(&mut node, msg_id, client_manager.into());
 
     // The data should now contain the previously inserted two entries.
     let msg_id = MessageId::new();
@@ -229,7 +232,38 @@ fn mdata_mutations() {
         entry_version: 0,
     };
 
-    assert_eq!(entries, vec![(key_0, value_0), (key_1, value_1)].into_iter().collect());
+    let size_0 = utils::mdata_entry_size(&key_0, &value_0);
+    let size_1 = utils::mdata_entry_size(&key_1, &value_1);
+    assert_eq!(inserted, (size_0 + size_1) as i64);
+    assert_eq!(entries,
+               vec![(key_0.clone(), value_0), (key_1.clone(), value_1)].into_iter().collect());
+
+    // Updating an entry reports the change in its size, and deleting one reports its size freed.
+    let actions = EntryActions::new()
+        .update(key_0.clone(), Vec::new(), 1)
+        .del(key_1, 1)
+        .into();
+    let msg_id = MessageId::new();
+    unwrap!(dm.handle_mutate_mdata_entries(&mut node,
+                                           client_manager.into(),
+                                           nae_manager,
+                                           data_name,
+                                           TEST_TAG,
+                                           actions,
+                                           msg_id,
+                                           client_key));
+    let message = unwrap!(node.sent_requests.remove(&msg_id));
+    let refresh = assert_match!(message.request, Request::Refresh(payload, _) => payload);
+    unwrap!(dm.handle_group_refresh(&mut node, refresh));
+    let message = unwrap!(node.sent_responses.remove(&msg_id));
+    assert_match!(message.response, Response::MutateMDataEntries { res: Ok(()), .. });
+
+    let value_0 = Value {
+        content: Vec::new(),
+        entry_version: 1,
+    };
+    let updated = take_bytes_change(&mut node, msg_id, client_manager.into());
+    assert_eq!(updated, utils::mdata_entry_size(&key_0, &value_0) as i64 - inserted);
 }
 
 #[test]
@@ -244,7 +278,7 @@ fn mdata_change_owner() {
     let nae_manager = Authority::NaeManager(data_name);
 
     let mut node = test_utils::new_routing_node(GROUP_SIZE);
-    let mut dm = unwrap!(DataManager::new(GROUP_SIZE, None, CHUNK_STORE_CAPACITY));
+    let mut dm = new_data_manager();
 
     // Put the data.
     dm.put_into_chunk_store(data);
@@ -297,7 +331,7 @@ fn handle_node_added() {
     let mut rng = SeededRng::new();
 
     let mut node = test_utils::new_routing_node(GROUP_SIZE);
-    let mut dm = unwrap!(DataManager::new(GROUP_SIZE, None, CHUNK_STORE_CAPACITY));
+    let mut dm = new_data_manager();
 
     let new_node_name = rand::random();
     node.add_to_routing_table(new_node_name);
@@ -419,6 +453,139 @@ fn idata_with_churn() {
     assert!(new_node.sent_requests.is_empty());
 }
 
+#[test]
+fn scrub_corrupt_idata() {
+    let mut rng = SeededRng::new();
+
+    let (mut node, mut dm, _) = setup_churn(&mut rng);
+
+    // Store immutable data whose content doesn't match its name, as if the chunk got corrupted
+    // on disk.
+    let data = test_utils::gen_immutable_data(10, &mut rng);
+    let bad_data = test_utils::gen_immutable_data(10, &mut rng);
+    unwrap!(dm.chunk_store.put(&data.id(), &bad_data));
+    dm.immutable_data_count += 1;
+
+    // Scrubbing should delete the chunk and request it from another holder.
+    unwrap!(dm.scrub_chunk_store(&mut node));
+    assert!(dm.get_from_chunk_store(&data.id()).is_none());
+
+    assert_eq!(node.sent_requests.len(), 1);
+    let (_, message) = unwrap!(node.sent_requests.drain().next());
+    let name = assert_match!(message.request, Request::GetIData { name, .. } => name);
+    assert_eq!(name, *data.name());
+    let dst = assert_match!(message.dst, Authority::ManagedNode(name) => name);
+
+    // Receiving the healthy data should put it back into the chunk store.
+    unwrap!(dm.handle_get_idata_success(&mut node, dst, data.clone()));
+    assert_eq!(dm.get_from_chunk_store(&data.id()), Some(data));
+
+    // The next pass finds nothing wrong.
+    unwrap!(dm.scrub_chunk_store(&mut node));
+    assert!(node.sent_requests.is_empty());
+}
+
+#[test]
+fn replication_ignores_full_percent() {
+    let mut rng = SeededRng::new();
+
+    let (_, client_key) = test_utils::gen_client_authority();
+    let client_manager = test_utils::gen_client_manager_authority(client_key);
+
+    let mut node = test_utils::new_routing_node(GROUP_SIZE);
+    let other_node_names: Vec<XorName> = rng.gen_iter().take(GROUP_SIZE - 1).collect();
+    for name in &other_node_names {
+        node.add_to_routing_table(*name);
+    }
+
+    let config = Config {
+        max_capacity: CHUNK_STORE_CAPACITY,
+        full_percent: Some(0),
+        chunk_store_type: Some(ChunkStoreType::Memory),
+        ..Default::default()
+    };
+    let mut dm = unwrap!(DataManager::new(GROUP_SIZE, &config));
+    dm.put_into_chunk_store(test_utils::gen_immutable_data(10, &mut rng));
+
+    // Clients can't put new data.
+    let data = test_utils::gen_immutable_data(10, &mut rng);
+    let msg_id = MessageId::new();
+    let _ = dm.handle_put_idata(
+        &mut node,
+        client_manager.into(),
+        Authority::NaeManager(*data.name()),
+        data.clone(),
+        msg_id,
+    );
+    let message = unwrap!(node.sent_responses.remove(&msg_id));
+    assert_match!(
+        message.response,
+        Response::PutIData { res: Err(ClientError::NetworkFull), .. }
+    );
+
+    // But data we are responsible for is still fetched during churn.
+    let refresh = Refresh::Fragment(FragmentInfo::ImmutableData(*data.name()));
+    let refresh_payload = unwrap!(serialise(&vec![refresh]));
+    for name in other_node_names.iter().take(QUORUM) {
+        unwrap!(dm.handle_serialised_refresh(&mut node, *name, &refresh_payload));
+    }
+    let (_, message) = unwrap!(node.sent_requests.drain().next());
+    let dst = assert_match!(message.dst, Authority::ManagedNode(name) => name);
+    unwrap!(dm.handle_get_idata_success(&mut node, dst, data.clone()));
+    assert_eq!(dm.get_from_chunk_store(&data.id()), Some(data));
+}
+
+#[test]
+fn replication_keeps_min_free_space() {
+    let mut rng = SeededRng::new();
+
+    let (_, client_key) = test_utils::gen_client_authority();
+    let client_manager = test_utils::gen_client_manager_authority(client_key);
+
+    let mut node = test_utils::new_routing_node(GROUP_SIZE);
+    let other_node_names: Vec<XorName> = rng.gen_iter().take(GROUP_SIZE - 1).collect();
+    for name in &other_node_names {
+        node.add_to_routing_table(*name);
+    }
+
+    let temp_dir = unwrap!(TempDir::new("safe_vault_test"));
+    let config = Config {
+        max_capacity: CHUNK_STORE_CAPACITY,
+        min_free_space: Some(u64::MAX),
+        chunk_store_root: Some(unwrap!(temp_dir.path().to_str()).to_string()),
+        chunk_store_type: Some(ChunkStoreType::Disk),
+        ..Default::default()
+    };
+    let mut dm = unwrap!(DataManager::new(GROUP_SIZE, &config));
+
+    // Clients can't put new data.
+    let data = test_utils::gen_immutable_data(10, &mut rng);
+    let msg_id = MessageId::new();
+    let _ = dm.handle_put_idata(
+        &mut node,
+        client_manager.into(),
+        Authority::NaeManager(*data.name()),
+        data.clone(),
+        msg_id,
+    );
+    let message = unwrap!(node.sent_responses.remove(&msg_id));
+    assert_match!(
+        message.response,
+        Response::PutIData { res: Err(ClientError::NetworkFull), .. }
+    );
+
+    // Nor is data we are responsible for stored during churn.
+    let refresh = Refresh::Fragment(FragmentInfo::ImmutableData(*data.name()));
+    let refresh_payload = unwrap!(serialise(&vec![refresh]));
+    for name in other_node_names.iter().take(QUORUM) {
+        unwrap!(dm.handle_serialised_refresh(&mut node, *name, &refresh_payload));
+    }
+    let (_, message) = unwrap!(node.sent_requests.drain().next());
+    let dst = assert_match!(message.dst, Authority::ManagedNode(name) => name);
+    assert!(dm.handle_get_idata_success(&mut node, dst, data.clone()).is_err());
+    assert_eq!(dm.get_from_chunk_store(&data.id()), None);
+}
+
 // Test how mutable data with some entries is replicated from nodes that hold it
 // to a newly joined node during churn.
 //
@@ -553,7 +720,7 @@ fn mdata_non_conflicting_parallel_mutations() {
     let mut rng = SeededRng::new();
 
     let mut node = test_utils::new_routing_node(GROUP_SIZE);
-    let mut dm = unwrap!(DataManager::new(GROUP_SIZE, None, CHUNK_STORE_CAPACITY));
+    let mut dm = new_data_manager();
 
     let (_, client_key_0) = test_utils::gen_client_authority();
     let client_manager_0 = test_utils::gen_client_manager_authority(client_key_0);
@@ -630,7 +797,7 @@ fn mdata_conflicting_parallel_mutations() {
     let mut rng = SeededRng::new();
 
     let mut node = test_utils::new_routing_node(GROUP_SIZE);
-    let mut dm = unwrap!(DataManager::new(GROUP_SIZE, None, CHUNK_STORE_CAPACITY));
+    let mut dm = new_data_manager();
 
     let (_, client_key_0) = test_utils::gen_client_authority();
     let client_manager_0 = test_utils::gen_client_manager_authority(client_key_0);
@@ -701,7 +868,7 @@ fn mdata_parallel_mutations_limits() {
     let mut rng = SeededRng::new();
 
     let mut node = test_utils::new_routing_node(GROUP_SIZE);
-    let mut dm = unwrap!(DataManager::new(GROUP_SIZE, None, CHUNK_STORE_CAPACITY));
+    let mut dm = new_data_manager();
 
     let (_, client_key_0) = test_utils::gen_client_authority();
     let client_manager_0 = test_utils::gen_client_manager_authority(client_key_0);
@@ -820,13 +987,23 @@ fn mdata_parallel_mutations_limits() {
     assert_match!(message.response, Response::MutateMDataEntries { res: Err(_), .. });
 }
 
+// Create a `DataManager` backed by an in-memory chunk store.
+fn new_data_manager() -> DataManager {
+    let config = Config {
+        max_capacity: CHUNK_STORE_CAPACITY,
+        chunk_store_type: Some(ChunkStoreType::Memory),
+        ..Default::default()
+    };
+    unwrap!(DataManager::new(GROUP_SIZE, &config))
+}
+
 // Create and setup all the objects necessary for churn-related tests.
 // Returns:
 //   - new node (RoutingNode + DataManager),
 //   - names of the rest of the nodes in the group.
 fn setup_churn<R: Rng>(rng: &mut R) -> (RoutingNode, DataManager, Vec<XorName>) {
     let mut new_node = test_utils::new_routing_node(GROUP_SIZE);
-    let new_dm = unwrap!(DataManager::new(GROUP_SIZE, None, CHUNK_STORE_CAPACITY));
+    let new_dm = new_data_manager();
 
     let other_node_names: Vec<_> = rng.gen_iter().take(GROUP_SIZE - 1).collect();
 
@@ -859,6 +1036,14 @@ fn setup_mdata_refresh<R: Rng>(
     (new_node, new_dm, other_node_names)
 }
 
+// Takes the change in the size of the account's data reported to its client managers.
+fn take_bytes_change(node: &mut RoutingNode, msg_id: MessageId, dst: Authority<XorName>) -> i64 {
+    let message = unwrap!(node.sent_requests.remove(&msg_id));
+    assert_eq!(message.dst, dst);
+    let payload = assert_match!(message.request, Request::Refresh(payload, _) => payload);
+    unwrap!(deserialise(&payload))
+}
+
 // Removes and returns sent `GetMData` request.
 fn take_get_mdata_request(node: &mut RoutingNode) -> (MessageId, MutableDataId) {
     let (msg_id, message) = take_request(node, |message| match message.request {
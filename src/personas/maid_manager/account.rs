@@ -15,9 +15,10 @@
 // Please review the Licences for the specific language governing permissions and limitations
 // relating to use of the SAFE Network Software.
 
-use routing::{AccountInfo, MessageId};
+use routing::{AccountInfo, ClientError, MessageId};
 use rust_sodium::crypto::sign;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::mem;
 
 /// Default available number of operations per account.
 #[cfg(not(feature = "use-mock-crust"))]
@@ -25,15 +26,186 @@ pub const DEFAULT_MAX_OPS_COUNT: u64 = 1000;
 /// Default available number of mutations per account.
 #[cfg(feature = "use-mock-crust")]
 pub const DEFAULT_MAX_OPS_COUNT: u64 = 100;
+/// Default number of bytes of data each account may store.
+pub const DEFAULT_MAX_BYTES: u64 = 100 * 1024 * 1024;
+/// The number of most recent data operations whose message ids are kept per account, to detect
+/// reused ids.
+pub const DATA_OPS_WINDOW: usize = 100;
+/// The number of seconds an app authentication key or an invitation is still accepted after it
+/// expires, to allow for the clocks of the client managers to differ.
+pub const KEY_EXPIRY_MARGIN_SECS: u64 = 60;
 
+/// A permission the admin can grant to a client account.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum AdminRole {
+    /// May put invitations to create accounts, and mutate them.
+    InviteIssuer,
+    /// May assign tiers to accounts.
+    AccountTierManager,
+    /// May suspend, resume and delete accounts.
+    AccountSuspender,
+}
+
+impl AdminRole {
+    /// All the roles.
+    pub fn all() -> &'static [AdminRole] {
+        &[
+            AdminRole::InviteIssuer,
+            AdminRole::AccountTierManager,
+            AdminRole::AccountSuspender,
+        ]
+    }
+
+    /// The name of the role, as used in the keys of `TYPE_TAG_ADMIN_ROLES` data.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            AdminRole::InviteIssuer => "invite-issuer",
+            AdminRole::AccountTierManager => "account-tier-manager",
+            AdminRole::AccountSuspender => "account-suspender",
+        }
+    }
+
+    /// Returns the role with the given name.
+    pub fn from_name(name: &[u8]) -> Option<AdminRole> {
+        AdminRole::all().iter().cloned().find(
+            |role| role.name().as_bytes() == name,
+        )
+    }
+}
+
+/// An operation admins with the `AccountSuspender` role can perform on an account.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum AccountOp {
+    /// Rejects all mutations of the account until it's resumed.
+    Suspend,
+    /// Allows mutations of a suspended account again.
+    Resume,
+    /// Removes the account.  The data it stored is kept.
+    Delete,
+}
+
+impl AccountOp {
+    /// The name of the operation, as used in `TYPE_TAG_ACCOUNT_OP` data.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            AccountOp::Suspend => "suspend",
+            AccountOp::Resume => "resume",
+            AccountOp::Delete => "delete",
+        }
+    }
+
+    /// Returns the operation with the given name.
+    pub fn from_name(name: &[u8]) -> Option<AccountOp> {
+        [AccountOp::Suspend, AccountOp::Resume, AccountOp::Delete]
+            .iter()
+            .cloned()
+            .find(|op| op.name().as_bytes() == name)
+    }
+}
+
+/// A type of data operation an app authentication key can be allowed to perform.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum DataOpType {
+    /// Putting immutable data.
+    PutIData,
+    /// Putting mutable data.
+    PutMData,
+    /// Mutating the entries of mutable data.
+    MutateMDataEntries,
+    /// Changing the permissions or owners of mutable data.
+    ManagePermissions,
+}
+
+/// Restrictions of an app authentication key.  The default scope doesn't restrict the key at all,
+/// so e.g. setting only `expires_at` limits how long the key is valid for, but nothing else.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct KeyScope {
+    /// The types of data operations the key may perform, or any if `None`.
+    pub ops: Option<BTreeSet<DataOpType>>,
+    /// The type tags of the mutable data the key may put or mutate, or any if `None`.
+    pub type_tags: Option<BTreeSet<u64>>,
+    /// Number of data operations the key may perform, or as many as the account may if `None`.
+    pub max_ops_count: Option<u64>,
+    /// Time after which the key is no longer authorised, in seconds since the Unix epoch, or never
+    /// if `None`.  The client managers accept the key for `KEY_EXPIRY_MARGIN_SECS` longer, and
+    /// then remove it from the account once they agree on the time, see
+    /// `Account::keys_expired_by`.
+    pub expires_at: Option<u64>,
+}
+
+impl KeyScope {
+    /// Returns whether the scope allows the operation on data with the given type tag, if any.
+    pub fn allows(&self, op: DataOpType, tag: Option<u64>) -> bool {
+        if self.ops.as_ref().map_or(false, |ops| !ops.contains(&op)) {
+            return false;
+        }
+        match (tag, self.type_tags.as_ref()) {
+            (Some(tag), Some(type_tags)) => type_tags.contains(&tag),
+            _ => true,
+        }
+    }
+
+    /// Returns whether the key has expired by the given time, in seconds since the Unix epoch,
+    /// allowing for `KEY_EXPIRY_MARGIN_SECS` of clock difference.
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.map_or(false, |expires_at| {
+            now > expires_at.saturating_add(KEY_EXPIRY_MARGIN_SECS)
+        })
+    }
+}
+
+/// The limits of an account.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Number of operations the account may perform.
+    pub max_ops_count: u64,
+    /// Number of bytes of data the account may store.
+    pub max_bytes: u64,
+}
+
+/// A client account, as managed by its client managers.
+///
+/// Data operations are deduplicated by message id, so a group refresh or a churn update repeating
+/// one isn't counted twice.  Only the ids of the last `DATA_OPS_WINDOW` operations are kept
+/// though, to bound the size of the account and of its churn refreshes: an operation replayed with
+/// an older message id is accepted and charged again.  Clients never reuse message ids, so this
+/// only affects requests resent long after the original, which are charged to the account twice in
+/// exchange for the bounded size.
 #[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
 pub struct Account {
-    /// Message ids of data operations performed by this account.
-    pub data_ops_msg_ids: BTreeSet<MessageId>,
+    /// Number of data operations performed by this account.
+    pub data_ops_count: u64,
+    /// Number of bytes of data stored by this account.  Data is charged its serialised size:
+    /// immutable and mutable data as a whole when put, and `MutableData` entries individually as
+    /// they're mutated, with updates charged the change in size and deletions refunded.
+    pub bytes_stored: u64,
+    /// Message ids of the most recent data operations performed by this account, oldest first.
+    /// At most `DATA_OPS_WINDOW` are kept.
+    pub recent_data_ops: VecDeque<MessageId>,
     /// Number of keys operations performed by this account.
     pub keys_ops_count: u64,
     /// App authentication keys.
     pub keys: BTreeSet<sign::PublicKey>,
+    /// Restrictions of the app authentication keys.  Keys without any are unrestricted.
+    pub key_scopes: BTreeMap<sign::PublicKey, KeyScope>,
+    /// Number of data operations performed by each app authentication key with a scope.
+    pub key_ops_counts: BTreeMap<sign::PublicKey, u64>,
+    /// Time by which the client managers agreed to remove the expired app authentication keys, in
+    /// seconds since the Unix epoch.  Keys which expired by then are removed whenever the keys are
+    /// replaced, so a keys operation agreed on before doesn't bring them back.
+    pub keys_expired_by: u64,
+    /// Name of the tier assigned to this account by the admin, if any.
+    pub tier: Option<String>,
+    /// Number of tier assignments, to order them.
+    pub tier_version: u64,
+    /// Admin roles granted to this account.
+    pub admin_roles: BTreeSet<AdminRole>,
+    /// Number of admin role assignments, to order them.
+    pub admin_roles_version: u64,
+    /// Whether the account has been suspended by an admin.
+    pub suspended: bool,
+    /// Number of suspensions and resumptions, to order them.
+    pub suspension_version: u64,
     /// Dev option to allow clients to make unlimited mutation requests.
     disable_mutation_limit: bool,
 }
@@ -41,20 +213,177 @@ pub struct Account {
 impl Account {
     pub fn new(disable_mutation_limit: bool) -> Self {
         Account {
-            data_ops_msg_ids: BTreeSet::new(),
+            data_ops_count: 0,
+            bytes_stored: 0,
+            recent_data_ops: VecDeque::new(),
             keys_ops_count: 0,
             keys: BTreeSet::new(),
+            key_scopes: BTreeMap::new(),
+            key_ops_counts: BTreeMap::new(),
+            keys_expired_by: 0,
+            tier: None,
+            tier_version: 0,
+            admin_roles: BTreeSet::new(),
+            admin_roles_version: 0,
+            suspended: false,
+            suspension_version: 0,
             disable_mutation_limit,
         }
     }
 
-    // TODO: Change the `AccountInfo` struct in routing.
-    pub fn balance(&self) -> AccountInfo {
-        let done = self.data_ops_msg_ids.len() as u64 + self.keys_ops_count;
+    /// Counts the data operation with the given message id, which stored `bytes` bytes of data
+    /// and was performed by `app_key`, if not by the owner, unless it's one of the most recent ones
+    /// already.  Returns whether it was counted.
+    pub fn insert_data_op(
+        &mut self,
+        msg_id: MessageId,
+        bytes: u64,
+        app_key: Option<&sign::PublicKey>,
+    ) -> bool {
+        if self.has_data_op(&msg_id) {
+            return false;
+        }
+        self.data_ops_count += 1;
+        self.bytes_stored = self.bytes_stored.saturating_add(bytes);
+        if let Some(app_key) = app_key {
+            if self.key_scopes.contains_key(app_key) {
+                *self.key_ops_counts.entry(*app_key).or_insert(0) += 1;
+            }
+        }
+        self.remember_data_op(msg_id);
+        true
+    }
+
+    /// Returns whether the app authentication key may perform the operation on data with the
+    /// given type tag, if any.
+    pub fn key_allows(&self, app_key: &sign::PublicKey, op: DataOpType, tag: Option<u64>) -> bool {
+        let scope = match self.key_scopes.get(app_key) {
+            Some(scope) => scope,
+            None => return true,
+        };
+        let ops_count = self.key_ops_counts.get(app_key).cloned().unwrap_or(0);
+        scope.allows(op, tag) && scope.max_ops_count.map_or(true, |max| ops_count < max)
+    }
+
+    /// Returns whether the app authentication key has expired by the given time, in seconds since
+    /// the Unix epoch.
+    pub fn key_expired(&self, app_key: &sign::PublicKey, now: u64) -> bool {
+        self.key_scopes.get(app_key).map_or(false, |scope| scope.is_expired(now))
+    }
+
+    /// Returns whether any of the app authentication keys has expired by the given time, in seconds
+    /// since the Unix epoch.  They're not removed here, as the client managers have to agree on the
+    /// time first, see `remove_expired_keys`.
+    pub fn has_expired_keys(&self, now: u64) -> bool {
+        self.key_scopes.values().any(|scope| scope.is_expired(now))
+    }
+
+    /// Removes the app authentication keys which have expired by the time the client managers
+    /// agreed on, unless they already removed them by a later time.  Unlike inserting or deleting
+    /// a key, this isn't a keys operation: it doesn't change the version of the keys, nor count
+    /// against the balance.  Returns whether the time was later.
+    pub fn remove_expired_keys(&mut self, expired_by: u64) -> bool {
+        if expired_by <= self.keys_expired_by {
+            return false;
+        }
+        self.keys_expired_by = expired_by;
+        self.prune_expired_keys();
+        true
+    }
+
+    /// Replaces the app authentication keys and their scopes with the ones after the given number
+    /// of keys operations.  The data operation counts of the keys left without a scope are dropped.
+    pub fn update_keys(
+        &mut self,
+        ops_count: u64,
+        keys: BTreeSet<sign::PublicKey>,
+        scopes: BTreeMap<sign::PublicKey, KeyScope>,
+    ) {
+        let key_ops_counts = mem::replace(&mut self.key_ops_counts, BTreeMap::new());
+        self.key_ops_counts = key_ops_counts
+            .into_iter()
+            .filter(|&(ref key, _)| scopes.contains_key(key))
+            .collect();
+        self.keys = keys;
+        self.keys_ops_count = ops_count;
+        self.key_scopes = scopes;
+        self.prune_expired_keys();
+    }
+
+    /// Replaces the scope of the app authentication key, or lifts its restrictions if `None`.
+    /// Returns `NoSuchKey` if the account doesn't have the key.
+    pub fn set_key_scope(
+        &mut self,
+        app_key: sign::PublicKey,
+        scope: Option<KeyScope>,
+    ) -> Result<(), ClientError> {
+        if !self.keys.contains(&app_key) {
+            return Err(ClientError::NoSuchKey);
+        }
+        match scope {
+            Some(scope) => {
+                let _ = self.key_scopes.insert(app_key, scope);
+            }
+            None => {
+                let _ = self.key_scopes.remove(&app_key);
+                let _ = self.key_ops_counts.remove(&app_key);
+            }
+        }
+        Ok(())
+    }
+
+    // Removes the keys which have expired by `keys_expired_by`, along with their scopes and data
+    // operation counts.
+    fn prune_expired_keys(&mut self) {
+        let expired_by = self.keys_expired_by;
+        let expired: Vec<_> = self.key_scopes
+            .iter()
+            .filter(|&(_, scope)| scope.is_expired(expired_by))
+            .map(|(key, _)| *key)
+            .collect();
+        for key in expired {
+            let _ = self.keys.remove(&key);
+            let _ = self.key_scopes.remove(&key);
+            let _ = self.key_ops_counts.remove(&key);
+        }
+    }
+
+    /// Adds the message id to the most recent data operations without counting it, e.g. if it's
+    /// already included in the count.
+    pub fn remember_data_op(&mut self, msg_id: MessageId) {
+        if self.has_data_op(&msg_id) {
+            return;
+        }
+        self.recent_data_ops.push_back(msg_id);
+        if self.recent_data_ops.len() > DATA_OPS_WINDOW {
+            let _ = self.recent_data_ops.pop_front();
+        }
+    }
+
+    /// Adds `change` to the bytes stored, which is negative when entries are deleted or shrunk.
+    pub fn change_bytes_stored(&mut self, change: i64) {
+        self.bytes_stored = if change < 0 {
+            self.bytes_stored.saturating_sub(change.wrapping_neg() as u64)
+        } else {
+            self.bytes_stored.saturating_add(change as u64)
+        };
+    }
+
+    /// Returns whether the message id is one of the most recent data operations.
+    pub fn has_data_op(&self, msg_id: &MessageId) -> bool {
+        self.recent_data_ops.contains(msg_id)
+    }
+
+    /// Returns the mutations done and available, as reported to the client.
+    ///
+    /// The bytes stored can't be reported: routing's `AccountInfo` only has the two mutation
+    /// counts.  Clients learn of the byte limit from mutations failing with `STORAGE_LIMIT_ERROR`.
+    pub fn balance(&self, limits: Limits) -> AccountInfo {
+        let done = self.data_ops_count + self.keys_ops_count;
         let available = if self.disable_mutation_limit {
             u64::max_value()
         } else {
-            DEFAULT_MAX_OPS_COUNT.saturating_sub(done)
+            limits.max_ops_count.saturating_sub(done)
         };
 
         AccountInfo {
@@ -63,32 +392,187 @@ impl Account {
         }
     }
 
-    pub fn has_balance(&self) -> bool {
+    pub fn has_balance(&self, limits: Limits) -> bool {
         self.disable_mutation_limit ||
-            self.data_ops_msg_ids.len() as u64 + self.keys_ops_count < DEFAULT_MAX_OPS_COUNT
+            self.data_ops_count + self.keys_ops_count < limits.max_ops_count
+    }
+
+    /// Returns whether the account may store another `bytes` bytes of data.
+    pub fn has_space(&self, bytes: u64, limits: Limits) -> bool {
+        self.disable_mutation_limit || self.bytes_stored.saturating_add(bytes) <= limits.max_bytes
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Account, DEFAULT_MAX_OPS_COUNT};
-    use routing::MessageId;
+    use super::{Account, DATA_OPS_WINDOW, DEFAULT_MAX_BYTES, DEFAULT_MAX_OPS_COUNT, DataOpType,
+                KEY_EXPIRY_MARGIN_SECS, KeyScope, Limits};
+    use routing::{ClientError, MessageId};
+    use rust_sodium::crypto::sign;
+
+    const LIMITS: Limits = Limits {
+        max_ops_count: DEFAULT_MAX_OPS_COUNT,
+        max_bytes: DEFAULT_MAX_BYTES,
+    };
 
     #[test]
     fn balance() {
         let mut account = Account::new(false);
-        assert!(account.has_balance());
+        assert!(account.has_balance(LIMITS));
 
         account.keys_ops_count = DEFAULT_MAX_OPS_COUNT - 1;
-        assert!(account.has_balance());
+        assert!(account.has_balance(LIMITS));
 
-        let _ = account.data_ops_msg_ids.insert(MessageId::zero());
-        assert!(!account.has_balance());
+        assert!(account.insert_data_op(MessageId::zero(), 0, None));
+        assert!(!account.has_balance(LIMITS));
 
         let mut unlimited_account = Account::new(true);
-        assert!(unlimited_account.has_balance());
+        assert!(unlimited_account.has_balance(LIMITS));
 
         unlimited_account.keys_ops_count = DEFAULT_MAX_OPS_COUNT;
-        assert!(unlimited_account.has_balance());
+        assert!(unlimited_account.has_balance(LIMITS));
+
+        // A higher limit leaves more balance.
+        let limits = Limits {
+            max_ops_count: DEFAULT_MAX_OPS_COUNT + 10,
+            ..LIMITS
+        };
+        assert!(account.has_balance(limits));
+        assert_eq!(account.balance(limits).mutations_available, 10);
+    }
+
+    #[test]
+    fn bytes() {
+        let mut account = Account::new(false);
+        assert!(account.has_space(DEFAULT_MAX_BYTES, LIMITS));
+        assert!(!account.has_space(DEFAULT_MAX_BYTES + 1, LIMITS));
+
+        assert!(account.insert_data_op(MessageId::new(), DEFAULT_MAX_BYTES - 1, None));
+        assert_eq!(account.bytes_stored, DEFAULT_MAX_BYTES - 1);
+        assert!(account.has_space(1, LIMITS));
+        assert!(!account.has_space(2, LIMITS));
+        assert!(account.balance(LIMITS).mutations_available > 0);
+
+        // Running out of bytes doesn't affect the mutations.
+        assert!(account.insert_data_op(MessageId::new(), 1, None));
+        assert!(!account.has_space(1, LIMITS));
+        assert!(account.has_balance(LIMITS));
+        assert!(account.balance(LIMITS).mutations_available > 0);
+
+        // Deleted and shrunk entries are refunded.
+        account.change_bytes_stored(-10);
+        assert_eq!(account.bytes_stored, DEFAULT_MAX_BYTES - 10);
+        assert!(account.has_space(10, LIMITS));
+        account.change_bytes_stored(4);
+        assert_eq!(account.bytes_stored, DEFAULT_MAX_BYTES - 6);
+        account.change_bytes_stored(-i64::max_value());
+        assert_eq!(account.bytes_stored, 0);
+    }
+
+    #[test]
+    fn key_scopes() {
+        let mut account = Account::new(false);
+        let (app_key, _) = sign::gen_keypair();
+        assert!(account.key_allows(&app_key, DataOpType::ManagePermissions, Some(1)));
+
+        let scope = KeyScope {
+            ops: Some(
+                vec![DataOpType::PutIData, DataOpType::MutateMDataEntries]
+                    .into_iter()
+                    .collect(),
+            ),
+            type_tags: Some(vec![1].into_iter().collect()),
+            max_ops_count: Some(1),
+            expires_at: None,
+        };
+        // Only the account's own keys can be restricted.
+        assert_eq!(
+            account.set_key_scope(app_key, Some(scope.clone())),
+            Err(ClientError::NoSuchKey)
+        );
+        let _ = account.keys.insert(app_key);
+        unwrap!(account.set_key_scope(app_key, Some(scope)));
+        assert!(account.key_allows(&app_key, DataOpType::PutIData, None));
+        assert!(account.key_allows(&app_key, DataOpType::MutateMDataEntries, Some(1)));
+        assert!(!account.key_allows(&app_key, DataOpType::MutateMDataEntries, Some(2)));
+        assert!(!account.key_allows(&app_key, DataOpType::ManagePermissions, Some(1)));
+
+        // The owner's operations don't count against the key's budget, but the key's own do.
+        assert!(account.insert_data_op(MessageId::new(), 0, None));
+        assert!(account.key_allows(&app_key, DataOpType::PutIData, None));
+        assert!(account.insert_data_op(MessageId::new(), 0, Some(&app_key)));
+        assert!(!account.key_allows(&app_key, DataOpType::PutIData, None));
+
+        unwrap!(account.set_key_scope(app_key, None));
+        assert!(account.key_allows(&app_key, DataOpType::ManagePermissions, Some(2)));
+        assert!(account.key_ops_counts.is_empty());
+
+        // A scope limiting only the lifetime of the key allows any operation.
+        let scope = KeyScope {
+            expires_at: Some(10),
+            ..Default::default()
+        };
+        unwrap!(account.set_key_scope(app_key, Some(scope)));
+        assert!(account.key_allows(&app_key, DataOpType::ManagePermissions, Some(2)));
+    }
+
+    #[test]
+    fn key_expiry() {
+        let mut account = Account::new(false);
+        let (app_key_0, _) = sign::gen_keypair();
+        let (app_key_1, _) = sign::gen_keypair();
+        for (app_key, expires_at) in vec![(app_key_0, 10), (app_key_1, 20)] {
+            let _ = account.keys.insert(app_key);
+            let scope = KeyScope {
+                expires_at: Some(expires_at),
+                ..Default::default()
+            };
+            unwrap!(account.set_key_scope(app_key, Some(scope)));
+        }
+
+        // The keys are accepted for a while after they expire.
+        let now = 10 + KEY_EXPIRY_MARGIN_SECS;
+        assert!(!account.key_expired(&app_key_0, now));
+        assert!(account.key_expired(&app_key_0, now + 1));
+        assert!(!account.has_expired_keys(now));
+        assert!(account.has_expired_keys(now + 1));
+
+        // Removing them is up to the client managers, and isn't a keys operation.
+        let (keys, scopes) = (account.keys.clone(), account.key_scopes.clone());
+        assert!(account.keys.contains(&app_key_0));
+        assert!(account.remove_expired_keys(now + 1));
+        assert!(!account.keys.contains(&app_key_0));
+        assert!(!account.key_scopes.contains_key(&app_key_0));
+        assert!(account.keys.contains(&app_key_1));
+        assert_eq!(account.keys_ops_count, 0);
+        assert!(!account.has_expired_keys(now + 1));
+        assert!(!account.remove_expired_keys(now));
+
+        // A keys operation agreed on before the removal doesn't bring them back.
+        account.update_keys(1, keys, scopes);
+        assert!(!account.keys.contains(&app_key_0));
+        assert!(account.keys.contains(&app_key_1));
+        assert_eq!(account.keys_ops_count, 1);
+    }
+
+    #[test]
+    fn data_ops_window() {
+        let mut account = Account::new(false);
+        let msg_ids: Vec<_> = (0..DATA_OPS_WINDOW + 1).map(|_| MessageId::new()).collect();
+        for msg_id in &msg_ids {
+            assert!(account.insert_data_op(*msg_id, 1, None));
+            assert!(!account.insert_data_op(*msg_id, 1, None));
+        }
+        assert_eq!(account.data_ops_count, DATA_OPS_WINDOW as u64 + 1);
+        assert_eq!(account.bytes_stored, DATA_OPS_WINDOW as u64 + 1);
+        assert_eq!(account.recent_data_ops.len(), DATA_OPS_WINDOW);
+
+        // The oldest message id dropped out of the window.
+        assert!(!account.has_data_op(&msg_ids[0]));
+        assert!(account.has_data_op(&msg_ids[DATA_OPS_WINDOW]));
+
+        account.remember_data_op(msg_ids[0]);
+        assert_eq!(account.data_ops_count, DATA_OPS_WINDOW as u64 + 1);
+        assert!(account.has_data_op(&msg_ids[0]));
     }
 }
@@ -0,0 +1,433 @@
+// Copyright 2017 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Persistent database of the accounts managed by a `MaidManager`.
+//!
+//! Every change to the accounts is appended to a write-ahead log, which is synced once after each
+//! event (group commit), before a later request can observe the change.  Data operations are
+//! logged as deltas rather than as whole accounts.  Once the log holds `CHECKPOINT_INTERVAL`
+//! records, all the accounts are written to a checkpoint file, and the log is cleared.  Reopening
+//! the database loads the checkpoint and replays the log on top of it.
+//!
+//! Besides the accounts, the database holds the tombstones of the accounts deleted by an admin, so
+//! they aren't recreated by refreshes still in flight, even after a restart.
+//!
+//! Both files consist of records, each preceded by its length and checksum, so a record only
+//! partially written before a crash is detected and discarded.
+
+use super::{Account, KeyScope};
+use chunk_store::sync_dir;
+use error::InternalError;
+use maidsafe_utilities::serialisation;
+use routing::{MessageId, XorName};
+use rust_sodium::crypto::sign;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use tiny_keccak;
+use utils::HashMap;
+
+/// The number of records in the log after which a checkpoint is written.
+const CHECKPOINT_INTERVAL: u64 = 1000;
+const CHECKPOINT_FILE: &str = "accounts";
+const LOG_FILE: &str = "accounts.log";
+const TEMP_SUFFIX: &str = ".tmp";
+/// The length of the length and checksum preceding each record.
+const RECORD_HEADER_LEN: usize = 4 + 32;
+
+/// A change to the accounts.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Record {
+    /// The account is created or replaced.
+    Put(XorName, Account),
+    /// The account is removed.
+    Delete(XorName),
+    /// The account is deleted by an admin, and must not be recreated.
+    Tombstone(XorName),
+    /// A data operation storing the given number of bytes, performed by the given app key if not by
+    /// the owner, is recorded in the account.
+    InsertDataOp(XorName, MessageId, u64, Option<sign::PublicKey>),
+    /// The bytes stored by the account change by the given amount, after its entries are mutated.
+    ChangeBytesStored(XorName, i64),
+    /// The data operations accumulated from the other client managers on churn are recorded:
+    /// the counts which increased, and the newly remembered message ids.
+    UpdateDataOps {
+        name: XorName,
+        count: Option<u64>,
+        bytes: Option<u64>,
+        key_ops_counts: BTreeMap<sign::PublicKey, u64>,
+        msg_ids: Vec<MessageId>,
+    },
+    /// The app authentication keys of the account and their scopes are replaced, and the keys
+    /// which expired by the given time removed.
+    UpdateKeys {
+        name: XorName,
+        ops_count: u64,
+        keys: BTreeSet<sign::PublicKey>,
+        scopes: BTreeMap<sign::PublicKey, KeyScope>,
+        expired_by: u64,
+    },
+}
+
+impl Record {
+    fn apply(self, accounts: &mut HashMap<XorName, Account>, deleted: &mut BTreeSet<XorName>) {
+        match self {
+            Record::Put(name, account) => {
+                let _ = accounts.insert(name, account);
+            }
+            Record::Delete(name) => {
+                let _ = accounts.remove(&name);
+            }
+            Record::Tombstone(name) => {
+                let _ = accounts.remove(&name);
+                let _ = deleted.insert(name);
+            }
+            Record::InsertDataOp(name, msg_id, bytes, app_key) => {
+                if let Some(account) = accounts.get_mut(&name) {
+                    let _ = account.insert_data_op(msg_id, bytes, app_key.as_ref());
+                }
+            }
+            Record::ChangeBytesStored(name, change) => {
+                if let Some(account) = accounts.get_mut(&name) {
+                    account.change_bytes_stored(change);
+                }
+            }
+            Record::UpdateDataOps {
+                name,
+                count,
+                bytes,
+                key_ops_counts,
+                msg_ids,
+            } => {
+                if let Some(account) = accounts.get_mut(&name) {
+                    if let Some(count) = count {
+                        account.data_ops_count = count;
+                    }
+                    if let Some(bytes) = bytes {
+                        account.bytes_stored = bytes;
+                    }
+                    account.key_ops_counts.extend(key_ops_counts);
+                    for msg_id in msg_ids {
+                        account.remember_data_op(msg_id);
+                    }
+                }
+            }
+            Record::UpdateKeys {
+                name,
+                ops_count,
+                keys,
+                scopes,
+                expired_by,
+            } => {
+                if let Some(account) = accounts.get_mut(&name) {
+                    account.update_keys(ops_count, keys, scopes);
+                    let _ = account.remove_expired_keys(expired_by);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct Checkpoint {
+    /// The sequence number of the last record included.
+    seq: u64,
+    accounts: Vec<(XorName, Account)>,
+    deleted: Vec<XorName>,
+}
+
+pub struct AccountDb {
+    dir: PathBuf,
+    log: File,
+    /// The sequence number of the last record written.
+    seq: u64,
+    /// The number of records in the log.
+    log_len: u64,
+    /// The length of the log file up to the end of its last complete record.
+    valid_len: u64,
+    /// Whether a record only partially appended couldn't be removed from the log again, so no
+    /// further records can be appended until a checkpoint clears the log.
+    failed: bool,
+    /// Whether records were appended since the log was last synced.
+    unsynced: bool,
+}
+
+impl AccountDb {
+    /// Opens the database in `dir`, creating it if it doesn't exist yet.  Returns the database
+    /// along with the accounts and the tombstones stored in it.
+    pub fn open(
+        dir: &Path,
+    ) -> Result<(Self, HashMap<XorName, Account>, BTreeSet<XorName>), InternalError> {
+        fs::create_dir_all(dir)?;
+        let (mut seq, mut accounts, mut deleted) =
+            match read_records(&dir.join(CHECKPOINT_FILE))?.0.into_iter().next() {
+                Some(contents) => {
+                    let checkpoint: Checkpoint = serialisation::deserialise(&contents)?;
+                    (
+                        checkpoint.seq,
+                        checkpoint.accounts.into_iter().collect(),
+                        checkpoint.deleted.into_iter().collect(),
+                    )
+                }
+                None => (0, HashMap::default(), BTreeSet::new()),
+            };
+
+        // Records from before the checkpoint are left over if we crashed before clearing the log.
+        let log_path = dir.join(LOG_FILE);
+        let (records, valid_len) = read_records(&log_path)?;
+        let log_len = records.len() as u64;
+        for contents in records {
+            let (record_seq, record): (u64, Record) = serialisation::deserialise(&contents)?;
+            if record_seq > seq {
+                record.apply(&mut accounts, &mut deleted);
+                seq = record_seq;
+            }
+        }
+
+        let log = OpenOptions::new().create(true).append(true).open(
+            &log_path,
+        )?;
+        if log.metadata()?.len() > valid_len {
+            warn!("Discarding the partially written last record of the account log.");
+            log.set_len(valid_len)?;
+            log.sync_all()?;
+        }
+
+        let db = AccountDb {
+            dir: dir.to_path_buf(),
+            log,
+            seq,
+            log_len,
+            valid_len,
+            failed: false,
+            unsynced: false,
+        };
+        Ok((db, accounts, deleted))
+    }
+
+    /// Appends the change to the log, and writes a checkpoint of `accounts` and `deleted` if due.
+    /// They must already include the change.  The change is only durable once `sync` is called, or
+    /// a checkpoint is written.
+    ///
+    /// If appending fails, whatever part of the record was written is removed from the log again.
+    /// If even that fails, further changes are recorded by writing checkpoints instead, until one
+    /// succeeds in clearing the log.
+    pub fn append(
+        &mut self,
+        record: &Record,
+        accounts: &HashMap<XorName, Account>,
+        deleted: &BTreeSet<XorName>,
+    ) -> Result<(), InternalError> {
+        if self.failed {
+            // Only a checkpoint, which clears the log, can record the change now.
+            self.seq += 1;
+            return self.checkpoint(accounts, deleted);
+        }
+
+        let contents = serialisation::serialise(&(self.seq + 1, record))?;
+        let encoded = encode_record(&contents);
+        if let Err(error) = self.log.write_all(&encoded) {
+            // Records appended after a partially written one would be lost on reopening.
+            if let Err(truncate_error) = self.log.set_len(self.valid_len) {
+                error!("Failed to truncate the account log: {}", truncate_error);
+                self.failed = true;
+            }
+            return Err(From::from(error));
+        }
+        self.valid_len += encoded.len() as u64;
+        self.unsynced = true;
+        self.seq += 1;
+        self.log_len += 1;
+
+        if self.log_len >= CHECKPOINT_INTERVAL {
+            self.checkpoint(accounts, deleted)?;
+        }
+        Ok(())
+    }
+
+    /// Syncs the records appended to the log since the last sync, if any.
+    pub fn sync(&mut self) -> Result<(), InternalError> {
+        if self.unsynced {
+            self.log.sync_data()?;
+            self.unsynced = false;
+        }
+        Ok(())
+    }
+
+    /// Writes all the `accounts` and the tombstones in `deleted` to the checkpoint file, and clears
+    /// the log.
+    pub fn checkpoint(
+        &mut self,
+        accounts: &HashMap<XorName, Account>,
+        deleted: &BTreeSet<XorName>,
+    ) -> Result<(), InternalError> {
+        let checkpoint = Checkpoint {
+            seq: self.seq,
+            accounts: accounts
+                .iter()
+                .map(|(name, account)| (*name, account.clone()))
+                .collect(),
+            deleted: deleted.iter().cloned().collect(),
+        };
+        let contents = serialisation::serialise(&checkpoint)?;
+
+        let path = self.dir.join(CHECKPOINT_FILE);
+        let temp_path = self.dir.join(format!("{}{}", CHECKPOINT_FILE, TEMP_SUFFIX));
+        {
+            let mut file = File::create(&temp_path)?;
+            file.write_all(&encode_record(&contents))?;
+            file.sync_all()?;
+        }
+        fs::rename(&temp_path, &path)?;
+        sync_dir(&self.dir)?;
+
+        self.log.set_len(0)?;
+        self.log.sync_all()?;
+        self.log_len = 0;
+        self.valid_len = 0;
+        self.failed = false;
+        self.unsynced = false;
+        Ok(())
+    }
+}
+
+fn encode_record(contents: &[u8]) -> Vec<u8> {
+    let len = contents.len() as u32;
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + contents.len());
+    record.extend_from_slice(&[len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8]);
+    record.extend_from_slice(&tiny_keccak::sha3_256(contents));
+    record.extend_from_slice(contents);
+    record
+}
+
+// Reads the records in the file at `path`, stopping at the first incomplete or corrupted one.
+// Returns the records and the length of the file up to that point.
+fn read_records(path: &Path) -> Result<(Vec<Vec<u8>>, u64), InternalError> {
+    let mut file_contents = Vec::new();
+    match File::open(path) {
+        Ok(mut file) => {
+            let _ = file.read_to_end(&mut file_contents)?;
+        }
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => (),
+        Err(error) => return Err(From::from(error)),
+    }
+
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while file_contents.len() - offset >= RECORD_HEADER_LEN {
+        let header = &file_contents[offset..offset + RECORD_HEADER_LEN];
+        let len = header[..4].iter().rev().fold(
+            0,
+            |len, byte| (len << 8) | *byte as usize,
+        );
+        let start = offset + RECORD_HEADER_LEN;
+        if file_contents.len() - start < len {
+            break;
+        }
+        let contents = &file_contents[start..start + len];
+        if tiny_keccak::sha3_256(contents)[..] != header[4..] {
+            break;
+        }
+        records.push(contents.to_vec());
+        offset = start + len;
+    }
+
+    Ok((records, offset as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand;
+    use tempdir::TempDir;
+
+    #[test]
+    fn reopen() {
+        let temp_dir = unwrap!(TempDir::new("safe_vault_test"));
+        let name0: XorName = rand::random();
+        let name1: XorName = rand::random();
+        let name2: XorName = rand::random();
+        let msg_id = MessageId::new();
+        let mut expected = HashMap::default();
+        let mut expected_deleted = BTreeSet::new();
+
+        {
+            let (mut db, accounts, deleted) = unwrap!(AccountDb::open(temp_dir.path()));
+            assert!(accounts.is_empty());
+            assert!(deleted.is_empty());
+
+            let records = vec![
+                Record::Put(name0, Account::new(false)),
+                Record::Put(name1, Account::new(false)),
+                Record::Put(name2, Account::new(false)),
+                Record::InsertDataOp(name0, msg_id, 10, None),
+                Record::ChangeBytesStored(name0, -3),
+                Record::UpdateDataOps {
+                    name: name0,
+                    count: Some(3),
+                    bytes: None,
+                    key_ops_counts: BTreeMap::new(),
+                    msg_ids: vec![MessageId::new()],
+                },
+                Record::UpdateKeys {
+                    name: name0,
+                    ops_count: 1,
+                    keys: BTreeSet::new(),
+                    scopes: BTreeMap::new(),
+                    expired_by: 0,
+                },
+                Record::Delete(name1),
+                Record::Tombstone(name2),
+            ];
+            for record in records {
+                record.clone().apply(&mut expected, &mut expected_deleted);
+                unwrap!(db.append(&record, &expected, &expected_deleted));
+            }
+            unwrap!(db.sync());
+        }
+
+        let (mut db, accounts, deleted) = unwrap!(AccountDb::open(temp_dir.path()));
+        assert_eq!(accounts, expected);
+        assert_eq!(deleted, expected_deleted);
+        assert!(!accounts.contains_key(&name2));
+        assert!(deleted.contains(&name2));
+        assert_eq!(accounts[&name0].keys_ops_count, 1);
+        assert!(accounts[&name0].has_data_op(&msg_id));
+        assert_eq!(accounts[&name0].data_ops_count, 3);
+        assert_eq!(accounts[&name0].recent_data_ops.len(), 2);
+        assert_eq!(accounts[&name0].bytes_stored, 7);
+
+        // Records appended after a checkpoint are replayed on top of it, and a partially written
+        // record is discarded.
+        unwrap!(db.checkpoint(&expected, &expected_deleted));
+        let record = Record::InsertDataOp(name0, MessageId::new(), 0, None);
+        record.clone().apply(&mut expected, &mut expected_deleted);
+        unwrap!(db.append(&record, &expected, &expected_deleted));
+        unwrap!(db.sync());
+        drop(db);
+
+        let log_path = temp_dir.path().join(LOG_FILE);
+        let mut log = unwrap!(OpenOptions::new().append(true).open(&log_path));
+        unwrap!(log.write_all(&encode_record(&[1, 2, 3])[..10]));
+        drop(log);
+
+        let (_, accounts, deleted) = unwrap!(AccountDb::open(temp_dir.path()));
+        assert_eq!(accounts, expected);
+        assert_eq!(deleted, expected_deleted);
+    }
+}
@@ -0,0 +1,153 @@
+// Copyright 2017 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use super::KEY_EXPIRY_MARGIN_SECS;
+use hex::{FromHex, ToHex};
+use maidsafe_utilities::serialisation;
+use routing::{ClientError, XorName};
+use tiny_keccak;
+use utils;
+
+/// Prefix of the invitation strings encoding an `Invite`.
+const INVITE_PREFIX: &str = "invite:";
+/// The maximum number of claims of a single invitation.
+///
+/// The claims are counted by the version of the `claimed` entry of the invitation data.  The
+/// client managers learn that version from the `EntryError` of a failed claim, which only carries
+/// its lowest byte, so they can't count more claims than fit in a byte.
+pub const MAX_INVITE_CLAIMS: u64 = 256;
+
+/// Invitation to create accounts.
+///
+/// The invitation string is either a plain invitation code, which can be claimed once and never
+/// expires, or `invite:` followed by the hex encoded serialised `Invite`, which can expire and be
+/// claimed up to `MAX_INVITE_CLAIMS` times.  Either way, an admin with the `InviteIssuer` role puts
+/// the invitation data named after the invitation string, and can revoke it by removing the
+/// permissions of `User::Anyone` from that data.  Allowing more than one claim requires granting
+/// `User::Anyone` the `Update` permission as well as `Insert`.
+///
+/// The invitation string isn't signed: anyone can make one up, but it can only be claimed if an
+/// admin put the invitation data under its name.  As the name covers the whole string, the expiry
+/// and the number of claims can't be changed without invalidating the invitation.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Invite {
+    /// Random code making the invitation unique.
+    pub code: String,
+    /// Time after which the invitation can't be claimed, in seconds since the Unix epoch.  The
+    /// client managers accept it for `KEY_EXPIRY_MARGIN_SECS` longer, to allow for their clocks
+    /// to differ.
+    pub expires_at: Option<u64>,
+    /// Number of accounts which can be created with the invitation, up to `MAX_INVITE_CLAIMS`.
+    pub max_claims: u64,
+}
+
+impl Invite {
+    /// Returns the invitation string to hand out.
+    pub fn encode(&self) -> String {
+        let serialised = unwrap!(serialisation::serialise(self));
+        format!("{}{}", INVITE_PREFIX, serialised.to_hex())
+    }
+
+    /// Returns the name of the invitation data for the invitation string.
+    pub fn name(invitation_string: &str) -> XorName {
+        XorName(tiny_keccak::sha3_256(invitation_string.as_bytes()))
+    }
+
+    /// Parses the invitation string, which is either a plain invitation code or an encoded
+    /// `Invite`, and checks the invitation can still be claimed.
+    pub fn parse(invitation_string: &str) -> Result<Invite, ClientError> {
+        if !invitation_string.starts_with(INVITE_PREFIX) {
+            return Ok(Invite {
+                code: invitation_string.to_string(),
+                expires_at: None,
+                max_claims: 1,
+            });
+        }
+
+        let serialised = Vec::from_hex(&invitation_string[INVITE_PREFIX.len()..])
+            .map_err(|_| ClientError::InvalidInvitation)?;
+        let invite: Invite = serialisation::deserialise(&serialised).map_err(|_| {
+            ClientError::InvalidInvitation
+        })?;
+
+        if invite.max_claims == 0 || invite.max_claims > MAX_INVITE_CLAIMS {
+            return Err(ClientError::InvalidInvitation);
+        }
+        if let Some(expires_at) = invite.expires_at {
+            if utils::now_secs() > expires_at.saturating_add(KEY_EXPIRY_MARGIN_SECS) {
+                return Err(ClientError::from(
+                    format!("Invitation expired at {}", expires_at),
+                ));
+            }
+        }
+
+        Ok(invite)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Invite, KEY_EXPIRY_MARGIN_SECS, MAX_INVITE_CLAIMS};
+    use routing::ClientError;
+    use utils::now_secs;
+
+    #[test]
+    fn parse() {
+        // Plain invitation codes are single use.
+        let invite = unwrap!(Invite::parse("code"));
+        assert_eq!(invite.code, "code");
+        assert_eq!(invite.expires_at, None);
+        assert_eq!(invite.max_claims, 1);
+
+        let invite = Invite {
+            code: "code".to_string(),
+            expires_at: Some(now_secs() + 60),
+            max_claims: 10,
+        };
+        let invitation_string = invite.encode();
+        assert_eq!(unwrap!(Invite::parse(&invitation_string)), invite);
+
+        // Malformed or truncated.
+        assert_eq!(Invite::parse("invite:xyz"), Err(ClientError::InvalidInvitation));
+        assert_eq!(
+            Invite::parse(&invitation_string[..invitation_string.len() - 2]),
+            Err(ClientError::InvalidInvitation)
+        );
+
+        // Expired, allowing for the clocks to differ.
+        let just_expired = Invite {
+            expires_at: Some(now_secs() - 1),
+            ..invite.clone()
+        };
+        assert_eq!(unwrap!(Invite::parse(&just_expired.encode())), just_expired);
+        let expired = Invite {
+            expires_at: Some(now_secs() - KEY_EXPIRY_MARGIN_SECS - 1),
+            ..invite.clone()
+        };
+        match Invite::parse(&expired.encode()) {
+            Err(ClientError::NetworkOther(_)) => (),
+            res => panic!("Unexpected result {:?}", res),
+        }
+
+        // Too many claims.
+        let unlimited = Invite {
+            max_claims: MAX_INVITE_CLAIMS + 1,
+            ..invite
+        };
+        assert_eq!(Invite::parse(&unlimited.encode()), Err(ClientError::InvalidInvitation));
+    }
+}
@@ -17,7 +17,7 @@
 
 use lru_time_cache::LruCache;
 use routing::{QUORUM_DENOMINATOR, QUORUM_NUMERATOR, XorName};
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::time::Duration;
 
 pub struct MessageIdAccumulator<K> {
@@ -52,6 +52,44 @@ where
     }
 }
 
+/// Accumulates counts, e.g. of the operations performed by an account, reported by the members of
+/// a group.  Since the members may have seen different numbers of operations, the accumulated
+/// count is the highest one at least a quorum of them reports having reached.
+pub struct CountAccumulator<K> {
+    group_size: usize,
+    map: LruCache<K, BTreeMap<XorName, u64>>,
+}
+
+impl<K> CountAccumulator<K>
+where
+    K: Clone + Ord,
+{
+    pub fn new(group_size: usize, duration: Duration) -> Self {
+        CountAccumulator {
+            group_size,
+            map: LruCache::with_expiry_duration(duration),
+        }
+    }
+
+    pub fn add(&mut self, key: K, src_name: XorName, count: u64) -> Option<u64> {
+        let quorum = self.group_size * QUORUM_NUMERATOR / QUORUM_DENOMINATOR + 1;
+        let agreed_count = {
+            let counts = self.map.entry(key.clone()).or_insert_with(Default::default);
+            let _ = counts.insert(src_name, count);
+            if counts.len() < quorum {
+                return None;
+            }
+
+            let mut sorted_counts: Vec<u64> = counts.values().cloned().collect();
+            sorted_counts.sort_by(|lhs, rhs| rhs.cmp(lhs));
+            sorted_counts[quorum - 1]
+        };
+
+        let _ = self.map.remove(&key);
+        Some(agreed_count)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -73,4 +111,17 @@ mod tests {
             Some(msg_id)
         );
     }
+
+    #[test]
+    fn count() {
+        let mut accumulator = CountAccumulator::new(8, Duration::from_secs(10));
+        let duplicate_sender = XorName(rand::random());
+        assert_eq!(accumulator.add(0, XorName(rand::random()), 1000), None);
+        assert_eq!(accumulator.add(0, duplicate_sender, 5), None);
+        assert_eq!(accumulator.add(0, XorName(rand::random()), 6), None);
+        assert_eq!(accumulator.add(0, XorName(rand::random()), 7), None);
+        assert_eq!(accumulator.add(0, duplicate_sender, 7), None);
+        // Fewer than a quorum of the members report more than 6.
+        assert_eq!(accumulator.add(0, XorName(rand::random()), 6), Some(6));
+    }
 }
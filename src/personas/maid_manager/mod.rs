@@ -16,15 +16,21 @@
 // relating to use of the SAFE Network Software.
 
 mod account;
+mod account_db;
+mod invite;
 mod message_id_accumulator;
 #[cfg(all(test, feature = "use-mock-routing"))]
 mod tests;
 
-use self::account::Account;
-pub use self::account::DEFAULT_MAX_OPS_COUNT;
-use self::message_id_accumulator::MessageIdAccumulator;
-use TYPE_TAG_INVITE;
+pub use self::account::{Account, AccountOp, AdminRole, DEFAULT_MAX_BYTES, DEFAULT_MAX_OPS_COUNT,
+                        DataOpType, KEY_EXPIRY_MARGIN_SECS, KeyScope};
+use self::account::Limits;
+use self::account_db::{AccountDb, Record};
+pub use self::invite::{Invite, MAX_INVITE_CLAIMS};
+use self::message_id_accumulator::{CountAccumulator, MessageIdAccumulator};
+use {TYPE_TAG_ACCOUNT_OP, TYPE_TAG_ACCOUNT_TIER, TYPE_TAG_ADMIN_ROLES, TYPE_TAG_INVITE};
 use authority::{ClientAuthority, ClientManagerAuthority};
+use config_handler::{AccountTier, Config};
 use error::InternalError;
 use lru_time_cache::LruCache;
 use maidsafe_utilities::serialisation;
@@ -34,6 +40,7 @@ use routing::{ACC_LOGIN_ENTRY_KEY, AccountPacket, Authority, ClientError, EntryA
 use rust_sodium::crypto::sign;
 use std::collections::{BTreeMap, BTreeSet};
 use std::collections::hash_map::{Entry, VacantEntry};
+use std::path::Path;
 use std::time::Duration;
 use tiny_keccak;
 use utils::{self, HashMap};
@@ -51,39 +58,87 @@ const ACCOUNT_CREATION_LIMIT: usize = 100;
 const INVITE_CLAIMED_KEY: &[u8] = b"claimed";
 const INVITE_CLAIMED_VALUE: &[u8] = &[1];
 
+const ACCOUNT_TIER_KEY: &[u8] = b"tier";
+const ACCOUNT_OP_KEY: &[u8] = b"op";
+
+/// The message of the `ClientError::NetworkOther` mutations of suspended accounts fail with.
+pub const ACCOUNT_SUSPENDED_ERROR: &str = "Account is suspended";
+/// The message of the `ClientError::NetworkOther` mutations fail with when the account's data
+/// would exceed its byte limit.  Running out of mutations fails with `ClientError::LowBalance`.
+pub const STORAGE_LIMIT_ERROR: &str = "Account storage limit reached";
+
 pub struct MaidManager {
     group_size: usize,
     accounts: HashMap<XorName, Account>,
+    /// The tombstones of the accounts deleted by an admin, which must not be recreated.
+    deleted_accounts: BTreeSet<XorName>,
     data_ops_msg_id_accumulator: MessageIdAccumulator<(XorName, MessageId)>,
+    data_ops_count_accumulator: CountAccumulator<XorName>,
+    bytes_stored_accumulator: CountAccumulator<XorName>,
+    key_ops_count_accumulator: CountAccumulator<(XorName, sign::PublicKey)>,
     request_cache: HashMap<MessageId, CachedRequest>,
-    invite_key: Option<sign::PublicKey>,
+    /// The admin configured by `Config::invite_key`, which has all the admin roles and can grant
+    /// them to other accounts.
+    root_admin_key: Option<sign::PublicKey>,
     /// The ongoing requests from clients to create a new account.
     account_creation_cache: LruCache<MessageId, CachedAccountCreation>,
     /// Dev option to allow clients to make unlimited mutation requests.
     disable_mutation_limit: bool,
+    /// Limits of the accounts without a tier.
+    default_limits: Limits,
+    account_tiers: BTreeMap<String, AccountTier>,
+    /// Persists the accounts, if enabled in the config.
+    db: Option<AccountDb>,
 }
 
 impl MaidManager {
-    pub fn new(
-        group_size: usize,
-        invite_key: Option<sign::PublicKey>,
-        disable_mutation_limit: bool,
-    ) -> MaidManager {
-        MaidManager {
+    pub fn new(group_size: usize, config: &Config) -> Result<MaidManager, InternalError> {
+        let (db, accounts, deleted_accounts) = match config.account_db_dir {
+            Some(ref dir) => {
+                let (db, accounts, deleted_accounts) = AccountDb::open(Path::new(dir))?;
+                info!("Loaded {} client accounts.", accounts.len());
+                (Some(db), accounts, deleted_accounts)
+            }
+            None => (None, HashMap::default(), BTreeSet::new()),
+        };
+        let disable_mutation_limit = config.dev.as_ref().map_or(false, |dev_config| {
+            dev_config.disable_mutation_limit
+        });
+
+        Ok(MaidManager {
             group_size,
-            accounts: HashMap::default(),
+            accounts,
+            deleted_accounts,
             data_ops_msg_id_accumulator: MessageIdAccumulator::new(
                 group_size,
                 Duration::from_secs(ACCUMULATOR_TIMEOUT_SECS),
             ),
+            data_ops_count_accumulator: CountAccumulator::new(
+                group_size,
+                Duration::from_secs(ACCUMULATOR_TIMEOUT_SECS),
+            ),
+            bytes_stored_accumulator: CountAccumulator::new(
+                group_size,
+                Duration::from_secs(ACCUMULATOR_TIMEOUT_SECS),
+            ),
+            key_ops_count_accumulator: CountAccumulator::new(
+                group_size,
+                Duration::from_secs(ACCUMULATOR_TIMEOUT_SECS),
+            ),
             request_cache: HashMap::default(),
-            invite_key: invite_key,
+            root_admin_key: config.invite_key.map(sign::PublicKey),
             account_creation_cache: LruCache::with_expiry_duration_and_capacity(
                 Duration::from_secs(ACCOUNT_CREATION_TIMEOUT_SECS),
                 ACCOUNT_CREATION_LIMIT,
             ),
             disable_mutation_limit,
-        }
+            default_limits: Limits {
+                max_ops_count: config.max_ops_count.unwrap_or(DEFAULT_MAX_OPS_COUNT),
+                max_bytes: config.max_bytes.unwrap_or(DEFAULT_MAX_BYTES),
+            },
+            account_tiers: config.account_tiers.clone().unwrap_or_else(BTreeMap::new),
+            db,
+        })
     }
 
     pub fn handle_serialised_refresh(
@@ -104,21 +159,76 @@ impl MaidManager {
         msg_id: MessageId,
         src_name: Option<XorName>,
     ) -> Result<(), InternalError> {
-        // `Refresh::Update` need to be accumulated using a custom algorithm, as `src` is a single
-        // node. The other variants don't need custom accumulation, so `src` is a group.
+        // `Refresh::UpdateDataOps` needs to be accumulated using a custom algorithm, as `src` is a
+        // single node.  The other variants don't need custom accumulation, as `src` is a group, so
+        // they're ignored from single nodes, which could otherwise apply them on their own.
+        if let Some(sender) = src_name {
+            match refresh {
+                Refresh::UpdateDataOps {
+                    name,
+                    count,
+                    bytes,
+                    msg_ids,
+                    key_ops_counts,
+                } => {
+                    self.handle_refresh_update_data_ops(
+                        routing_node,
+                        sender,
+                        name,
+                        count,
+                        bytes,
+                        msg_ids,
+                        key_ops_counts,
+                    )
+                }
+                refresh => warn!("Ignoring {:?} sent by single node {}.", refresh, sender),
+            }
+            return Ok(());
+        }
 
         match refresh {
-            Refresh::UpdateDataOps { name, msg_ids } => {
-                self.handle_refresh_update_data_ops(routing_node, unwrap!(src_name), name, msg_ids)
+            Refresh::UpdateDataOps { name, .. } => {
+                warn!("Ignoring data ops refresh for {} not sent by a single node.", name)
             }
             Refresh::UpdateKeys {
                 name,
                 ops_count,
                 keys,
-            } => self.handle_refresh_update_keys(routing_node, name, ops_count, keys),
-            Refresh::InsertDataOp(name) => {
-                self.handle_refresh_insert_data_op(routing_node, name, msg_id)
+                scopes,
+                expired_by,
+            } => {
+                self.handle_refresh_update_keys(
+                    routing_node,
+                    name,
+                    ops_count,
+                    keys,
+                    scopes,
+                    expired_by,
+                )
+            }
+            Refresh::InsertDataOp(name, bytes, app_key) => {
+                self.handle_refresh_insert_data_op(routing_node, name, msg_id, bytes, app_key)
+            }
+            Refresh::SetTier { name, tier } => self.handle_refresh_set_tier(name, tier),
+            Refresh::UpdateTier {
+                name,
+                tier,
+                version,
+            } => self.handle_refresh_update_tier(routing_node, name, tier, version),
+            Refresh::SetAdminRoles { name, roles } => {
+                self.handle_refresh_set_admin_roles(name, roles)
             }
+            Refresh::UpdateAdminRoles {
+                name,
+                roles,
+                version,
+            } => self.handle_refresh_update_admin_roles(routing_node, name, roles, version),
+            Refresh::AccountOp { name, op } => self.handle_refresh_account_op(name, op),
+            Refresh::UpdateSuspension {
+                name,
+                suspended,
+                version,
+            } => self.handle_refresh_update_suspension(routing_node, name, suspended, version),
             Refresh::Delete(name) => self.handle_refresh_delete(name),
         }
 
@@ -132,7 +242,9 @@ impl MaidManager {
         dst: ClientManagerAuthority,
         msg_id: MessageId,
     ) -> Result<(), InternalError> {
-        let res = self.get_account(&src, &dst).map(Account::balance);
+        let res = self.get_account(&src, &dst).map(|account| {
+            account.balance(self.limits(account))
+        });
         routing_node.send_get_account_info_response(
             dst.into(),
             src.into(),
@@ -160,12 +272,16 @@ impl MaidManager {
             return Ok(());
         }
 
+        let bytes = serialisation::serialised_size(&data);
         if let Err(err) = self.prepare_data_mutation(
             &src,
             &dst,
             AuthPolicy::Key,
+            DataOpType::PutIData,
+            None,
             Some(msg_id),
             None,
+            bytes,
         )
         {
             routing_node.send_put_idata_response(
@@ -178,7 +294,7 @@ impl MaidManager {
         }
 
         // Forwarding the request to NAE Manager.
-        if let Some(insert) = self.insert_into_request_cache(msg_id, src, dst, None) {
+        if let Some(insert) = self.insert_into_request_cache(msg_id, src, dst, None, bytes) {
             let fwd_src = dst.into();
             let fwd_dst = Authority::NaeManager(*data.name());
             trace!("MM forwarding PutIData request to {:?}", fwd_dst);
@@ -233,24 +349,63 @@ impl MaidManager {
     ) -> Result<(), InternalError> {
         match self.prepare_put_mdata(src, dst, data, msg_id, requester) {
             Ok(PutMDataAction::Claim(invite_name)) => {
-                let invite_src = dst.into();
-                let invite_dst = Authority::NaeManager(invite_name);
-                let actions = EntryActions::new()
-                    .ins(
-                        INVITE_CLAIMED_KEY.to_vec(),
-                        INVITE_CLAIMED_VALUE.to_vec(),
-                        0,
-                    )
-                    .into();
-
-                routing_node.send_mutate_mdata_entries_request(
-                    invite_src,
-                    invite_dst,
-                    invite_name,
-                    TYPE_TAG_INVITE,
-                    actions,
+                send_invite_claim(routing_node, dst, invite_name, None, msg_id, requester)?;
+            }
+            Ok(PutMDataAction::SetTier(account_name, tier)) => {
+                trace!("MM assigning tier {:?} to account {}", tier, account_name);
+                self.send_refresh(
+                    routing_node,
+                    dst.into(),
+                    Authority::ClientManager(account_name),
+                    Refresh::SetTier {
+                        name: account_name,
+                        tier,
+                    },
+                    msg_id,
+                )?;
+                routing_node.send_put_mdata_response(
+                    dst.into(),
+                    src.into(),
+                    Ok(()),
+                    msg_id,
+                )?;
+            }
+            Ok(PutMDataAction::SetAdminRoles(account_name, roles)) => {
+                trace!("MM granting admin roles {:?} to account {}", roles, account_name);
+                self.send_refresh(
+                    routing_node,
+                    dst.into(),
+                    Authority::ClientManager(account_name),
+                    Refresh::SetAdminRoles {
+                        name: account_name,
+                        roles,
+                    },
+                    msg_id,
+                )?;
+                routing_node.send_put_mdata_response(
+                    dst.into(),
+                    src.into(),
+                    Ok(()),
+                    msg_id,
+                )?;
+            }
+            Ok(PutMDataAction::AccountOp(account_name, op)) => {
+                trace!("MM performing {:?} on account {}", op, account_name);
+                self.send_refresh(
+                    routing_node,
+                    dst.into(),
+                    Authority::ClientManager(account_name),
+                    Refresh::AccountOp {
+                        name: account_name,
+                        op,
+                    },
+                    msg_id,
+                )?;
+                routing_node.send_put_mdata_response(
+                    dst.into(),
+                    src.into(),
+                    Ok(()),
                     msg_id,
-                    requester,
                 )?;
             }
             Ok(PutMDataAction::Forward(data)) => {
@@ -282,7 +437,7 @@ impl MaidManager {
         res: Result<(), ClientError>,
         msg_id: MessageId,
     ) -> Result<(), InternalError> {
-        let CachedRequest { src, dst, tag } = self.handle_data_mutation_response(
+        let CachedRequest { src, dst, tag, .. } = self.handle_data_mutation_response(
             routing_node,
             msg_id,
             res.is_ok(),
@@ -294,6 +449,7 @@ impl MaidManager {
                 // We wouldn't have forwarded two `Put` requests for the same account, so
                 // it must have been created via another client manager.
                 let _ = self.accounts.remove(src.name());
+                self.log(Record::Delete(*src.name()));
 
                 trace!("MM sending delete refresh for account {}", src.name());
                 self.send_refresh(
@@ -331,14 +487,23 @@ impl MaidManager {
         msg_id: MessageId,
         requester: sign::PublicKey,
     ) -> Result<(), InternalError> {
-        if let Err(err) = self.prepare_data_mutation(
-            &src,
-            &dst,
-            AuthPolicy::Key,
-            Some(msg_id),
-            Some(requester),
-        )
-        {
+        let bytes = entry_actions_size(&actions);
+        // Invitations are claimed by the client managers, so only invite issuers may mutate them.
+        let res = if tag == TYPE_TAG_INVITE && !self.is_admin(&src, &dst, AdminRole::InviteIssuer) {
+            Err(ClientError::InvalidOperation)
+        } else {
+            self.prepare_data_mutation(
+                &src,
+                &dst,
+                AuthPolicy::Key,
+                DataOpType::MutateMDataEntries,
+                Some(tag),
+                Some(msg_id),
+                Some(requester),
+                bytes,
+            )
+        };
+        if let Err(err) = res {
             routing_node.send_mutate_mdata_entries_response(
                 dst.into(),
                 src.into(),
@@ -348,8 +513,9 @@ impl MaidManager {
             return Ok(());
         }
 
-        // Forwarding the request to NAE Manager.
-        if let Some(insert) = self.insert_into_request_cache(msg_id, src, dst, Some(tag)) {
+        // Forwarding the request to NAE Manager.  Nothing is charged for the bytes yet, as the
+        // data managers report the change in size once the actions are applied.
+        if let Some(insert) = self.insert_into_request_cache(msg_id, src, dst, Some(tag), 0) {
             let fwd_src = dst.into();
             let fwd_dst = Authority::NaeManager(name);
             trace!("MM forwarding MutateMDataEntries request to {:?}", fwd_dst);
@@ -382,14 +548,17 @@ impl MaidManager {
         res: Result<(), ClientError>,
         msg_id: MessageId,
     ) -> Result<(), InternalError> {
-        if let Some(CachedAccountCreation { src, dst, data }) =
+        if let Some(creation) =
             // Invitation claim.
             self.account_creation_cache.remove(&msg_id) {
 
-            match res {
+            let max_claims = creation.max_claims;
+            match res.map_err(|error| next_claim_version(error, max_claims)) {
                 Ok(()) => {
+                    let CachedAccountCreation { src, dst, data, .. } = creation;
                     let _ = self.accounts.insert(*src.name(),
                                                  Account::new(self.disable_mutation_limit));
+                    self.log_put(*src.name());
                     self.forward_put_mdata(routing_node,
                                            src,
                                            dst,
@@ -397,29 +566,20 @@ impl MaidManager {
                                            msg_id,
                                            *src.client_key())?;
                 }
-                Err(error) => {
-                    let converted_error = match error {
-                        ClientError::NoSuchData => ClientError::InvalidInvitation,
-                        ClientError::InvalidEntryActions(ref entry_errors) => {
-                            let is_entry_exists_error = |entry_error: &EntryError| {
-                                match *entry_error {
-                                    EntryError::EntryExists(_) => true,
-                                    EntryError::NoSuchEntry |
-                                    EntryError::InvalidSuccessor(_) => false,
-                                }
-                            };
-                            if entry_errors.values().any(is_entry_exists_error) {
-                                ClientError::InvitationAlreadyClaimed
-                            } else {
-                                ClientError::from(format!("Error claiming invitation: {:?}", error))
-                            }
-                        }
-                        _ => ClientError::from(format!("Error claiming invitation: {:?}", error)),
-                    };
-
-                    routing_node.send_put_mdata_response(dst.into(),
-                                                         src.into(),
-                                                         Err(converted_error),
+                Err(Ok(version)) => {
+                    // The invitation has been claimed before, but allows another claim.
+                    send_invite_claim(routing_node,
+                                      creation.dst,
+                                      creation.invite_name,
+                                      Some(version),
+                                      msg_id,
+                                      *creation.src.client_key())?;
+                    let _ = self.account_creation_cache.insert(msg_id, creation);
+                }
+                Err(Err(error)) => {
+                    routing_node.send_put_mdata_response(creation.dst.into(),
+                                                         creation.src.into(),
+                                                         Err(error),
                                                          msg_id)?;
                 }
             }
@@ -452,8 +612,11 @@ impl MaidManager {
             &src,
             &dst,
             AuthPolicy::Key,
+            DataOpType::ManagePermissions,
+            Some(tag),
             Some(msg_id),
             Some(requester),
+            0,
         )
         {
             routing_node.send_set_mdata_user_permissions_response(
@@ -466,7 +629,7 @@ impl MaidManager {
         }
 
         // Forwarding the request to NAE Manager.
-        if let Some(insert) = self.insert_into_request_cache(msg_id, src, dst, Some(tag)) {
+        if let Some(insert) = self.insert_into_request_cache(msg_id, src, dst, Some(tag), 0) {
             let fwd_src = dst.into();
             let fwd_dst = Authority::NaeManager(name);
             trace!(
@@ -534,8 +697,11 @@ impl MaidManager {
             &src,
             &dst,
             AuthPolicy::Key,
+            DataOpType::ManagePermissions,
+            Some(tag),
             Some(msg_id),
             Some(requester),
+            0,
         )
         {
             routing_node.send_del_mdata_user_permissions_response(
@@ -548,7 +714,7 @@ impl MaidManager {
         }
 
         // Forwarding the request to NAE Manager.
-        if let Some(insert) = self.insert_into_request_cache(msg_id, src, dst, Some(tag)) {
+        if let Some(insert) = self.insert_into_request_cache(msg_id, src, dst, Some(tag), 0) {
             let fwd_src = dst.into();
             let fwd_dst = Authority::NaeManager(name);
             trace!(
@@ -614,8 +780,11 @@ impl MaidManager {
             &src,
             &dst,
             AuthPolicy::Owner,
+            DataOpType::ManagePermissions,
+            Some(tag),
             Some(msg_id),
             None,
+            0,
         )
         {
             routing_node.send_change_mdata_owner_response(
@@ -628,7 +797,7 @@ impl MaidManager {
         }
 
         // Forwarding the request to NAE Manager.
-        if let Some(insert) = self.insert_into_request_cache(msg_id, src, dst, Some(tag)) {
+        if let Some(insert) = self.insert_into_request_cache(msg_id, src, dst, Some(tag), 0) {
             let fwd_src = dst.into();
             let fwd_dst = Authority::NaeManager(name);
             trace!("MM forwarding ChangeMDataOwner request to {:?}", fwd_dst);
@@ -674,6 +843,9 @@ impl MaidManager {
         Ok(())
     }
 
+    // Routing's response only has room for the keys and their version, not their scopes.  Expired
+    // keys are left out, whether or not they've been removed yet, so that all the client managers
+    // respond the same.
     pub fn handle_list_auth_keys_and_version(
         &mut self,
         routing_node: &mut RoutingNode,
@@ -681,8 +853,15 @@ impl MaidManager {
         dst: ClientManagerAuthority,
         msg_id: MessageId,
     ) -> Result<(), InternalError> {
+        let now = utils::now_secs();
         let res = self.get_account(&src, &dst).map(|account| {
-            (account.keys.clone(), account.keys_ops_count)
+            let keys = account
+                .keys
+                .iter()
+                .filter(|key| !account.key_expired(key, now))
+                .cloned()
+                .collect();
+            (keys, account.keys_ops_count)
         });
         routing_node.send_list_auth_keys_and_version_response(
             dst.into(),
@@ -717,6 +896,55 @@ impl MaidManager {
         self.mutate_auth_keys(routing_node, src, dst, KeysOp::Del, key, version, msg_id)
     }
 
+    /// Proposes to the rest of the group to remove the app authentication keys which have expired
+    /// by now from the accounts.  The removal is only applied once enough of the group agree on the
+    /// time, see `Account::keys_expired_by`.  The message id is derived from the account and the
+    /// time, so every client manager sends the same message.
+    pub fn handle_tick(&mut self, routing_node: &mut RoutingNode) -> Result<(), InternalError> {
+        // The time is rounded down, so the client managers propose the same one despite their
+        // clocks differing a little.  Version 0 never replaces the keys, so the refresh only
+        // removes the expired ones, without racing with the client's keys operations.
+        let now = utils::now_secs();
+        let expired_by = now - now % KEY_EXPIRY_MARGIN_SECS;
+        let names: Vec<_> = self.accounts
+            .iter()
+            .filter(|&(_, account)| {
+                expired_by > account.keys_expired_by && account.has_expired_keys(expired_by)
+            })
+            .map(|(name, _)| *name)
+            .collect();
+
+        for name in names {
+            trace!("MM removing keys of account {} expired by {}", name, expired_by);
+            let hash = tiny_keccak::sha3_256(&serialisation::serialise(&(name, expired_by))?);
+            let msg_id = MessageId::from_added_node(XorName(hash));
+            self.send_refresh(
+                routing_node,
+                Authority::ClientManager(name),
+                Authority::ClientManager(name),
+                Refresh::UpdateKeys {
+                    name,
+                    ops_count: 0,
+                    keys: BTreeSet::new(),
+                    scopes: BTreeMap::new(),
+                    expired_by,
+                },
+                msg_id,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Returns a copy of all the accounts we manage, sorted by name.
+    pub fn snapshot(&self) -> Vec<(XorName, Account)> {
+        let mut accounts: Vec<_> = self.accounts
+            .iter()
+            .map(|(name, account)| (*name, account.clone()))
+            .collect();
+        accounts.sort_by(|lhs, rhs| lhs.0.cmp(&rhs.0));
+        accounts
+    }
+
     pub fn handle_node_added(
         &mut self,
         routing_node: &mut RoutingNode,
@@ -750,12 +978,22 @@ impl MaidManager {
         for name in &accounts_to_delete {
             trace!("No longer a MM for {}", name);
             let _ = self.accounts.remove(name);
+            self.log(Record::Delete(*name));
         }
 
         if !accounts_to_delete.is_empty() {
             info!("Managing {} client accounts.", self.accounts.len());
         }
 
+        // The tombstones are only needed while we manage the accounts.  Dropping them isn't logged,
+        // so they may come back after a restart until the next checkpoint, which is harmless.
+        let deleted_accounts = self.deleted_accounts
+            .iter()
+            .filter(|name| routing_table.is_closest(*name, self.group_size))
+            .cloned()
+            .collect();
+        self.deleted_accounts = deleted_accounts;
+
         let mut account_list: Vec<(XorName, Account)> = Vec::new();
         for (name, account) in &self.accounts {
             match routing_table.other_closest_names(name, self.group_size) {
@@ -843,20 +1081,33 @@ impl MaidManager {
                 check_msg_id = None;
                 self.prepare_put_invite(src, dst, data)
             }
+            TYPE_TAG_ACCOUNT_TIER => self.prepare_set_tier(&src, &dst, &data),
+            TYPE_TAG_ADMIN_ROLES => self.prepare_set_admin_roles(&src, &data),
+            TYPE_TAG_ACCOUNT_OP => self.prepare_account_op(&src, &dst, &data),
             _ => Ok(PutMDataAction::Forward(data)),
         };
 
-        if let Ok(PutMDataAction::Forward(_)) = res {
-            self.prepare_data_mutation(&src, &dst, AuthPolicy::Key, check_msg_id, Some(requester))
-                .map_err(|error| {
-                    trace!("MM PutMData request failed: {:?}", error);
-                    // Undo the account creation
-                    if tag == TYPE_TAG_SESSION_PACKET {
-                        let _ = self.accounts.remove(src.name());
-                    }
+        if let Ok(PutMDataAction::Forward(ref data)) = res {
+            let bytes = serialisation::serialised_size(data);
+            self.prepare_data_mutation(
+                &src,
+                &dst,
+                AuthPolicy::Key,
+                DataOpType::PutMData,
+                Some(tag),
+                check_msg_id,
+                Some(requester),
+                bytes,
+            ).map_err(|error| {
+                trace!("MM PutMData request failed: {:?}", error);
+                // Undo the account creation
+                if tag == TYPE_TAG_SESSION_PACKET {
+                    let _ = self.accounts.remove(src.name());
+                    self.log(Record::Delete(*src.name()));
+                }
 
-                    error
-                })?;
+                error
+            })?;
         }
 
         res
@@ -873,25 +1124,29 @@ impl MaidManager {
             trace!("MM Cannot create account for {:?} as {:?}.", src, dst);
             return Err(ClientError::InvalidOperation);
         }
+        if self.deleted_accounts.contains(src.name()) {
+            trace!("MM Cannot create account for {:?} - it was deleted by an admin", src);
+            return Err(ClientError::InvalidOperation);
+        }
 
-        if self.is_admin(&src) || self.invite_key.is_none() {
-            let len = self.accounts.len();
-            match self.accounts.entry(*src.name()) {
-                Entry::Vacant(entry) => {
-                    let _ = entry.insert(Account::new(self.disable_mutation_limit));
-                    info!("Managing {} client accounts.", len + 1);
-                    Ok(PutMDataAction::Forward(data))
-                }
-                Entry::Occupied(_) => {
-                    trace!("MM Cannot create account for {:?} - it already exists", src);
-                    Err(ClientError::AccountExists)
-                }
+        if self.is_root_admin(&src) || self.root_admin_key.is_none() {
+            if self.accounts.contains_key(src.name()) {
+                trace!("MM Cannot create account for {:?} - it already exists", src);
+                return Err(ClientError::AccountExists);
             }
+
+            let _ = self.accounts.insert(
+                *src.name(),
+                Account::new(self.disable_mutation_limit),
+            );
+            info!("Managing {} client accounts.", self.accounts.len());
+            self.log_put(*src.name());
+            Ok(PutMDataAction::Forward(data))
         } else if self.accounts.contains_key(src.name()) {
             trace!("MM Cannot create account for {:?} - it already exists", src);
             Err(ClientError::AccountExists)
         } else {
-            let invite_name = get_invite_name(&data)?;
+            let (invite_name, invite) = get_invite(&data)?;
             trace!(
                 "Creating account for {:?} with invitation {:?}.",
                 src.name(),
@@ -902,6 +1157,8 @@ impl MaidManager {
                 src: src,
                 dst: dst,
                 data: data,
+                invite_name: invite_name,
+                max_claims: invite.max_claims,
             };
             if let Some(old) = self.account_creation_cache.insert(msg_id, item) {
                 debug!(
@@ -924,7 +1181,7 @@ impl MaidManager {
         data: MutableData,
     ) -> Result<PutMDataAction, ClientError> {
         // Only the authorised admin client can create invitations.
-        if !self.is_admin(&src) {
+        if !self.is_admin(&src, &dst, AdminRole::InviteIssuer) {
             trace!("Cannot put {:?} as {:?}.", data, dst);
             Err(ClientError::InvalidOperation)
         } else {
@@ -933,6 +1190,82 @@ impl MaidManager {
         }
     }
 
+    fn prepare_set_tier(
+        &self,
+        src: &ClientAuthority,
+        dst: &ClientManagerAuthority,
+        data: &MutableData,
+    ) -> Result<PutMDataAction, ClientError> {
+        // Only the authorised admin client can assign tiers.
+        if !self.is_admin(src, dst, AdminRole::AccountTierManager) {
+            trace!("Cannot assign tier to {} as {:?}.", data.name(), src);
+            return Err(ClientError::InvalidOperation);
+        }
+
+        let tier = match data.get(ACCOUNT_TIER_KEY) {
+            Some(value) => {
+                let tier = String::from_utf8(value.content.clone()).map_err(|_| {
+                    ClientError::InvalidOperation
+                })?;
+                if !self.account_tiers.contains_key(&tier) {
+                    return Err(ClientError::from(format!("Unknown account tier {:?}", tier)));
+                }
+                Some(tier)
+            }
+            None => None,
+        };
+
+        Ok(PutMDataAction::SetTier(*data.name(), tier))
+    }
+
+    fn prepare_set_admin_roles(
+        &self,
+        src: &ClientAuthority,
+        data: &MutableData,
+    ) -> Result<PutMDataAction, ClientError> {
+        // Only the root admin can grant admin roles.
+        if !self.is_root_admin(src) {
+            trace!("Cannot grant admin roles to {} as {:?}.", data.name(), src);
+            return Err(ClientError::InvalidOperation);
+        }
+
+        let mut roles = BTreeSet::new();
+        for key in data.keys() {
+            let role = AdminRole::from_name(key).ok_or_else(|| {
+                ClientError::from(format!(
+                    "Unknown admin role {:?}",
+                    String::from_utf8_lossy(key)
+                ))
+            })?;
+            let _ = roles.insert(role);
+        }
+
+        Ok(PutMDataAction::SetAdminRoles(*data.name(), roles))
+    }
+
+    fn prepare_account_op(
+        &self,
+        src: &ClientAuthority,
+        dst: &ClientManagerAuthority,
+        data: &MutableData,
+    ) -> Result<PutMDataAction, ClientError> {
+        // Only the authorised admin clients can suspend, resume or delete accounts.
+        if !self.is_admin(src, dst, AdminRole::AccountSuspender) {
+            trace!("Cannot perform account operation on {} as {:?}.", data.name(), src);
+            return Err(ClientError::InvalidOperation);
+        }
+
+        let value = data.get(ACCOUNT_OP_KEY).ok_or(ClientError::InvalidOperation)?;
+        let op = AccountOp::from_name(&value.content).ok_or_else(|| {
+            ClientError::from(format!(
+                "Unknown account operation {:?}",
+                String::from_utf8_lossy(&value.content)
+            ))
+        })?;
+
+        Ok(PutMDataAction::AccountOp(*data.name(), op))
+    }
+
     fn forward_put_mdata(
         &mut self,
         routing_node: &mut RoutingNode,
@@ -942,7 +1275,10 @@ impl MaidManager {
         msg_id: MessageId,
         requester: sign::PublicKey,
     ) -> Result<(), InternalError> {
-        if let Some(insert) = self.insert_into_request_cache(msg_id, src, dst, Some(data.tag())) {
+        let bytes = serialisation::serialised_size(&data);
+        if let Some(insert) =
+            self.insert_into_request_cache(msg_id, src, dst, Some(data.tag()), bytes)
+        {
             let fwd_src = dst.into();
             let fwd_dst = Authority::NaeManager(*data.name());
 
@@ -1001,18 +1337,8 @@ impl MaidManager {
         msg_id: MessageId,
     ) -> Result<(), InternalError> {
         let res = match self.prepare_auth_keys_mutation(&src, &dst, op, key, version) {
-            Ok(keys) => {
-                self.send_refresh(
-                    routing_node,
-                    dst.into(),
-                    dst.into(),
-                    Refresh::UpdateKeys {
-                        name: *src.name(),
-                        keys: keys,
-                        ops_count: version,
-                    },
-                    msg_id,
-                )?;
+            Ok(refresh) => {
+                self.send_refresh(routing_node, dst.into(), dst.into(), refresh, msg_id)?;
                 Ok(())
             }
             Err(error) => Err(error),
@@ -1047,7 +1373,7 @@ impl MaidManager {
         op: KeysOp,
         key: sign::PublicKey,
         version: u64,
-    ) -> Result<BTreeSet<sign::PublicKey>, ClientError> {
+    ) -> Result<Refresh, ClientError> {
         let client_name = src.name();
         let client_manager_name = dst.name();
 
@@ -1055,38 +1381,59 @@ impl MaidManager {
             return Err(ClientError::AccessDenied);
         }
 
-        let account = self.accounts.get_mut(client_manager_name).ok_or(
-            ClientError::NoSuchAccount,
-        )?;
+        let limits = self.accounts.get(client_manager_name).map_or(
+            self.default_limits,
+            |account| self.limits(account),
+        );
+        let refresh = {
+            let account = self.accounts.get_mut(client_manager_name).ok_or(
+                ClientError::NoSuchAccount,
+            )?;
 
-        if version != account.keys_ops_count + 1 {
-            return Err(ClientError::InvalidSuccessor(account.keys_ops_count));
-        }
+            if account.suspended {
+                return Err(ClientError::from(ACCOUNT_SUSPENDED_ERROR));
+            }
 
-        if !account.has_balance() {
-            return Err(ClientError::LowBalance);
-        }
+            if version != account.keys_ops_count + 1 {
+                return Err(ClientError::InvalidSuccessor(account.keys_ops_count));
+            }
 
-        op.apply(&mut account.keys, key)?;
-        account.keys_ops_count = version;
+            if !account.has_balance(limits) {
+                return Err(ClientError::LowBalance);
+            }
+
+            // Deleting the key lifts its restrictions too, so they don't apply if it's reinserted.
+            if let KeysOp::Del = op {
+                account.set_key_scope(key, None)?;
+            }
+            op.apply(&mut account.keys, key)?;
+            account.keys_ops_count = version;
+            Refresh::update_keys_ops(client_manager_name, account)
+        };
 
-        Ok(account.keys.clone())
+        self.log_update_keys(*client_manager_name);
+        Ok(refresh)
     }
 
+    #[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
     fn prepare_data_mutation(
         &mut self,
         src: &ClientAuthority,
         dst: &ClientManagerAuthority,
         policy: AuthPolicy,
+        op: DataOpType,
+        tag: Option<u64>,
         msg_id: Option<MessageId>,
         requester: Option<sign::PublicKey>,
+        bytes: u64,
     ) -> Result<(), ClientError> {
         let account = self.accounts.get(dst.name()).ok_or(
             ClientError::NoSuchAccount,
         )?;
         let allowed = src.name() == dst.name() ||
             if AuthPolicy::Key == policy {
-                account.keys.contains(src.client_key())
+                account.keys.contains(src.client_key()) &&
+                    !account.key_expired(src.client_key(), utils::now_secs())
             } else {
                 false
             };
@@ -1095,19 +1442,31 @@ impl MaidManager {
             return Err(ClientError::AccessDenied);
         }
 
+        if src.name() != dst.name() && !account.key_allows(src.client_key(), op, tag) {
+            return Err(ClientError::AccessDenied);
+        }
+
         if let Some(requester) = requester {
             if requester != *src.client_key() {
                 return Err(ClientError::AccessDenied);
             }
         }
 
+        if account.suspended {
+            return Err(ClientError::from(ACCOUNT_SUSPENDED_ERROR));
+        }
+
         if let Some(msg_id) = msg_id {
-            if !account.has_balance() {
+            let limits = self.limits(account);
+            if !account.has_balance(limits) {
                 return Err(ClientError::LowBalance);
             }
+            if !account.has_space(bytes, limits) {
+                return Err(ClientError::from(STORAGE_LIMIT_ERROR));
+            }
 
             // Prevent reusing message Ids.
-            if account.data_ops_msg_ids.contains(&msg_id) {
+            if account.has_data_op(&msg_id) {
                 return Err(ClientError::InvalidOperation);
             }
         }
@@ -1127,7 +1486,7 @@ impl MaidManager {
                 routing_node,
                 req.dst.into(),
                 req.dst.into(),
-                Refresh::InsertDataOp(*req.dst.name()),
+                Refresh::InsertDataOp(*req.dst.name(), req.bytes, req.app_key()),
                 msg_id,
             )?;
         }
@@ -1178,6 +1537,45 @@ impl MaidManager {
                 Refresh::update_keys_ops(&account_name, &account),
                 msg_id,
             )?;
+            if account.tier_version > 0 {
+                self.send_refresh(
+                    routing_node,
+                    Authority::ClientManager(account_name),
+                    dst,
+                    Refresh::UpdateTier {
+                        name: account_name,
+                        tier: account.tier.clone(),
+                        version: account.tier_version,
+                    },
+                    msg_id,
+                )?;
+            }
+            if account.admin_roles_version > 0 {
+                self.send_refresh(
+                    routing_node,
+                    Authority::ClientManager(account_name),
+                    dst,
+                    Refresh::UpdateAdminRoles {
+                        name: account_name,
+                        roles: account.admin_roles.clone(),
+                        version: account.admin_roles_version,
+                    },
+                    msg_id,
+                )?;
+            }
+            if account.suspension_version > 0 {
+                self.send_refresh(
+                    routing_node,
+                    Authority::ClientManager(account_name),
+                    dst,
+                    Refresh::UpdateSuspension {
+                        name: account_name,
+                        suspended: account.suspended,
+                        version: account.suspension_version,
+                    },
+                    msg_id,
+                )?;
+            }
         }
 
         Ok(())
@@ -1189,9 +1587,50 @@ impl MaidManager {
         routing_node: &mut RoutingNode,
         sender: XorName,
         account_name: XorName,
-        data_ops_msg_ids: BTreeSet<MessageId>,
+        count: u64,
+        bytes: u64,
+        recent_msg_ids: BTreeSet<MessageId>,
+        key_ops_counts: BTreeMap<sign::PublicKey, u64>,
     ) {
-        for msg_id in data_ops_msg_ids {
+        // The count, the stored bytes and the recent message ids are accumulated separately, and
+        // the message ids are only remembered, not counted, as the count already includes them.
+        // Only the accumulated values which changed the account are logged.
+        let mut updated_count = None;
+        let mut updated_bytes = None;
+        let mut updated_key_ops_counts = BTreeMap::new();
+        let mut remembered_msg_ids = Vec::new();
+        if let Some(count) = self.data_ops_count_accumulator.add(account_name, sender, count) {
+            if let Some(account) = self.fetch_account(routing_node, account_name) {
+                if account.data_ops_count < count {
+                    account.data_ops_count = count;
+                    updated_count = Some(count);
+                }
+            }
+        }
+        if let Some(bytes) = self.bytes_stored_accumulator.add(account_name, sender, bytes) {
+            if let Some(account) = self.fetch_account(routing_node, account_name) {
+                // Unlike the counts, the bytes also decrease as data is deleted.
+                if account.bytes_stored != bytes {
+                    account.bytes_stored = bytes;
+                    updated_bytes = Some(bytes);
+                }
+            }
+        }
+        // The counts may arrive before the scopes, so they're kept even for keys without one yet.
+        for (key, count) in key_ops_counts {
+            if let Some(count) =
+                self.key_ops_count_accumulator.add((account_name, key), sender, count)
+            {
+                if let Some(account) = self.fetch_account(routing_node, account_name) {
+                    let ops_count = account.key_ops_counts.entry(key).or_insert(0);
+                    if *ops_count < count {
+                        *ops_count = count;
+                        let _ = updated_key_ops_counts.insert(key, count);
+                    }
+                }
+            }
+        }
+        for msg_id in recent_msg_ids {
             if let Some((_, msg_id)) =
                 self.data_ops_msg_id_accumulator.add(
                     (account_name, msg_id),
@@ -1199,10 +1638,25 @@ impl MaidManager {
                 )
             {
                 if let Some(account) = self.fetch_account(routing_node, account_name) {
-                    let _ = account.data_ops_msg_ids.insert(msg_id);
+                    if !account.has_data_op(&msg_id) {
+                        account.remember_data_op(msg_id);
+                        remembered_msg_ids.push(msg_id);
+                    }
                 }
             }
         }
+
+        if updated_count.is_some() || updated_bytes.is_some() ||
+            !updated_key_ops_counts.is_empty() || !remembered_msg_ids.is_empty()
+        {
+            self.log(Record::UpdateDataOps {
+                name: account_name,
+                count: updated_count,
+                bytes: updated_bytes,
+                key_ops_counts: updated_key_ops_counts,
+                msg_ids: remembered_msg_ids,
+            });
+        }
     }
 
     // `src` is a group - already accumulated.
@@ -1211,10 +1665,10 @@ impl MaidManager {
         routing_node: &RoutingNode,
         account_name: XorName,
         msg_id: MessageId,
+        bytes: u64,
+        app_key: Option<sign::PublicKey>,
     ) {
-        if let Some(account) = self.fetch_account(routing_node, account_name) {
-            let _ = account.data_ops_msg_ids.insert(msg_id);
-        }
+        self.insert_data_op(routing_node, account_name, msg_id, bytes, app_key);
     }
 
     // `src` is a group - already accumulated.
@@ -1224,19 +1678,197 @@ impl MaidManager {
         account_name: XorName,
         ops_count: u64,
         keys: BTreeSet<sign::PublicKey>,
+        scopes: BTreeMap<sign::PublicKey, KeyScope>,
+        expired_by: u64,
+    ) {
+        let updated = match self.fetch_account(routing_node, account_name) {
+            Some(account) => {
+                let replaced = if account.keys_ops_count < ops_count {
+                    account.update_keys(ops_count, keys, scopes);
+                    true
+                } else {
+                    false
+                };
+                // Against the time the group agreed on rather than our own clock, so all the
+                // client managers remove the same keys.
+                account.remove_expired_keys(expired_by) || replaced
+            }
+            None => false,
+        };
+        if updated {
+            self.log_update_keys(account_name);
+        }
+    }
+
+    // `src` is a group - already accumulated.  Sent by the client managers of the admin, so unlike
+    // the other refreshes, this doesn't create the account if we don't have it.
+    fn handle_refresh_set_tier(&mut self, account_name: XorName, tier: Option<String>) {
+        match self.accounts.get_mut(&account_name) {
+            Some(account) => {
+                account.tier = tier;
+                account.tier_version += 1;
+            }
+            None => return,
+        }
+        self.log_put(account_name);
+    }
+
+    // `src` is a group - already accumulated.
+    fn handle_refresh_update_tier(
+        &mut self,
+        routing_node: &RoutingNode,
+        account_name: XorName,
+        tier: Option<String>,
+        version: u64,
+    ) {
+        let updated = match self.fetch_account(routing_node, account_name) {
+            Some(account) if account.tier_version < version => {
+                account.tier = tier;
+                account.tier_version = version;
+                true
+            }
+            _ => false,
+        };
+        if updated {
+            self.log_put(account_name);
+        }
+    }
+
+    // `src` is a group - already accumulated.  Sent by the client managers of the root admin, so
+    // this doesn't create the account if we don't have it.
+    fn handle_refresh_set_admin_roles(
+        &mut self,
+        account_name: XorName,
+        roles: BTreeSet<AdminRole>,
+    ) {
+        match self.accounts.get_mut(&account_name) {
+            Some(account) => {
+                account.admin_roles = roles;
+                account.admin_roles_version += 1;
+            }
+            None => return,
+        }
+        self.log_put(account_name);
+    }
+
+    // `src` is a group - already accumulated.
+    fn handle_refresh_update_admin_roles(
+        &mut self,
+        routing_node: &RoutingNode,
+        account_name: XorName,
+        roles: BTreeSet<AdminRole>,
+        version: u64,
+    ) {
+        let updated = match self.fetch_account(routing_node, account_name) {
+            Some(account) if account.admin_roles_version < version => {
+                account.admin_roles = roles;
+                account.admin_roles_version = version;
+                true
+            }
+            _ => false,
+        };
+        if updated {
+            self.log_put(account_name);
+        }
+    }
+
+    // `src` is a group - already accumulated.  Sent by the client managers of the admin, so this
+    // doesn't create the account if we don't have it.
+    fn handle_refresh_account_op(&mut self, account_name: XorName, op: AccountOp) {
+        let suspended = match op {
+            AccountOp::Suspend => true,
+            AccountOp::Resume => false,
+            AccountOp::Delete => {
+                self.delete_account_by_admin(account_name);
+                return;
+            }
+        };
+
+        match self.accounts.get_mut(&account_name) {
+            Some(account) => {
+                account.suspended = suspended;
+                account.suspension_version += 1;
+            }
+            None => return,
+        }
+        self.log_put(account_name);
+    }
+
+    // `src` is a group - already accumulated.
+    fn handle_refresh_update_suspension(
+        &mut self,
+        routing_node: &RoutingNode,
+        account_name: XorName,
+        suspended: bool,
+        version: u64,
     ) {
-        if let Some(account) = self.fetch_account(routing_node, account_name) {
-            if account.keys_ops_count < ops_count {
-                account.keys = keys;
-                account.keys_ops_count = ops_count;
+        let updated = match self.fetch_account(routing_node, account_name) {
+            Some(account) if account.suspension_version < version => {
+                account.suspended = suspended;
+                account.suspension_version = version;
+                true
             }
+            _ => false,
+        };
+        if updated {
+            self.log_put(account_name);
         }
     }
 
+    // Deletes the account, and keeps its tombstone so the refreshes and the requests still in
+    // flight don't recreate it.  Its pending requests are dropped, so their responses aren't
+    // charged to it.
+    fn delete_account_by_admin(&mut self, account_name: XorName) {
+        if !self.deleted_accounts.insert(account_name) {
+            return;
+        }
+        info!("Deleting client account {} as requested by an admin.", account_name);
+        let _ = self.accounts.remove(&account_name);
+        self.request_cache.retain(
+            |_, request| *request.dst.name() != account_name,
+        );
+        info!("Managing {} client accounts.", self.accounts.len());
+        self.log(Record::Tombstone(account_name));
+    }
+
     // `src` is a group - already accumulated.
     fn handle_refresh_delete(&mut self, account_name: XorName) {
         let _ = self.accounts.remove(&account_name);
         info!("Managing {} client accounts.", self.accounts.len());
+        self.log(Record::Delete(account_name));
+    }
+
+    /// Handles the change in the size of the account's data reported by the data managers after
+    /// mutating its entries.  `src` is a group - already accumulated.
+    pub fn handle_serialised_bytes_change(
+        &mut self,
+        account_name: XorName,
+        serialised_change: &[u8],
+    ) -> Result<(), InternalError> {
+        let change: i64 = serialisation::deserialise(serialised_change)?;
+        match self.accounts.get_mut(&account_name) {
+            Some(account) => account.change_bytes_stored(change),
+            None => return Ok(()),
+        }
+        self.log(Record::ChangeBytesStored(account_name, change));
+        Ok(())
+    }
+
+    fn insert_data_op(
+        &mut self,
+        routing_node: &RoutingNode,
+        account_name: XorName,
+        msg_id: MessageId,
+        bytes: u64,
+        app_key: Option<sign::PublicKey>,
+    ) {
+        let inserted = match self.fetch_account(routing_node, account_name) {
+            Some(account) => account.insert_data_op(msg_id, bytes, app_key.as_ref()),
+            None => false,
+        };
+        if inserted {
+            self.log(Record::InsertDataOp(account_name, msg_id, bytes, app_key));
+        }
     }
 
     fn insert_into_request_cache(
@@ -1245,10 +1877,19 @@ impl MaidManager {
         src: ClientAuthority,
         dst: ClientManagerAuthority,
         tag: Option<u64>,
+        bytes: u64,
     ) -> Option<RequestCacheInsert> {
         match self.request_cache.entry(msg_id) {
             Entry::Vacant(entry) => {
-                Some(RequestCacheInsert(entry, CachedRequest { src, dst, tag }))
+                Some(RequestCacheInsert(
+                    entry,
+                    CachedRequest {
+                        src,
+                        dst,
+                        tag,
+                        bytes,
+                    },
+                ))
             }
             Entry::Occupied(_) => None,
         }
@@ -1274,19 +1915,109 @@ impl MaidManager {
         {
             return None;
         }
+        if self.deleted_accounts.contains(&account_name) {
+            trace!("MM not recreating account {} deleted by an admin.", account_name);
+            return None;
+        }
 
-        let accounts_len = self.accounts.len();
-        let disable_mutation_limit = self.disable_mutation_limit;
-        let account = self.accounts.entry(account_name).or_insert_with(|| {
-            info!("Managing {} client accounts.", accounts_len + 1);
-            Account::new(disable_mutation_limit)
-        });
+        if !self.accounts.contains_key(&account_name) {
+            let _ = self.accounts.insert(
+                account_name,
+                Account::new(self.disable_mutation_limit),
+            );
+            info!("Managing {} client accounts.", self.accounts.len());
+            self.log_put(account_name);
+        }
+
+        self.accounts.get_mut(&account_name)
+    }
+
+    // Records the current state of the account in the account database.
+    fn log_put(&mut self, account_name: XorName) {
+        let account = match self.accounts.get(&account_name) {
+            Some(account) => account.clone(),
+            None => return,
+        };
+        self.log(Record::Put(account_name, account));
+    }
+
+    // Records the current app keys of the account in the account database.
+    fn log_update_keys(&mut self, account_name: XorName) {
+        let record = match self.accounts.get(&account_name) {
+            Some(account) => {
+                Record::UpdateKeys {
+                    name: account_name,
+                    ops_count: account.keys_ops_count,
+                    keys: account.keys.clone(),
+                    scopes: account.key_scopes.clone(),
+                    expired_by: account.keys_expired_by,
+                }
+            }
+            None => return,
+        };
+        self.log(record);
+    }
+
+    // Records a change already made to the accounts in the account database.  It's only durable
+    // once `sync_db` is called.
+    fn log(&mut self, record: Record) {
+        if let Some(ref mut db) = self.db {
+            if let Err(error) = db.append(&record, &self.accounts, &self.deleted_accounts) {
+                error!("Failed to persist {:?}: {:?}", record, error);
+            }
+        }
+    }
+
+    /// Makes the changes to the accounts recorded since the last call durable.  Called once after
+    /// handling each event, so the changes are synced in a single write per event rather than one
+    /// per change, before any later request can observe them.
+    pub fn sync_db(&mut self) {
+        if let Some(ref mut db) = self.db {
+            if let Err(error) = db.sync() {
+                error!("Failed to sync the account database: {:?}", error);
+            }
+        }
+    }
+
+    // Returns whether the client may act as an admin with the given role.  Only the owner of the
+    // account can, not its apps, so it's decided by the client managers of the account.
+    fn is_admin(
+        &self,
+        src: &ClientAuthority,
+        dst: &ClientManagerAuthority,
+        role: AdminRole,
+    ) -> bool {
+        if self.is_root_admin(src) {
+            return true;
+        }
+        src.name() == dst.name() &&
+            self.accounts.get(dst.name()).map_or(false, |account| {
+                account.admin_roles.contains(&role)
+            })
+    }
 
-        Some(account)
+    fn is_root_admin(&self, authority: &ClientAuthority) -> bool {
+        Some(*authority.client_key()) == self.root_admin_key
     }
 
-    fn is_admin(&self, authority: &ClientAuthority) -> bool {
-        Some(*authority.client_key()) == self.invite_key
+    // Returns the limits of the account, as per its tier.
+    fn limits(&self, account: &Account) -> Limits {
+        match account.tier {
+            Some(ref tier) => {
+                if let Some(account_tier) = self.account_tiers.get(tier) {
+                    Limits {
+                        max_ops_count: account_tier.max_ops_count,
+                        max_bytes: account_tier.max_bytes.unwrap_or(
+                            self.default_limits.max_bytes,
+                        ),
+                    }
+                } else {
+                    warn!("Unknown account tier {:?} - using the default limits.", tier);
+                    self.default_limits
+                }
+            }
+            None => self.default_limits,
+        }
     }
 }
 
@@ -1294,7 +2025,7 @@ impl MaidManager {
 impl MaidManager {
     pub fn get_mutation_count(&self, client_name: &XorName) -> Option<u64> {
         self.accounts.get(client_name).map(|account| {
-            account.data_ops_msg_ids.len() as u64
+            account.data_ops_count
         })
     }
 }
@@ -1303,22 +2034,69 @@ impl MaidManager {
 pub enum Refresh {
     UpdateDataOps {
         name: XorName,
+        count: u64,
+        bytes: u64,
+        /// The message ids of the most recent data operations, at most `DATA_OPS_WINDOW` of them.
+        /// Operations with older ids aren't recognised as duplicates, see `Account`.
         msg_ids: BTreeSet<MessageId>,
+        /// The number of data operations performed by each app key with a scope.
+        key_ops_counts: BTreeMap<sign::PublicKey, u64>,
     },
     UpdateKeys {
         name: XorName,
         ops_count: u64,
         keys: BTreeSet<sign::PublicKey>,
+        scopes: BTreeMap<sign::PublicKey, KeyScope>,
+        /// The time by which the expired keys are removed, see `Account::keys_expired_by`.
+        expired_by: u64,
     },
-    InsertDataOp(XorName),
+    /// Counts a data operation which stored the given number of bytes, and was performed by the
+    /// given app key if not by the owner of the account.
+    InsertDataOp(XorName, u64, Option<sign::PublicKey>),
     Delete(XorName),
+    /// Assigns a tier to the account, or resets it to the default limits if `None`.
+    SetTier {
+        name: XorName,
+        tier: Option<String>,
+    },
+    /// Replicates the tier of the account during churn.
+    UpdateTier {
+        name: XorName,
+        tier: Option<String>,
+        version: u64,
+    },
+    /// Grants the admin roles to the account, replacing any it had.
+    SetAdminRoles {
+        name: XorName,
+        roles: BTreeSet<AdminRole>,
+    },
+    /// Replicates the admin roles of the account during churn.
+    UpdateAdminRoles {
+        name: XorName,
+        roles: BTreeSet<AdminRole>,
+        version: u64,
+    },
+    /// Suspends, resumes or deletes the account.
+    AccountOp {
+        name: XorName,
+        op: AccountOp,
+    },
+    /// Replicates the suspension of the account during churn.
+    UpdateSuspension {
+        name: XorName,
+        suspended: bool,
+        version: u64,
+    },
 }
 
 impl Refresh {
     fn update_data_ops(name: &XorName, account: &Account) -> Self {
         Refresh::UpdateDataOps {
             name: *name,
-            msg_ids: account.data_ops_msg_ids.clone(),
+            count: account.data_ops_count,
+            bytes: account.bytes_stored,
+            msg_ids: account.recent_data_ops.iter().cloned().collect(),
+            key_ops_counts: account.key_ops_counts.clone(),
         }
     }
 
@@ -1327,8 +2105,11 @@ impl Refresh {
             name: *name,
             ops_count: account.keys_ops_count,
             keys: account.keys.clone(),
+            scopes: account.key_scopes.clone(),
+            expired_by: account.keys_expired_by,
         }
     }
+
 }
 
 #[derive(Clone, Copy)]
@@ -1373,6 +2154,19 @@ struct CachedRequest {
     src: ClientAuthority,
     dst: ClientManagerAuthority,
     tag: Option<u64>,
+    // Number of bytes the request stores, charged to the account on success.
+    bytes: u64,
+}
+
+impl CachedRequest {
+    // The app key the request was made with, if not made by the owner of the account.
+    fn app_key(&self) -> Option<sign::PublicKey> {
+        if self.src.name() == self.dst.name() {
+            None
+        } else {
+            Some(*self.src.client_key())
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -1380,6 +2174,8 @@ struct CachedAccountCreation {
     src: ClientAuthority,
     dst: ClientManagerAuthority,
     data: MutableData,
+    invite_name: XorName,
+    max_claims: u64,
 }
 
 // What to do when handling `PutMData`.
@@ -1388,6 +2184,12 @@ enum PutMDataAction {
     Claim(XorName),
     // Forward the request to the `NaeManager`.
     Forward(MutableData),
+    // Assign the tier to the account with the given name.
+    SetTier(XorName, Option<String>),
+    // Grant the admin roles to the account with the given name.
+    SetAdminRoles(XorName, BTreeSet<AdminRole>),
+    // Perform the operation on the account with the given name.
+    AccountOp(XorName, AccountOp),
 }
 
 struct RequestCacheInsert<'a>(VacantEntry<'a, MessageId, CachedRequest>, CachedRequest);
@@ -1398,7 +2200,22 @@ impl<'a> RequestCacheInsert<'a> {
     }
 }
 
-fn get_invite_name(data: &MutableData) -> Result<XorName, ClientError> {
+// Returns the most bytes the entry actions can add to the account: the size of the inserted and
+// updated entries.  The actual change also accounts for the replaced and deleted entries.
+fn entry_actions_size(actions: &BTreeMap<Vec<u8>, EntryAction>) -> u64 {
+    actions
+        .iter()
+        .map(|(key, action)| match *action {
+            EntryAction::Ins(ref value) |
+            EntryAction::Update(ref value) => utils::mdata_entry_size(key, value),
+            EntryAction::Del(_) => 0,
+        })
+        .sum()
+}
+
+// Returns the name of the invitation the account packet claims, and the invitation itself, if
+// it can still be claimed.
+fn get_invite(data: &MutableData) -> Result<(XorName, Invite), ClientError> {
     let content = &data.get(ACC_LOGIN_ENTRY_KEY)
         .ok_or(ClientError::InvalidInvitation)?
         .content;
@@ -1408,8 +2225,74 @@ fn get_invite_name(data: &MutableData) -> Result<XorName, ClientError> {
     })?;
 
     if let AccountPacket::WithInvitation { invitation_string, .. } = account_packet {
-        Ok(XorName(tiny_keccak::sha3_256(invitation_string.as_bytes())))
+        let invite = Invite::parse(&invitation_string)?;
+        Ok((Invite::name(&invitation_string), invite))
     } else {
         Err(ClientError::InvalidInvitation)
     }
 }
+
+// Claims the invitation by inserting its `claimed` entry or, if it has been claimed before,
+// updating it to the given version.  The version of the entry counts the claims.
+fn send_invite_claim(
+    routing_node: &mut RoutingNode,
+    src: ClientManagerAuthority,
+    invite_name: XorName,
+    version: Option<u64>,
+    msg_id: MessageId,
+    requester: sign::PublicKey,
+) -> Result<(), InternalError> {
+    let actions = EntryActions::new();
+    let actions = match version {
+        None => actions.ins(INVITE_CLAIMED_KEY.to_vec(), INVITE_CLAIMED_VALUE.to_vec(), 0),
+        Some(version) => {
+            actions.update(
+                INVITE_CLAIMED_KEY.to_vec(),
+                INVITE_CLAIMED_VALUE.to_vec(),
+                version,
+            )
+        }
+    };
+
+    routing_node.send_mutate_mdata_entries_request(
+        src.into(),
+        Authority::NaeManager(invite_name),
+        invite_name,
+        TYPE_TAG_INVITE,
+        actions.into(),
+        msg_id,
+        requester,
+    )?;
+    Ok(())
+}
+
+// Returns the version to claim the invitation with next, if it failed to be claimed because it
+// has been claimed before but allows more claims.  Otherwise returns why the claim failed.
+fn next_claim_version(error: ClientError, max_claims: u64) -> Result<u64, ClientError> {
+    let claimed_version = match error {
+        ClientError::NoSuchData => return Err(ClientError::InvalidInvitation),
+        ClientError::AccessDenied => {
+            return Err(ClientError::from("Invitation has been revoked".to_string()))
+        }
+        ClientError::InvalidEntryActions(ref entry_errors) => {
+            match entry_errors.get(INVITE_CLAIMED_KEY) {
+                Some(&EntryError::EntryExists(version)) |
+                Some(&EntryError::InvalidSuccessor(version)) => u64::from(version),
+                Some(&EntryError::NoSuchEntry) |
+                None => {
+                    return Err(ClientError::from(
+                        format!("Error claiming invitation: {:?}", error),
+                    ))
+                }
+            }
+        }
+        _ => return Err(ClientError::from(format!("Error claiming invitation: {:?}", error))),
+    };
+
+    // The first claim inserts the entry at version zero.
+    if claimed_version + 1 < max_claims {
+        Ok(claimed_version + 1)
+    } else {
+        Err(ClientError::InvitationAlreadyClaimed)
+    }
+}
@@ -21,6 +21,7 @@ use maidsafe_utilities::serialisation::{deserialise, serialise};
 use rand;
 use routing::{AccountInfo, MAX_IMMUTABLE_DATA_SIZE_IN_BYTES, MAX_MUTABLE_DATA_ENTRIES,
               MAX_MUTABLE_DATA_SIZE_IN_BYTES, Request, Response, Value};
+use tempdir::TempDir;
 use test_utils;
 use vault::Refresh as VaultRefresh;
 
@@ -35,7 +36,7 @@ fn account_basics() {
     let dst = test_utils::gen_client_manager_authority(client_key);
 
     let mut node = test_utils::new_routing_node(GROUP_SIZE);
-    let mut mm = MaidManager::new(GROUP_SIZE, None, false);
+    let mut mm = unwrap!(MaidManager::new(GROUP_SIZE, &Config::default()));
 
     // Retrieving account info for non-existing account fails.
     let res = get_account_info(&mut node, &mut mm, src, dst);
@@ -57,7 +58,7 @@ fn idata_basics() {
     let client_manager = test_utils::gen_client_manager_authority(client_key);
 
     let mut node = test_utils::new_routing_node(GROUP_SIZE);
-    let mut mm = MaidManager::new(GROUP_SIZE, None, false);
+    let mut mm = unwrap!(MaidManager::new(GROUP_SIZE, &Config::default()));
 
     // Create account and retrieve the current account info.
     let _ = create_account(&mut node, &mut mm, client, client_manager);
@@ -108,7 +109,7 @@ fn mdata_basics() {
     let client_manager = test_utils::gen_client_manager_authority(client_key);
 
     let mut node = test_utils::new_routing_node(GROUP_SIZE);
-    let mut mm = MaidManager::new(GROUP_SIZE, None, false);
+    let mut mm = unwrap!(MaidManager::new(GROUP_SIZE, &Config::default()));
 
     // Create account and retrieve the current account info.
     let _ = create_account(&mut node, &mut mm, client, client_manager);
@@ -195,7 +196,7 @@ fn mdata_permissions_and_owners() {
     let client_manager = test_utils::gen_client_manager_authority(client_key);
 
     let mut node = test_utils::new_routing_node(GROUP_SIZE);
-    let mut mm = MaidManager::new(GROUP_SIZE, None, false);
+    let mut mm = unwrap!(MaidManager::new(GROUP_SIZE, &Config::default()));
 
     let _ = create_account(&mut node, &mut mm, client, client_manager);
 
@@ -359,7 +360,7 @@ fn auth_keys() {
     let (_, app_key) = test_utils::gen_client_authority();
 
     let mut node = test_utils::new_routing_node(GROUP_SIZE);
-    let mut mm = MaidManager::new(GROUP_SIZE, None, false);
+    let mut mm = unwrap!(MaidManager::new(GROUP_SIZE, &Config::default()));
 
     // Create owner account
     let _ = create_account(&mut node, &mut mm, owner_client, owner_client_manager);
@@ -431,6 +432,185 @@ fn auth_keys() {
     assert_eq!(version, 1);
 }
 
+#[test]
+fn auth_key_scopes() {
+    let mut rng = rand::thread_rng();
+
+    let (owner_client, owner_key) = test_utils::gen_client_authority();
+    let owner_client_manager = test_utils::gen_client_manager_authority(owner_key);
+    let (app_client, app_key) = test_utils::gen_client_authority();
+
+    let mut node = test_utils::new_routing_node(GROUP_SIZE);
+    let mut mm = unwrap!(MaidManager::new(GROUP_SIZE, &Config::default()));
+    let _ = create_account(&mut node, &mut mm, owner_client, owner_client_manager);
+
+    let msg_id = MessageId::new();
+    unwrap!(mm.handle_ins_auth_key(&mut node,
+                                   owner_client,
+                                   owner_client_manager,
+                                   app_key,
+                                   1,
+                                   msg_id));
+    assert_match!(unwrap!(node.sent_responses.remove(&msg_id)).response,
+                  Response::InsAuthKey { res: Ok(()), .. });
+    simulate_refresh(&mut node, &mut mm, msg_id, 1);
+
+    let scope = KeyScope {
+        ops: Some(vec![DataOpType::PutIData].into_iter().collect()),
+        type_tags: None,
+        max_ops_count: Some(1),
+        expires_at: None,
+    };
+    unwrap!(unwrap!(mm.accounts.get_mut(owner_client_manager.name()))
+                .set_key_scope(app_key, Some(scope)));
+
+    // The app can't put mutable data.
+    let mdata = test_utils::gen_mutable_data(TEST_TAG, 0, owner_key, &mut rng);
+    let msg_id = MessageId::new();
+    unwrap!(mm.handle_put_mdata(&mut node,
+                                app_client,
+                                owner_client_manager,
+                                mdata.clone(),
+                                msg_id,
+                                app_key));
+    assert_match!(unwrap!(node.sent_responses.remove(&msg_id)).response,
+                  Response::PutMData { res: Err(ClientError::AccessDenied), .. });
+
+    // But it can put immutable data, as many times as its budget allows.
+    let msg_id = MessageId::new();
+    let idata = test_utils::gen_immutable_data(10, &mut rng);
+    unwrap!(mm.handle_put_idata(&mut node, app_client, owner_client_manager, idata, msg_id));
+    assert!(node.sent_requests.remove(&msg_id).is_some());
+    unwrap!(mm.handle_put_idata_response(&mut node, Ok(()), msg_id));
+    let message = unwrap!(node.sent_requests.remove(&msg_id));
+    let refresh = assert_match!(message.request, Request::Refresh(payload, ..) => payload);
+    let refresh: Refresh = unwrap!(deserialise(&refresh));
+    let key = assert_match!(refresh.clone(), Refresh::InsertDataOp(_, _, Some(key)) => key);
+    assert_eq!(key, app_key);
+    unwrap!(mm.handle_refresh(&mut node, refresh, msg_id, None));
+
+    let msg_id = MessageId::new();
+    let idata = test_utils::gen_immutable_data(10, &mut rng);
+    unwrap!(mm.handle_put_idata(&mut node, app_client, owner_client_manager, idata, msg_id));
+    assert_match!(unwrap!(node.sent_responses.remove(&msg_id)).response,
+                  Response::PutIData { res: Err(ClientError::AccessDenied), .. });
+
+    // Deleting the key deletes its scope too, so reinserting it lets the app do anything again.
+    let msg_id = MessageId::new();
+    unwrap!(mm.handle_del_auth_key(&mut node,
+                                   owner_client,
+                                   owner_client_manager,
+                                   app_key,
+                                   2,
+                                   msg_id));
+    assert_match!(unwrap!(node.sent_responses.remove(&msg_id)).response,
+                  Response::DelAuthKey { res: Ok(()), .. });
+    simulate_refresh(&mut node, &mut mm, msg_id, 1);
+
+    let msg_id = MessageId::new();
+    unwrap!(mm.handle_ins_auth_key(&mut node,
+                                   owner_client,
+                                   owner_client_manager,
+                                   app_key,
+                                   3,
+                                   msg_id));
+    assert_match!(unwrap!(node.sent_responses.remove(&msg_id)).response,
+                  Response::InsAuthKey { res: Ok(()), .. });
+    simulate_refresh(&mut node, &mut mm, msg_id, 1);
+    assert!(mm.accounts[owner_client_manager.name()].key_scopes.is_empty());
+
+    let msg_id = MessageId::new();
+    unwrap!(mm.handle_put_mdata(&mut node,
+                                app_client,
+                                owner_client_manager,
+                                mdata,
+                                msg_id,
+                                app_key));
+    let message = unwrap!(node.sent_requests.remove(&msg_id));
+    assert_match!(message.request, Request::PutMData { .. });
+}
+
+#[test]
+fn auth_key_expiry() {
+    let (owner_client, owner_key) = test_utils::gen_client_authority();
+    let owner_client_manager = test_utils::gen_client_manager_authority(owner_key);
+    let (app_client, app_key) = test_utils::gen_client_authority();
+
+    let mut node = test_utils::new_routing_node(GROUP_SIZE);
+    let mut mm = unwrap!(MaidManager::new(GROUP_SIZE, &Config::default()));
+    let _ = create_account(&mut node, &mut mm, owner_client, owner_client_manager);
+
+    let msg_id = MessageId::new();
+    unwrap!(mm.handle_ins_auth_key(&mut node,
+                                   owner_client,
+                                   owner_client_manager,
+                                   app_key,
+                                   1,
+                                   msg_id));
+    assert_match!(unwrap!(node.sent_responses.remove(&msg_id)).response,
+                  Response::InsAuthKey { res: Ok(()), .. });
+
+    // The key has already expired, beyond the clock skew margin, and the rounding of the time the
+    // client managers agree to remove it by.
+    let scope = KeyScope {
+        expires_at: Some(utils::now_secs() - 2 * KEY_EXPIRY_MARGIN_SECS - 1),
+        ..Default::default()
+    };
+    unwrap!(unwrap!(mm.accounts.get_mut(owner_client_manager.name()))
+                .set_key_scope(app_key, Some(scope)));
+    let refresh_msg_id = msg_id;
+
+    // The expired key can't be used, and isn't listed.
+    let msg_id = MessageId::new();
+    let idata = test_utils::gen_immutable_data(10, &mut rand::thread_rng());
+    unwrap!(mm.handle_put_idata(&mut node, app_client, owner_client_manager, idata, msg_id));
+    assert_match!(unwrap!(node.sent_responses.remove(&msg_id)).response,
+                  Response::PutIData { res: Err(ClientError::AccessDenied), .. });
+
+    let msg_id = MessageId::new();
+    unwrap!(mm.handle_list_auth_keys_and_version(&mut node,
+                                                 owner_client,
+                                                 owner_client_manager,
+                                                 msg_id));
+    let (auth_keys, version) = assert_match!(unwrap!(node.sent_responses.remove(&msg_id)).response,
+                      Response::ListAuthKeysAndVersion { res: Ok(ok), .. } => ok);
+    assert!(auth_keys.is_empty());
+    assert_eq!(version, 1);
+
+    // Other keys refreshes don't remove it, so all the client managers keep the same keys.
+    simulate_refresh(&mut node, &mut mm, refresh_msg_id, 1);
+    assert!(mm.accounts[owner_client_manager.name()].keys.contains(&app_key));
+
+    // The client managers remove it once the group agrees on the time, without changing the
+    // version of the keys.
+    unwrap!(mm.handle_tick(&mut node));
+    assert!(mm.accounts[owner_client_manager.name()].keys.contains(&app_key));
+    assert_eq!(node.sent_requests.len(), 1);
+    let msg_id = *unwrap!(node.sent_requests.keys().next());
+    simulate_refresh(&mut node, &mut mm, msg_id, 1);
+    {
+        let account = &mm.accounts[owner_client_manager.name()];
+        assert!(account.keys.is_empty());
+        assert!(account.key_scopes.is_empty());
+        assert_eq!(account.keys_ops_count, 1);
+    }
+
+    unwrap!(mm.handle_tick(&mut node));
+    assert!(node.sent_requests.is_empty());
+
+    // So a keys operation of the client racing with the removal still succeeds.
+    let (_, other_app_key) = test_utils::gen_client_authority();
+    let msg_id = MessageId::new();
+    unwrap!(mm.handle_ins_auth_key(&mut node,
+                                   owner_client,
+                                   owner_client_manager,
+                                   other_app_key,
+                                   2,
+                                   msg_id));
+    assert_match!(unwrap!(node.sent_responses.remove(&msg_id)).response,
+                  Response::InsAuthKey { res: Ok(()), .. });
+}
+
 #[test]
 fn mutation_authorisation() {
     let mut rng = rand::thread_rng();
@@ -440,7 +620,7 @@ fn mutation_authorisation() {
     let (app_client, app_key) = test_utils::gen_client_authority();
 
     let mut node = test_utils::new_routing_node(GROUP_SIZE);
-    let mut mm = MaidManager::new(GROUP_SIZE, None, false);
+    let mut mm = unwrap!(MaidManager::new(GROUP_SIZE, &Config::default()));
 
     // Create owner account
     let _ = create_account(&mut node, &mut mm, owner_client, owner_client_manager);
@@ -650,13 +830,13 @@ fn account_replication_during_churn() {
     let client_manager = test_utils::gen_client_manager_authority(client_key);
 
     let mut old_node = test_utils::new_routing_node(GROUP_SIZE);
-    let mut old_mm = MaidManager::new(GROUP_SIZE, None, false);
+    let mut old_mm = unwrap!(MaidManager::new(GROUP_SIZE, &Config::default()));
 
     let op_msg_id = create_account(&mut old_node, &mut old_mm, client, client_manager);
     let old_info = unwrap!(get_account_info(&mut old_node, &mut old_mm, client, client_manager));
 
     let mut new_node = test_utils::new_routing_node(GROUP_SIZE);
-    let mut new_mm = MaidManager::new(GROUP_SIZE, None, false);
+    let mut new_mm = unwrap!(MaidManager::new(GROUP_SIZE, &Config::default()));
     let new_node_name = *unwrap!(new_node.id()).name();
 
     // The new node doesn't have the account initially.
@@ -682,7 +862,10 @@ fn account_replication_during_churn() {
     let _ = msg_id_list.insert(op_msg_id);
     let refresh_data_ops = Refresh::UpdateDataOps {
         name: *client_manager.name(),
+        count: 1,
+        bytes: 10,
         msg_ids: msg_id_list,
+        key_ops_counts: BTreeMap::new(),
     };
     let serialised_refresh = unwrap!(serialise(&refresh_data_ops));
 
@@ -708,12 +891,475 @@ fn account_replication_during_churn() {
     assert_eq!(new_info, old_info);
 }
 
+#[test]
+fn accounts_should_survive_restart() {
+    let (client, client_key) = test_utils::gen_client_authority();
+    let client_manager = test_utils::gen_client_manager_authority(client_key);
+    let (_, app_key) = test_utils::gen_client_authority();
+    let temp_dir = unwrap!(TempDir::new("safe_vault_test"));
+    let config = Config {
+        account_db_dir: Some(unwrap!(temp_dir.path().to_str()).to_string()),
+        ..Default::default()
+    };
+
+    let mut node = test_utils::new_routing_node(GROUP_SIZE);
+    let old_info = {
+        let mut mm = unwrap!(MaidManager::new(GROUP_SIZE, &config));
+        let _ = create_account(&mut node, &mut mm, client, client_manager);
+
+        let msg_id = MessageId::new();
+        unwrap!(mm.handle_ins_auth_key(&mut node, client, client_manager, app_key, 1, msg_id));
+        simulate_refresh(&mut node, &mut mm, msg_id, 1);
+        unwrap!(get_account_info(&mut node, &mut mm, client, client_manager))
+    };
+
+    // A new instance reloads the accounts.
+    let mut mm = unwrap!(MaidManager::new(GROUP_SIZE, &config));
+    let new_info = unwrap!(get_account_info(&mut node, &mut mm, client, client_manager));
+    assert_eq!(new_info, old_info);
+
+    let msg_id = MessageId::new();
+    unwrap!(mm.handle_list_auth_keys_and_version(&mut node, client, client_manager, msg_id));
+    let (auth_keys, version) = assert_match!(unwrap!(node.sent_responses.remove(&msg_id)).response,
+                      Response::ListAuthKeysAndVersion { res: Ok(ok), .. } => ok);
+    assert!(auth_keys.contains(&app_key));
+    assert_eq!(version, 1);
+}
+
+#[test]
+fn account_tiers() {
+    let (admin, admin_key) = test_utils::gen_client_authority();
+    let admin_manager = test_utils::gen_client_manager_authority(admin_key);
+    let (client, client_key) = test_utils::gen_client_authority();
+    let client_manager = test_utils::gen_client_manager_authority(client_key);
+
+    let mut account_tiers = BTreeMap::new();
+    let _ = account_tiers.insert(
+        "partner".to_string(),
+        AccountTier {
+            max_ops_count: 100,
+            max_bytes: None,
+        },
+    );
+    let config = Config {
+        max_ops_count: Some(10),
+        account_tiers: Some(account_tiers),
+        ..Default::default()
+    };
+    let admin_config = Config {
+        invite_key: Some(admin_key.0),
+        ..config.clone()
+    };
+
+    let mut node = test_utils::new_routing_node(GROUP_SIZE);
+    let mut mm = unwrap!(MaidManager::new(GROUP_SIZE, &config));
+    let mut admin_mm = unwrap!(MaidManager::new(GROUP_SIZE, &admin_config));
+
+    // The configured default limit applies.
+    let _ = create_account(&mut node, &mut mm, client, client_manager);
+    let account_info = unwrap!(get_account_info(&mut node, &mut mm, client, client_manager));
+    assert_eq!(account_info.mutations_available, 9);
+
+    let tier_data = |tier: &str| {
+        let mut entries = BTreeMap::new();
+        let value = Value {
+            content: tier.as_bytes().to_vec(),
+            entry_version: 0,
+        };
+        let _ = entries.insert(ACCOUNT_TIER_KEY.to_vec(), value);
+        let mut owners = BTreeSet::new();
+        let _ = owners.insert(admin_key);
+        unwrap!(MutableData::new(
+            *client_manager.name(),
+            TYPE_TAG_ACCOUNT_TIER,
+            Default::default(),
+            entries,
+            owners,
+        ))
+    };
+
+    // Only the admin can assign tiers, and only known ones.
+    let msg_id = MessageId::new();
+    unwrap!(mm.handle_put_mdata(&mut node, admin, admin_manager, tier_data("partner"), msg_id,
+                                admin_key));
+    assert_match!(unwrap!(node.sent_responses.remove(&msg_id)).response,
+                  Response::PutMData { res: Err(ClientError::InvalidOperation), .. });
+
+    let msg_id = MessageId::new();
+    unwrap!(admin_mm.handle_put_mdata(&mut node, admin, admin_manager, tier_data("gold"), msg_id,
+                                      admin_key));
+    assert_match!(unwrap!(node.sent_responses.remove(&msg_id)).response,
+                  Response::PutMData { res: Err(_), .. });
+
+    // The admin's client managers tell the client's ones about the tier.
+    let msg_id = MessageId::new();
+    unwrap!(admin_mm.handle_put_mdata(&mut node, admin, admin_manager, tier_data("partner"),
+                                      msg_id, admin_key));
+    assert_match!(unwrap!(node.sent_responses.remove(&msg_id)).response,
+                  Response::PutMData { res: Ok(()), .. });
+    let message = unwrap!(node.sent_requests.remove(&msg_id));
+    assert_eq!(message.dst, Authority::from(client_manager));
+    let refresh = assert_match!(message.request, Request::Refresh(payload, ..) => payload);
+    unwrap!(mm.handle_serialised_refresh(&mut node, &refresh, msg_id, None));
+
+    let account_info = unwrap!(get_account_info(&mut node, &mut mm, client, client_manager));
+    assert_eq!(account_info.mutations_available, 99);
+}
+
+#[test]
+fn admin_roles() {
+    let (root, root_key) = test_utils::gen_client_authority();
+    let root_manager = test_utils::gen_client_manager_authority(root_key);
+    let (admin, admin_key) = test_utils::gen_client_authority();
+    let admin_manager = test_utils::gen_client_manager_authority(admin_key);
+    let root_config = Config {
+        invite_key: Some(root_key.0),
+        ..Default::default()
+    };
+
+    let mut node = test_utils::new_routing_node(GROUP_SIZE);
+    let mut mm = unwrap!(MaidManager::new(GROUP_SIZE, &Config::default()));
+    let mut root_mm = unwrap!(MaidManager::new(GROUP_SIZE, &root_config));
+    let _ = create_account(&mut node, &mut mm, admin, admin_manager);
+
+    let roles_data = |owner: sign::PublicKey, roles: &[&str]| {
+        let entries = roles
+            .iter()
+            .map(|role| {
+                let value = Value {
+                    content: Vec::new(),
+                    entry_version: 0,
+                };
+                (role.as_bytes().to_vec(), value)
+            })
+            .collect();
+        let mut owners = BTreeSet::new();
+        let _ = owners.insert(owner);
+        unwrap!(MutableData::new(
+            *admin_manager.name(),
+            TYPE_TAG_ADMIN_ROLES,
+            Default::default(),
+            entries,
+            owners,
+        ))
+    };
+    let put_invite = |node: &mut RoutingNode, mm: &mut MaidManager| {
+        let mut rng = rand::thread_rng();
+        let data = test_utils::gen_mutable_data(TYPE_TAG_INVITE, 0, admin_key, &mut rng);
+        let msg_id = MessageId::new();
+        unwrap!(mm.handle_put_mdata(node, admin, admin_manager, data, msg_id, admin_key));
+        if node.sent_requests.remove(&msg_id).is_some() {
+            Ok(())
+        } else {
+            let message = unwrap!(node.sent_responses.remove(&msg_id));
+            assert_match!(message.response, Response::PutMData { res, .. } => res)
+        }
+    };
+    let mut grant = |node: &mut RoutingNode, mm: &mut MaidManager, data: MutableData| {
+        let msg_id = MessageId::new();
+        unwrap!(root_mm.handle_put_mdata(node, root, root_manager, data, msg_id, root_key));
+        let res = assert_match!(unwrap!(node.sent_responses.remove(&msg_id)).response,
+                                Response::PutMData { res, .. } => res);
+        if res.is_ok() {
+            let message = unwrap!(node.sent_requests.remove(&msg_id));
+            assert_eq!(message.dst, Authority::from(admin_manager));
+            let refresh = assert_match!(message.request, Request::Refresh(payload, ..) => payload);
+            unwrap!(mm.handle_serialised_refresh(node, &refresh, msg_id, None));
+        }
+        res
+    };
+
+    // Accounts aren't admins to begin with.
+    assert_match!(put_invite(&mut node, &mut mm), Err(ClientError::InvalidOperation));
+
+    // Only the root admin can grant roles, and only known ones.
+    let msg_id = MessageId::new();
+    let data = roles_data(admin_key, &[AdminRole::InviteIssuer.name()]);
+    unwrap!(mm.handle_put_mdata(&mut node, admin, admin_manager, data, msg_id, admin_key));
+    assert_match!(unwrap!(node.sent_responses.remove(&msg_id)).response,
+                  Response::PutMData { res: Err(ClientError::InvalidOperation), .. });
+
+    let data = roles_data(root_key, &["superuser"]);
+    assert_match!(grant(&mut node, &mut mm, data), Err(ClientError::NetworkOther(_)));
+
+    // The root admin's client managers tell the admin's ones about the roles.
+    let data = roles_data(root_key, &[AdminRole::InviteIssuer.name()]);
+    unwrap!(grant(&mut node, &mut mm, data));
+    unwrap!(put_invite(&mut node, &mut mm));
+
+    // Removing the roles takes the permissions away again.
+    unwrap!(grant(&mut node, &mut mm, roles_data(root_key, &[])));
+    assert_match!(put_invite(&mut node, &mut mm), Err(ClientError::InvalidOperation));
+    assert_eq!(mm.accounts[admin_manager.name()].admin_roles_version, 2);
+}
+
+#[test]
+fn group_refreshes_from_single_nodes_are_ignored() {
+    let (client, client_key) = test_utils::gen_client_authority();
+    let client_manager = test_utils::gen_client_manager_authority(client_key);
+
+    let mut node = test_utils::new_routing_node(GROUP_SIZE);
+    let mut mm = unwrap!(MaidManager::new(GROUP_SIZE, &Config::default()));
+    let _ = create_account(&mut node, &mut mm, client, client_manager);
+
+    // A single node can't grant admin roles on its own.
+    let mut roles = BTreeSet::new();
+    let _ = roles.insert(AdminRole::InviteIssuer);
+    let refresh = Refresh::SetAdminRoles {
+        name: *client_manager.name(),
+        roles,
+    };
+    unwrap!(mm.handle_refresh(&mut node, refresh, MessageId::new(), Some(XorName(rand::random()))));
+    assert!(mm.accounts[client_manager.name()].admin_roles.is_empty());
+    assert_eq!(mm.accounts[client_manager.name()].admin_roles_version, 0);
+}
+
+#[test]
+fn account_suspension() {
+    let (root, root_key) = test_utils::gen_client_authority();
+    let root_manager = test_utils::gen_client_manager_authority(root_key);
+    let (client, client_key) = test_utils::gen_client_authority();
+    let client_manager = test_utils::gen_client_manager_authority(client_key);
+    let root_config = Config {
+        invite_key: Some(root_key.0),
+        ..Default::default()
+    };
+
+    let mut node = test_utils::new_routing_node(GROUP_SIZE);
+    let mut mm = unwrap!(MaidManager::new(GROUP_SIZE, &Config::default()));
+    let mut root_mm = unwrap!(MaidManager::new(GROUP_SIZE, &root_config));
+    let _ = create_account(&mut node, &mut mm, client, client_manager);
+
+    let op_data = |owner: sign::PublicKey, op: &str| {
+        let mut entries = BTreeMap::new();
+        let value = Value {
+            content: op.as_bytes().to_vec(),
+            entry_version: 0,
+        };
+        let _ = entries.insert(ACCOUNT_OP_KEY.to_vec(), value);
+        let mut owners = BTreeSet::new();
+        let _ = owners.insert(owner);
+        unwrap!(MutableData::new(
+            *client_manager.name(),
+            TYPE_TAG_ACCOUNT_OP,
+            Default::default(),
+            entries,
+            owners,
+        ))
+    };
+    let mut perform = |node: &mut RoutingNode, mm: &mut MaidManager, op: AccountOp| {
+        let msg_id = MessageId::new();
+        let data = op_data(root_key, op.name());
+        unwrap!(root_mm.handle_put_mdata(node, root, root_manager, data, msg_id, root_key));
+        assert_match!(unwrap!(node.sent_responses.remove(&msg_id)).response,
+                      Response::PutMData { res: Ok(()), .. });
+        let message = unwrap!(node.sent_requests.remove(&msg_id));
+        assert_eq!(message.dst, Authority::from(client_manager));
+        let refresh = assert_match!(message.request, Request::Refresh(payload, ..) => payload);
+        unwrap!(mm.handle_serialised_refresh(node, &refresh, msg_id, None));
+    };
+    let put_idata = |node: &mut RoutingNode, mm: &mut MaidManager| {
+        let data = test_utils::gen_immutable_data(10, &mut rand::thread_rng());
+        let msg_id = MessageId::new();
+        unwrap!(mm.handle_put_idata(node, client, client_manager, data, msg_id));
+        if node.sent_requests.remove(&msg_id).is_some() {
+            Ok(())
+        } else {
+            let message = unwrap!(node.sent_responses.remove(&msg_id));
+            assert_match!(message.response, Response::PutIData { res, .. } => res)
+        }
+    };
+
+    // Only admins can perform account operations.
+    let msg_id = MessageId::new();
+    let data = op_data(client_key, AccountOp::Suspend.name());
+    unwrap!(mm.handle_put_mdata(&mut node, client, client_manager, data, msg_id, client_key));
+    assert_match!(unwrap!(node.sent_responses.remove(&msg_id)).response,
+                  Response::PutMData { res: Err(ClientError::InvalidOperation), .. });
+
+    // Suspended accounts can't mutate anything.
+    perform(&mut node, &mut mm, AccountOp::Suspend);
+    match put_idata(&mut node, &mut mm) {
+        Err(ClientError::NetworkOther(ref message)) if message == ACCOUNT_SUSPENDED_ERROR => (),
+        res => panic!("Unexpected result {:?}", res),
+    }
+    let (_, app_key) = test_utils::gen_client_authority();
+    let msg_id = MessageId::new();
+    unwrap!(mm.handle_ins_auth_key(&mut node, client, client_manager, app_key, 1, msg_id));
+    assert_match!(unwrap!(node.sent_responses.remove(&msg_id)).response,
+                  Response::InsAuthKey { res: Err(ClientError::NetworkOther(_)), .. });
+
+    // Until they are resumed.
+    perform(&mut node, &mut mm, AccountOp::Resume);
+    unwrap!(put_idata(&mut node, &mut mm));
+    assert_eq!(mm.accounts[client_manager.name()].suspension_version, 2);
+
+    // Deleted accounts are gone.
+    perform(&mut node, &mut mm, AccountOp::Delete);
+    let res = get_account_info(&mut node, &mut mm, client, client_manager);
+    assert_match!(res, Err(ClientError::NoSuchAccount));
+}
+
+#[test]
+fn deleted_accounts_are_not_recreated() {
+    let (client, client_key) = test_utils::gen_client_authority();
+    let client_manager = test_utils::gen_client_manager_authority(client_key);
+    let (_, app_key) = test_utils::gen_client_authority();
+    let temp_dir = unwrap!(TempDir::new("safe_vault_test"));
+    let config = Config {
+        account_db_dir: Some(unwrap!(temp_dir.path().to_str()).to_string()),
+        ..Default::default()
+    };
+
+    let mut node = test_utils::new_routing_node(GROUP_SIZE);
+    let mut mm = unwrap!(MaidManager::new(GROUP_SIZE, &config));
+    let _ = create_account(&mut node, &mut mm, client, client_manager);
+
+    // A request still in flight when the account is deleted isn't charged to it.
+    let data = test_utils::gen_immutable_data(10, &mut rand::thread_rng());
+    let put_msg_id = MessageId::new();
+    unwrap!(mm.handle_put_idata(&mut node, client, client_manager, data, put_msg_id));
+    assert!(node.sent_requests.remove(&put_msg_id).is_some());
+
+    let refresh = Refresh::AccountOp {
+        name: *client_manager.name(),
+        op: AccountOp::Delete,
+    };
+    unwrap!(mm.handle_refresh(&mut node, refresh, MessageId::new(), None));
+    assert!(!mm.accounts.contains_key(client_manager.name()));
+    assert!(mm.request_cache.is_empty());
+    assert!(mm.handle_put_idata_response(&mut node, Ok(()), put_msg_id).is_err());
+    assert!(node.sent_requests.remove(&put_msg_id).is_none());
+
+    // Neither do the refreshes of the other client managers bring it back.
+    let mut keys = BTreeSet::new();
+    let _ = keys.insert(app_key);
+    let refreshes = vec![
+        Refresh::InsertDataOp(*client_manager.name(), 10, None),
+        Refresh::UpdateKeys {
+            name: *client_manager.name(),
+            ops_count: 1,
+            keys,
+            scopes: BTreeMap::new(),
+            expired_by: 0,
+        },
+        Refresh::UpdateSuspension {
+            name: *client_manager.name(),
+            suspended: false,
+            version: 1,
+        },
+    ];
+    for refresh in refreshes {
+        unwrap!(mm.handle_refresh(&mut node, refresh, MessageId::new(), None));
+    }
+    let refresh = Refresh::UpdateDataOps {
+        name: *client_manager.name(),
+        count: 1,
+        bytes: 0,
+        msg_ids: BTreeSet::new(),
+        key_ops_counts: BTreeMap::new(),
+    };
+    for _ in 0..QUORUM {
+        unwrap!(mm.handle_refresh(&mut node,
+                                  refresh.clone(),
+                                  MessageId::new(),
+                                  Some(XorName(rand::random()))));
+    }
+    assert!(!mm.accounts.contains_key(client_manager.name()));
+
+    // Nor does a restart.
+    drop(mm);
+    let mm = unwrap!(MaidManager::new(GROUP_SIZE, &config));
+    assert!(!mm.accounts.contains_key(client_manager.name()));
+    assert!(mm.deleted_accounts.contains(client_manager.name()));
+}
+
+#[test]
+fn byte_quota() {
+    let (client, client_key) = test_utils::gen_client_authority();
+    let client_manager = test_utils::gen_client_manager_authority(client_key);
+    let config = Config {
+        max_bytes: Some(1000),
+        ..Default::default()
+    };
+
+    let mut node = test_utils::new_routing_node(GROUP_SIZE);
+    let mut mm = unwrap!(MaidManager::new(GROUP_SIZE, &config));
+
+    // The account packet is charged too.
+    let _ = create_account(&mut node, &mut mm, client, client_manager);
+    let bytes_stored = mm.accounts[client_manager.name()].bytes_stored;
+    assert!(bytes_stored > 0);
+    assert!(bytes_stored < 500);
+
+    let put_idata = |node: &mut RoutingNode, mm: &mut MaidManager, size: usize| {
+        let data = test_utils::gen_immutable_data(size, &mut rand::thread_rng());
+        let bytes = serialisation::serialised_size(&data);
+        let msg_id = MessageId::new();
+        unwrap!(mm.handle_put_idata(node, client, client_manager, data, msg_id));
+        if node.sent_requests.remove(&msg_id).is_none() {
+            let message = unwrap!(node.sent_responses.remove(&msg_id));
+            return assert_match!(message.response, Response::PutIData { res, .. } => res)
+                .map(|()| bytes);
+        }
+        unwrap!(mm.handle_put_idata_response(node, Ok(()), msg_id));
+        let _ = node.sent_responses.remove(&msg_id);
+        simulate_refresh(node, mm, msg_id, 1);
+        Ok(bytes)
+    };
+
+    // Data is charged its serialised size.
+    let bytes = unwrap!(put_idata(&mut node, &mut mm, 500));
+    assert!(bytes >= 500);
+    let bytes_stored = bytes_stored + bytes;
+    assert_eq!(mm.accounts[client_manager.name()].bytes_stored, bytes_stored);
+
+    // Data which doesn't fit is rejected, but the mutations aren't used up.
+    match put_idata(&mut node, &mut mm, 500) {
+        Err(ClientError::NetworkOther(ref message)) if message == STORAGE_LIMIT_ERROR => (),
+        res => panic!("Unexpected result {:?}", res),
+    }
+    let account_info = unwrap!(get_account_info(&mut node, &mut mm, client, client_manager));
+    assert!(account_info.mutations_available > 0);
+
+    // Mutating entries is charged the change in size reported by the data managers, not upfront.
+    let actions: BTreeMap<_, _> = EntryActions::new()
+        .update(vec![0; 10], vec![1; 10], 1)
+        .del(vec![1; 10], 1)
+        .into();
+    let msg_id = MessageId::new();
+    unwrap!(mm.handle_mutate_mdata_entries(&mut node,
+                                           client,
+                                           client_manager,
+                                           rand::random(),
+                                           TEST_TAG,
+                                           actions,
+                                           msg_id,
+                                           client_key));
+    let _ = unwrap!(node.sent_requests.remove(&msg_id));
+    unwrap!(mm.handle_mutate_mdata_entries_response(&mut node, Ok(()), msg_id));
+    let _ = node.sent_responses.remove(&msg_id);
+    simulate_refresh(&mut node, &mut mm, msg_id, 1);
+    assert_eq!(mm.accounts[client_manager.name()].bytes_stored, bytes_stored);
+
+    // Deleted and shrunk entries are refunded, and grown ones charged.
+    let change = unwrap!(serialise(&-400i64));
+    unwrap!(mm.handle_serialised_bytes_change(*client_manager.name(), &change));
+    assert_eq!(mm.accounts[client_manager.name()].bytes_stored, bytes_stored - 400);
+    let change = unwrap!(serialise(&100i64));
+    unwrap!(mm.handle_serialised_bytes_change(*client_manager.name(), &change));
+    let bytes_stored = bytes_stored - 300;
+    assert_eq!(mm.accounts[client_manager.name()].bytes_stored, bytes_stored);
+
+    unwrap!(put_idata(&mut node, &mut mm, 1000 - bytes_stored as usize - 100));
+}
+
 #[test]
 fn limits() {
     let mut rng = rand::thread_rng();
 
     let mut node = test_utils::new_routing_node(GROUP_SIZE);
-    let mut mm = MaidManager::new(GROUP_SIZE, None, false);
+    let mut mm = unwrap!(MaidManager::new(GROUP_SIZE, &Config::default()));
 
     let (client, client_key) = test_utils::gen_client_authority();
     let client_manager = test_utils::gen_client_manager_authority(client_key);
@@ -787,13 +1433,114 @@ fn limits() {
                   Response::PutMData { res: Err(ClientError::DataTooLarge), .. });
 }
 
+#[test]
+fn invite_claims() {
+    let (client, client_key) = test_utils::gen_client_authority();
+    let client_manager = test_utils::gen_client_manager_authority(client_key);
+    let (invite_key, _) = sign::gen_keypair();
+    let config = Config {
+        invite_key: Some(invite_key.0),
+        ..Default::default()
+    };
+
+    let mut node = test_utils::new_routing_node(GROUP_SIZE);
+    let mut mm = unwrap!(MaidManager::new(GROUP_SIZE, &config));
+
+    // Malformed invitations are rejected.
+    let msg_id = claim_invite(&mut node, &mut mm, "invite:xyz");
+    assert!(node.sent_requests.remove(&msg_id).is_none());
+    assert_match!(unwrap!(node.sent_responses.remove(&msg_id)).response,
+                  Response::PutMData { res: Err(ClientError::InvalidInvitation), .. });
+
+    let invite = Invite {
+        code: "cohort".to_string(),
+        expires_at: None,
+        max_claims: 2,
+    };
+    let invitation_string = invite.encode();
+    let invite_name = Invite::name(&invitation_string);
+
+    let claimed_error = |version| {
+        let mut entry_errors = BTreeMap::new();
+        let _ = entry_errors.insert(INVITE_CLAIMED_KEY.to_vec(), EntryError::EntryExists(version));
+        ClientError::InvalidEntryActions(entry_errors)
+    };
+
+    // The first claim inserts the `claimed` entry, and then the account is created.
+    let msg_id = claim_invite(&mut node, &mut mm, &invitation_string);
+    let message = unwrap!(node.sent_requests.remove(&msg_id));
+    assert_eq!(message.dst, Authority::NaeManager(invite_name));
+    let actions = assert_match!(message.request,
+                                Request::MutateMDataEntries { actions, .. } => actions);
+    assert_match!(actions.get(INVITE_CLAIMED_KEY), Some(&EntryAction::Ins(_)));
+
+    unwrap!(mm.handle_mutate_mdata_entries_response(&mut node, Ok(()), msg_id));
+    assert_match!(unwrap!(node.sent_requests.remove(&msg_id)).request,
+                  Request::PutMData { .. });
+
+    // The second claim finds the entry inserted already, so updates it instead.
+    let msg_id = claim_invite(&mut node, &mut mm, &invitation_string);
+    assert!(node.sent_requests.remove(&msg_id).is_some());
+    unwrap!(mm.handle_mutate_mdata_entries_response(&mut node, Err(claimed_error(0)), msg_id));
+    let actions = assert_match!(unwrap!(node.sent_requests.remove(&msg_id)).request,
+                                Request::MutateMDataEntries { actions, .. } => actions);
+    assert_match!(actions.get(INVITE_CLAIMED_KEY),
+                  Some(&EntryAction::Update(ref value)) => assert_eq!(value.entry_version, 1));
+
+    unwrap!(mm.handle_mutate_mdata_entries_response(&mut node, Ok(()), msg_id));
+    assert_match!(unwrap!(node.sent_requests.remove(&msg_id)).request,
+                  Request::PutMData { .. });
+
+    // Then the invitation is used up.
+    let msg_id = claim_invite(&mut node, &mut mm, &invitation_string);
+    assert!(node.sent_requests.remove(&msg_id).is_some());
+    unwrap!(mm.handle_mutate_mdata_entries_response(&mut node, Err(claimed_error(1)), msg_id));
+    assert_match!(unwrap!(node.sent_responses.remove(&msg_id)).response,
+                  Response::PutMData { res: Err(ClientError::InvitationAlreadyClaimed), .. });
+
+    // Revoked invitations are denied access to.
+    let msg_id = claim_invite(&mut node, &mut mm, &invitation_string);
+    assert!(node.sent_requests.remove(&msg_id).is_some());
+    unwrap!(mm.handle_mutate_mdata_entries_response(&mut node,
+                                                    Err(ClientError::AccessDenied),
+                                                    msg_id));
+    assert_match!(unwrap!(node.sent_responses.remove(&msg_id)).response,
+                  Response::PutMData { res: Err(ClientError::NetworkOther(_)), .. });
+
+    // Expired invitations aren't claimed at all.
+    let expired = Invite {
+        expires_at: Some(1),
+        ..invite
+    };
+    let msg_id = claim_invite(&mut node, &mut mm, &expired.encode());
+    assert!(node.sent_requests.remove(&msg_id).is_none());
+    assert_match!(unwrap!(node.sent_responses.remove(&msg_id)).response,
+                  Response::PutMData { res: Err(ClientError::NetworkOther(_)), .. });
+
+    // Other clients can't mutate invitations directly.
+    let msg_id = MessageId::new();
+    let actions = EntryActions::new()
+        .update(INVITE_CLAIMED_KEY.to_vec(), INVITE_CLAIMED_VALUE.to_vec(), 1)
+        .into();
+    unwrap!(mm.handle_mutate_mdata_entries(&mut node,
+                                           client,
+                                           client_manager,
+                                           invite_name,
+                                           TYPE_TAG_INVITE,
+                                           actions,
+                                           msg_id,
+                                           client_key));
+    assert_match!(unwrap!(node.sent_responses.remove(&msg_id)).response,
+                  Response::MutateMDataEntries { res: Err(ClientError::InvalidOperation), .. });
+}
+
 #[test]
 fn refresh_data_ops_count() {
     let (client, client_key) = test_utils::gen_client_authority();
     let client_manager = test_utils::gen_client_manager_authority(client_key);
 
     let mut node = test_utils::new_routing_node(GROUP_SIZE);
-    let mut mm = MaidManager::new(GROUP_SIZE, None, false);
+    let mut mm = unwrap!(MaidManager::new(GROUP_SIZE, &Config::default()));
 
     // Create account and retrieve the current balance.
     let _ = create_account(&mut node, &mut mm, client, client_manager);
@@ -812,10 +1559,14 @@ fn refresh_data_ops_count() {
     let message = unwrap!(node.sent_requests.remove(&msg_id));
     let refresh = assert_match!(message.request, Request::Refresh(payload, ..) => payload);
     let refresh: Refresh = unwrap!(deserialise(&refresh));
-    let account_name = assert_match!(refresh, Refresh::InsertDataOp(name) => name);
+    let (account_name, bytes, app_key) = assert_match!(
+        refresh,
+        Refresh::InsertDataOp(name, bytes, app_key) => (name, bytes, app_key));
+    assert_eq!(bytes, 10);
+    assert_eq!(app_key, None);
 
     // Simulate receiving the refresh.
-    let refresh_in = Refresh::InsertDataOp(account_name);
+    let refresh_in = Refresh::InsertDataOp(account_name, bytes, app_key);
     let serialised_refresh_in = unwrap!(serialise(&refresh_in));
 
     unwrap!(mm.handle_serialised_refresh(&mut node, &serialised_refresh_in, msg_id, None));
@@ -851,6 +1602,43 @@ fn create_account(
     msg_id
 }
 
+// Sends a request to create an account for a new client with the invitation, returning its
+// message id.
+fn claim_invite(
+    node: &mut RoutingNode,
+    mm: &mut MaidManager,
+    invitation_string: &str,
+) -> MessageId {
+    let (client, client_key) = test_utils::gen_client_authority();
+    let client_manager = test_utils::gen_client_manager_authority(client_key);
+
+    let account_packet = AccountPacket::WithInvitation {
+        invitation_string: invitation_string.to_string(),
+        acc_pkt: Vec::new(),
+    };
+    let mut entries = BTreeMap::new();
+    let _ = entries.insert(
+        ACC_LOGIN_ENTRY_KEY.to_vec(),
+        Value {
+            content: unwrap!(serialise(&account_packet)),
+            entry_version: 0,
+        },
+    );
+    let mut owners = BTreeSet::new();
+    let _ = owners.insert(client_key);
+    let data = unwrap!(MutableData::new(
+        rand::random(),
+        TYPE_TAG_SESSION_PACKET,
+        Default::default(),
+        entries,
+        owners,
+    ));
+
+    let msg_id = MessageId::new();
+    unwrap!(mm.handle_put_mdata(node, client, client_manager, data, msg_id, client_key));
+    msg_id
+}
+
 fn get_account_info(
     node: &mut RoutingNode,
     mm: &mut MaidManager,
@@ -0,0 +1,199 @@
+// Copyright 2017 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Periodic point-in-time snapshots of the chunk store and account state of a running vault, for
+//! backups.
+//!
+//! Each snapshot is a directory named after its creation time in seconds since the Unix epoch,
+//! holding:
+//!
+//! * `chunks`: hard links to the chunk files, laid out as a disk chunk store, so they can be read
+//!   with the `inspect-store` and `export-store` commands.
+//! * `manifest.json`: the creation time, the identifiers of the saved chunks, those whose writes
+//!   were still in progress (their saved copies may be old, new or missing), the pending writes
+//!   which are not applied to the chunk store yet, and the client accounts.
+//!
+//! The chunks and accounts are listed on the event thread, but the files are linked and the
+//! manifest written on a separate thread, so the vault keeps handling events meanwhile.  A
+//! snapshot is built in a temporary directory and only synced and renamed into place once
+//! complete, so any directory with a numeric name is safe to copy.
+
+use chunk_store::{SnapshotJob, sync_dir};
+use config_handler::Config;
+use error::InternalError;
+use maidsafe_utilities::thread::{self, Joiner};
+use personas::data_manager::{DataId, DataManager, PendingWriteRecord};
+use personas::maid_manager::{Account, MaidManager};
+use routing::XorName;
+use serde_json;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::time::Duration;
+use utils::{self, Instant};
+
+/// The default time between snapshots.
+const DEFAULT_SNAPSHOT_INTERVAL_SECS: u64 = 24 * 60 * 60;
+/// The number of most recent snapshots kept.  Older ones are removed.
+const SNAPSHOTS_KEPT: usize = 3;
+const CHUNKS_DIR: &str = "chunks";
+const MANIFEST_FILE: &str = "manifest.json";
+/// Suffix of the directories of snapshots still being built.
+const TEMP_SUFFIX: &str = ".tmp";
+
+#[derive(Serialize)]
+struct Manifest {
+    created: u64,
+    chunks: Vec<DataId>,
+    writing: Vec<DataId>,
+    pending_writes: Vec<PendingWriteRecord>,
+    accounts: Vec<(XorName, Account)>,
+}
+
+/// Takes snapshots into the configured directory at the configured interval.
+pub struct Snapshots {
+    dir: PathBuf,
+    interval: Duration,
+    time: Instant,
+    // The thread saving the latest snapshot, and a receiver notified once it's done.
+    saving: Option<(Joiner, Receiver<()>)>,
+}
+
+impl Snapshots {
+    /// Returns `None` if snapshots aren't enabled in `config`.
+    pub fn new(config: &Config) -> Option<Self> {
+        config.snapshot_dir.as_ref().map(|dir| {
+            Snapshots {
+                dir: PathBuf::from(dir),
+                interval: Duration::from_secs(config.snapshot_interval_secs.unwrap_or(
+                    DEFAULT_SNAPSHOT_INTERVAL_SECS,
+                )),
+                time: Instant::now(),
+                saving: None,
+            }
+        })
+    }
+
+    /// Starts taking a snapshot if the interval has elapsed since the previous one, unless that
+    /// one is still being saved.  Old snapshots are removed once the new one is saved.
+    pub fn handle_tick(&mut self, data_manager: &DataManager, maid_manager: &MaidManager) {
+        if self.time.elapsed() < self.interval {
+            return;
+        }
+        if let Some((_, ref done)) = self.saving {
+            if let Err(TryRecvError::Empty) = done.try_recv() {
+                return;
+            }
+        }
+        self.time = Instant::now();
+        self.saving = None;
+
+        match self.start(data_manager, maid_manager) {
+            Ok(saving) => self.saving = Some(saving),
+            Err(error) => warn!("Failed to save snapshot: {:?}", error),
+        }
+    }
+
+    // Lists the chunks and accounts, and spawns a thread saving them.
+    fn start(
+        &self,
+        data_manager: &DataManager,
+        maid_manager: &MaidManager,
+    ) -> Result<(Joiner, Receiver<()>), InternalError> {
+        let (job, pending_writes) = data_manager.prepare_snapshot()?;
+        let manifest = Manifest {
+            created: utils::now_secs(),
+            chunks: Vec::new(),
+            writing: job.writing().to_vec(),
+            pending_writes,
+            accounts: maid_manager.snapshot(),
+        };
+
+        let dir = self.dir.clone();
+        let (done_tx, done_rx) = mpsc::channel();
+        let joiner = thread::named("Snapshot", move || {
+            match save(&dir, job, manifest) {
+                Ok(path) => info!("Saved snapshot {}.", path.display()),
+                Err(error) => warn!("Failed to save snapshot: {:?}", error),
+            }
+            if let Err(error) = prune(&dir) {
+                warn!("Failed to remove old snapshots: {:?}", error);
+            }
+            let _ = done_tx.send(());
+        });
+        Ok((joiner, done_rx))
+    }
+}
+
+// Saves a snapshot into `dir`.  Returns its directory.
+fn save(
+    dir: &Path,
+    job: SnapshotJob<DataId>,
+    manifest: Manifest,
+) -> Result<PathBuf, InternalError> {
+    let path = dir.join(manifest.created.to_string());
+    let temp_path = dir.join(format!("{}{}", manifest.created, TEMP_SUFFIX));
+
+    if temp_path.exists() {
+        fs::remove_dir_all(&temp_path)?;
+    }
+    let result = write_snapshot(&temp_path, job, manifest).and_then(|()| {
+        fs::rename(&temp_path, &path)?;
+        Ok(sync_dir(dir)?)
+    });
+    if result.is_err() {
+        let _ = fs::remove_dir_all(&temp_path);
+    }
+    result.map(|()| path)
+}
+
+fn write_snapshot(
+    dir: &Path,
+    job: SnapshotJob<DataId>,
+    mut manifest: Manifest,
+) -> Result<(), InternalError> {
+    manifest.chunks = job.save(&dir.join(CHUNKS_DIR))?;
+
+    let mut file = File::create(dir.join(MANIFEST_FILE))?;
+    serde_json::to_writer_pretty(&mut file, &manifest)?;
+    file.sync_all()?;
+    Ok(sync_dir(dir)?)
+}
+
+// Removes all but the `SNAPSHOTS_KEPT` most recent snapshots in `dir`, and any incomplete ones.
+fn prune(dir: &Path) -> Result<(), InternalError> {
+    let mut snapshots = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        if let Ok(created) = name.parse::<u64>() {
+            snapshots.push((created, entry.path()));
+        } else if name.ends_with(TEMP_SUFFIX) {
+            fs::remove_dir_all(entry.path())?;
+        }
+    }
+
+    snapshots.sort();
+    let count = snapshots.len().saturating_sub(SNAPSHOTS_KEPT);
+    for (_, path) in snapshots.into_iter().take(count) {
+        fs::remove_dir_all(path)?;
+    }
+    Ok(())
+}
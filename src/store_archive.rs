@@ -0,0 +1,310 @@
+// Copyright 2017 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Export of a chunk store to a single, portable archive file, and import of such an archive into
+//! another chunk store.
+//!
+//! An archive consists of a magic number, the format version, a manifest listing the `DataId`,
+//! version and hash of every chunk, and then the serialised chunks in the order of the manifest.
+
+use chunk_store::{self, ChunkStore};
+use error::InternalError;
+use maidsafe_utilities::serialisation;
+use personas::data_manager::{Data, DataId, ImmutableDataId};
+use routing::{ImmutableData, MutableData};
+use std::io::{Read, Write};
+use tiny_keccak;
+use utils::{self, SecureHash};
+
+/// Magic number identifying an archive.
+const ARCHIVE_MAGIC: [u8; 8] = *b"SAFECHNK";
+/// Version of the archive format.
+const ARCHIVE_VERSION: u8 = 1;
+
+/// Summary of exporting a chunk store.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ExportSummary {
+    /// Number of chunks written to the archive.
+    pub exported: usize,
+    /// Number of chunks left out because they couldn't be read or don't match their names.
+    pub skipped: usize,
+}
+
+/// Summary of importing an archive into a chunk store.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ImportSummary {
+    /// Number of chunks put into the store.
+    pub imported: usize,
+    /// Number of chunks left out because the store already holds them, at the same or a newer
+    /// version.
+    pub skipped: usize,
+    /// Number of chunks left out because they don't match their manifest entry.
+    pub rejected: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ManifestEntry {
+    data_id: DataId,
+    // Always zero for immutable data.
+    version: u64,
+    hash: SecureHash,
+}
+
+enum ImportOutcome {
+    Imported,
+    Skipped,
+    Rejected,
+}
+
+/// Writes every readable chunk of `chunk_store` to `writer`.
+pub fn export<W: Write>(
+    chunk_store: &ChunkStore<DataId>,
+    writer: &mut W,
+) -> Result<ExportSummary, InternalError> {
+    let mut summary = ExportSummary::default();
+
+    // The manifest goes first, so the chunks are read twice: once to build it, then to write them.
+    let mut manifest = Vec::new();
+    for data_id in chunk_store.keys() {
+        match read_chunk(chunk_store, &data_id) {
+            Some((version, serialised)) => {
+                manifest.push(ManifestEntry {
+                    data_id,
+                    version,
+                    hash: hash(&serialised),
+                });
+            }
+            None => summary.skipped += 1,
+        }
+    }
+
+    writer.write_all(&ARCHIVE_MAGIC)?;
+    writer.write_all(&[ARCHIVE_VERSION])?;
+    serialisation::serialise_into(&manifest, writer)?;
+
+    for entry in &manifest {
+        let serialised = match read_chunk(chunk_store, &entry.data_id) {
+            Some((_, serialised)) => serialised,
+            None => return Err(chunk_store::Error::NotFound.into()),
+        };
+        serialisation::serialise_into(&serialised, writer)?;
+        summary.exported += 1;
+    }
+
+    Ok(summary)
+}
+
+/// Reads an archive written by `export` from `reader` and puts its chunks into `chunk_store`.
+///
+/// Chunks already held by the store are kept, unless the archive holds a newer version of them.
+/// Fails if the archive is malformed or if the store runs out of space, in which case the chunks
+/// imported so far are kept.
+pub fn import<R: Read>(
+    chunk_store: &mut ChunkStore<DataId>,
+    reader: &mut R,
+) -> Result<ImportSummary, InternalError> {
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    let mut version = [0];
+    reader.read_exact(&mut version)?;
+    if magic != ARCHIVE_MAGIC || version[0] != ARCHIVE_VERSION {
+        return Err(InternalError::InvalidArchive);
+    }
+
+    let manifest: Vec<ManifestEntry> = serialisation::deserialise_from(reader)?;
+    let mut summary = ImportSummary::default();
+
+    for entry in &manifest {
+        let serialised: Vec<u8> = serialisation::deserialise_from(reader)?;
+        let outcome = if hash(&serialised) == entry.hash {
+            import_chunk(chunk_store, entry, &serialised)?
+        } else {
+            ImportOutcome::Rejected
+        };
+
+        match outcome {
+            ImportOutcome::Imported => summary.imported += 1,
+            ImportOutcome::Skipped => summary.skipped += 1,
+            ImportOutcome::Rejected => {
+                warn!("Rejected {:?} from the archive.", entry.data_id);
+                summary.rejected += 1;
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+// Returns the version and the serialised chunk, if it is readable and matches its name.
+fn read_chunk(chunk_store: &ChunkStore<DataId>, data_id: &DataId) -> Option<(u64, Vec<u8>)> {
+    let result = match *data_id {
+        DataId::Immutable(data_id) => {
+            match chunk_store.get(&data_id) {
+                Ok(ref data) if is_valid_idata(&data_id, data) => {
+                    serialisation::serialise(data).ok().map(|serialised| (0, serialised))
+                }
+                _ => None,
+            }
+        }
+        DataId::Mutable(data_id) => {
+            match chunk_store.get(&data_id) {
+                Ok(ref data) if data.id() == data_id => {
+                    serialisation::serialise(data).ok().map(|serialised| {
+                        (data.version(), serialised)
+                    })
+                }
+                _ => None,
+            }
+        }
+    };
+
+    if result.is_none() {
+        warn!("Skipped unreadable {:?}.", data_id);
+    }
+    result
+}
+
+fn import_chunk(
+    chunk_store: &mut ChunkStore<DataId>,
+    entry: &ManifestEntry,
+    serialised: &[u8],
+) -> Result<ImportOutcome, InternalError> {
+    match entry.data_id {
+        DataId::Immutable(data_id) => {
+            let data: ImmutableData = match serialisation::deserialise(serialised) {
+                Ok(data) => data,
+                Err(_) => return Ok(ImportOutcome::Rejected),
+            };
+            if !is_valid_idata(&data_id, &data) {
+                return Ok(ImportOutcome::Rejected);
+            }
+            if chunk_store.has(&data_id) {
+                return Ok(ImportOutcome::Skipped);
+            }
+            chunk_store.put(&data_id, &data)?;
+        }
+        DataId::Mutable(data_id) => {
+            let data: MutableData = match serialisation::deserialise(serialised) {
+                Ok(data) => data,
+                Err(_) => return Ok(ImportOutcome::Rejected),
+            };
+            if data.id() != data_id || data.version() != entry.version {
+                return Ok(ImportOutcome::Rejected);
+            }
+            if let Ok(existing) = chunk_store.get(&data_id) {
+                if existing.version() >= data.version() {
+                    return Ok(ImportOutcome::Skipped);
+                }
+            }
+            chunk_store.put(&data_id, &data)?;
+        }
+    }
+
+    Ok(ImportOutcome::Imported)
+}
+
+fn is_valid_idata(data_id: &ImmutableDataId, data: &ImmutableData) -> bool {
+    tiny_keccak::sha3_256(data.value()) == data_id.name().0
+}
+
+fn hash(serialised: &[u8]) -> SecureHash {
+    utils::secure_hash(&serialised)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chunk_store::MemoryBackend;
+    use config_handler::Compression;
+    use rand;
+    use routing::XorName;
+    use rust_sodium::crypto::sign;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    fn new_chunk_store() -> ChunkStore<DataId> {
+        unwrap!(ChunkStore::new(
+            Box::new(MemoryBackend::new()),
+            None,
+            Compression::None,
+            None,
+        ))
+    }
+
+    fn new_mdata(name: XorName, version: u64) -> MutableData {
+        let mut owners = BTreeSet::new();
+        let _ = owners.insert(sign::gen_keypair().0);
+        let mut data = unwrap!(MutableData::new(
+            name,
+            10_000,
+            BTreeMap::new(),
+            BTreeMap::new(),
+            owners,
+        ));
+        if version > 0 {
+            assert!(data.change_owner_without_validation(sign::gen_keypair().0, version));
+        }
+        data
+    }
+
+    #[test]
+    fn export_and_import() {
+        let idata = ImmutableData::new(vec![1; 10]);
+        let mdata_name = rand::random();
+        let mdata = new_mdata(mdata_name, 2);
+
+        let mut source = new_chunk_store();
+        unwrap!(source.put(&idata.id(), &idata));
+        unwrap!(source.put(&mdata.id(), &mdata));
+        // A chunk not matching its name isn't exported.
+        unwrap!(source.put(&ImmutableDataId(rand::random()), &idata));
+
+        let mut archive = Vec::new();
+        let summary = unwrap!(export(&source, &mut archive));
+        assert_eq!(summary.exported, 2);
+        assert_eq!(summary.skipped, 1);
+
+        // The target already holds an older version of the mutable data.
+        let mut target = new_chunk_store();
+        unwrap!(target.put(&mdata.id(), &new_mdata(mdata_name, 1)));
+
+        let summary = unwrap!(import(&mut target, &mut &archive[..]));
+        assert_eq!(summary.imported, 2);
+        assert_eq!(summary.skipped, 0);
+        assert_eq!(summary.rejected, 0);
+        assert_eq!(unwrap!(target.get(&idata.id())), idata);
+        assert_eq!(unwrap!(target.get(&mdata.id())).version(), 2);
+
+        // Importing again changes nothing.
+        let summary = unwrap!(import(&mut target, &mut &archive[..]));
+        assert_eq!(summary.imported, 0);
+        assert_eq!(summary.skipped, 2);
+
+        // Tampered chunks are rejected.
+        let last = archive.len() - 1;
+        archive[last] ^= 1;
+        let mut target = new_chunk_store();
+        let summary = unwrap!(import(&mut target, &mut &archive[..]));
+        assert_eq!(summary.imported + summary.rejected, 2);
+        assert_eq!(summary.rejected, 1);
+
+        // Anything else isn't an archive.
+        match import(&mut target, &mut &[0u8; 16][..]) {
+            Err(InternalError::InvalidArchive) => (),
+            result => panic!("Unexpected {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,362 @@
+// Copyright 2017 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+//! Offline inspection, repair, export and import of a vault's chunk store.
+
+use chunk_store::{self, Backend, ChunkStore, DiskBackend};
+use config_handler::{self, Compression, Config};
+use error::InternalError;
+use maidsafe_utilities::serialisation;
+use personas::data_manager::{DataId, ImmutableDataId, MutableDataId};
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use store_archive::{self, ExportSummary, ImportSummary};
+use tiny_keccak;
+
+/// Report about a single chunk file.
+#[derive(Debug)]
+pub struct ChunkReport {
+    /// The key the chunk is stored under.  The chunk file is named after its hex encoding.
+    pub key: Vec<u8>,
+    /// Identifier of the chunk, if the key decodes to one.
+    pub data_id: Option<DataId>,
+    /// Size of the chunk file in bytes.
+    pub size: u64,
+    /// Version of the chunk, if it is readable mutable data.
+    pub version: Option<u64>,
+    /// The problem found with the chunk, if any.
+    pub problem: Option<ChunkProblem>,
+}
+
+/// A problem found with a chunk file.
+#[derive(Debug)]
+pub enum ChunkProblem {
+    /// The key doesn't decode to a `DataId`.
+    UnrecognisedKey,
+    /// The chunk can't be read, e.g. because it is corrupted or fails to deserialise.
+    Unreadable(chunk_store::Error),
+    /// The chunk can't be decrypted, most likely because the configured key is wrong.
+    Undecryptable,
+    /// The chunk content doesn't match its name (or type tag).
+    NameMismatch,
+}
+
+impl Display for ChunkProblem {
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match *self {
+            ChunkProblem::UnrecognisedKey => write!(formatter, "unrecognised key"),
+            ChunkProblem::Unreadable(ref error) => write!(formatter, "unreadable: {}", error),
+            ChunkProblem::Undecryptable => write!(formatter, "can't be decrypted"),
+            ChunkProblem::NameMismatch => write!(formatter, "content doesn't match name"),
+        }
+    }
+}
+
+/// Opens a chunk store directory while the vault is not running, to list, verify, repair, export
+/// and import the chunks in it.
+///
+/// The store is opened as it is: it is never cleaned up or migrated, and only created by
+/// `StoreInspector::create`.  It is locked while open, so opening it fails with
+/// `chunk_store::Error::Locked` while the vault (or another inspector) is using it.
+pub struct StoreInspector {
+    // Used for raw access to the chunk files.
+    backend: DiskBackend,
+    // Used for reading the chunks, which may be compressed and encrypted.
+    chunk_store: ChunkStore<DataId>,
+}
+
+impl StoreInspector {
+    /// Opens the chunk store directory `dir`, or if that's `None`, the persistent chunk store
+    /// directory of the vault as per its config file.
+    ///
+    /// The encryption key, if any, is read from the key file in the vault config file.
+    pub fn open(dir: Option<PathBuf>) -> Result<Self, InternalError> {
+        Self::with_config(dir, config_handler::read_config_file()?, false)
+    }
+
+    /// Like `open`, but creates the chunk store directory if it doesn't exist yet, e.g. to import
+    /// an archive into the store of a new vault.
+    pub fn create(dir: Option<PathBuf>) -> Result<Self, InternalError> {
+        Self::with_config(dir, config_handler::read_config_file()?, true)
+    }
+
+    fn with_config(
+        dir: Option<PathBuf>,
+        config: Config,
+        create: bool,
+    ) -> Result<Self, InternalError> {
+        let encryption_key = match config.chunk_store_key_file {
+            Some(ref path) => Some(chunk_store::read_key_file(path)?),
+            None => None,
+        };
+        let compression = config.chunk_store_compression.unwrap_or(Compression::None);
+        let max_capacity = config.max_capacity;
+        let allow_plaintext = config.chunk_store_allow_plaintext.unwrap_or(false);
+        let dir = dir.unwrap_or_else(|| DiskBackend::persistent_dir(config.chunk_store_root));
+        if create {
+            fs::create_dir_all(&dir)?;
+        }
+
+        let backend = DiskBackend::open(dir.clone())?;
+        let values = if create {
+            Some(backend.value_store()?)
+        } else {
+            backend.open_value_store()
+        };
+        let mut chunk_store = ChunkStore::new(
+            Box::new(backend.reopen()),
+            max_capacity,
+            compression,
+            encryption_key,
+        )?;
+        if let Some(values) = values {
+            chunk_store.set_value_store(Box::new(values), false);
+        }
+        chunk_store.set_allow_plaintext(allow_plaintext);
+
+        Ok(StoreInspector {
+            backend,
+            chunk_store,
+        })
+    }
+
+    /// Returns the chunk store directory.
+    pub fn path(&self) -> &Path {
+        self.backend.path()
+    }
+
+    /// Reads and checks every chunk in the store.
+    pub fn inspect(&self) -> Vec<ChunkReport> {
+        let mut reports: Vec<_> = self.backend
+            .keys()
+            .into_iter()
+            .map(|key| self.inspect_chunk(&key))
+            .collect();
+        reports.sort_by(|lhs, rhs| lhs.data_id.cmp(&rhs.data_id));
+        reports
+    }
+
+    /// Moves the chunk described by `report` out of the store, into its `quarantine`
+    /// subdirectory, and releases the values it shares with other chunks.  Returns the new path of
+    /// the chunk file.
+    pub fn quarantine(&mut self, report: &ChunkReport) -> Result<PathBuf, InternalError> {
+        let backend = &self.backend;
+        Ok(self.chunk_store.remove_with(
+            &report.key,
+            |key| backend.quarantine(key),
+        )?)
+    }
+
+    /// Writes every readable chunk in the store to the archive file at `path`, replacing the file
+    /// if it exists.
+    pub fn export<P: AsRef<Path>>(&self, path: P) -> Result<ExportSummary, InternalError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let summary = store_archive::export(&self.chunk_store, &mut writer)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        Ok(summary)
+    }
+
+    /// Puts the chunks from the archive file at `path`, written by `export`, into the store.
+    pub fn import<P: AsRef<Path>>(&mut self, path: P) -> Result<ImportSummary, InternalError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        store_archive::import(&mut self.chunk_store, &mut reader)
+    }
+
+    fn inspect_chunk(&self, key: &[u8]) -> ChunkReport {
+        let mut report = ChunkReport {
+            key: key.to_vec(),
+            data_id: serialisation::deserialise(key).ok(),
+            size: self.backend.size(key).unwrap_or(0),
+            version: None,
+            problem: None,
+        };
+
+        report.problem = match report.data_id {
+            None => Some(ChunkProblem::UnrecognisedKey),
+            Some(DataId::Immutable(data_id)) => self.check_idata(&data_id),
+            Some(DataId::Mutable(data_id)) => {
+                match self.chunk_store.get(&data_id) {
+                    Ok(data) => {
+                        report.version = Some(data.version());
+                        if MutableDataId(*data.name(), data.tag()) == data_id {
+                            None
+                        } else {
+                            Some(ChunkProblem::NameMismatch)
+                        }
+                    }
+                    Err(error) => Some(read_error_to_problem(error)),
+                }
+            }
+        };
+        report
+    }
+
+    fn check_idata(&self, data_id: &ImmutableDataId) -> Option<ChunkProblem> {
+        match self.chunk_store.get(data_id) {
+            Ok(data) => {
+                if tiny_keccak::sha3_256(data.value()) == data_id.name().0 {
+                    None
+                } else {
+                    Some(ChunkProblem::NameMismatch)
+                }
+            }
+            Err(error) => Some(read_error_to_problem(error)),
+        }
+    }
+}
+
+fn read_error_to_problem(error: chunk_store::Error) -> ChunkProblem {
+    match error {
+        chunk_store::Error::Decryption => ChunkProblem::Undecryptable,
+        error => ChunkProblem::Unreadable(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use personas::data_manager::Data;
+    use rand;
+    use routing::{ImmutableData, MutableData, Value};
+    use rust_sodium::crypto::sign;
+    use std::collections::{BTreeMap, BTreeSet};
+    use tempdir::TempDir;
+
+    #[test]
+    fn inspect_and_quarantine() {
+        let temp_dir = unwrap!(TempDir::new("safe_vault_test"));
+        let root = unwrap!(temp_dir.path().to_str()).to_string();
+        let config = Config {
+            chunk_store_root: Some(root.clone()),
+            ..Default::default()
+        };
+
+        let idata = ImmutableData::new(vec![1; 10]);
+        let mut owners = BTreeSet::new();
+        let _ = owners.insert(sign::gen_keypair().0);
+        let mdata = unwrap!(MutableData::new(
+            rand::random(),
+            10_000,
+            BTreeMap::new(),
+            BTreeMap::new(),
+            owners,
+        ));
+        let misplaced_idata_id = ImmutableDataId(rand::random());
+
+        {
+            let mut backend = unwrap!(DiskBackend::new(Some(root), true));
+            // A chunk whose key isn't a `DataId`.
+            unwrap!(backend.put(&[1, 2, 3], &[4, 5, 6]));
+
+            let mut chunk_store = unwrap!(ChunkStore::<DataId>::new(
+                Box::new(backend),
+                None,
+                Compression::None,
+                None,
+            ));
+            unwrap!(chunk_store.put(&idata.id(), &idata));
+            unwrap!(chunk_store.put(&misplaced_idata_id, &idata));
+            unwrap!(chunk_store.put(&mdata.id(), &mdata));
+        }
+
+        let mut inspector = unwrap!(StoreInspector::with_config(None, config, false));
+        let reports = inspector.inspect();
+        assert_eq!(reports.len(), 4);
+
+        for report in &reports {
+            match (report.data_id, &report.problem) {
+                (None, &Some(ChunkProblem::UnrecognisedKey)) |
+                (Some(DataId::Immutable(_)), &None) => (),
+                (Some(DataId::Immutable(data_id)), &Some(ChunkProblem::NameMismatch)) => {
+                    assert_eq!(data_id, misplaced_idata_id)
+                }
+                (Some(DataId::Mutable(_)), &None) => assert_eq!(report.version, Some(0)),
+                (_, problem) => panic!("Unexpected {:?} for {:?}", problem, report.data_id),
+            }
+        }
+
+        for report in reports.iter().filter(|report| report.problem.is_some()) {
+            let path = unwrap!(inspector.quarantine(report));
+            assert!(path.exists());
+        }
+
+        let reports = inspector.inspect();
+        assert_eq!(reports.len(), 2);
+        assert!(reports.iter().all(|report| report.problem.is_none()));
+    }
+
+    #[test]
+    fn quarantine_releases_shared_values() {
+        let temp_dir = unwrap!(TempDir::new("safe_vault_test"));
+        let root = unwrap!(temp_dir.path().to_str()).to_string();
+        let config = Config {
+            chunk_store_root: Some(root.clone()),
+            ..Default::default()
+        };
+
+        let mut entries = BTreeMap::new();
+        let _ = entries.insert(
+            vec![0],
+            Value {
+                content: vec![1; 2000],
+                entry_version: 0,
+            },
+        );
+        let mut owners = BTreeSet::new();
+        let _ = owners.insert(sign::gen_keypair().0);
+        let mdata = unwrap!(MutableData::new(
+            rand::random(),
+            10_000,
+            BTreeMap::new(),
+            entries,
+            owners,
+        ));
+        // Stored under the wrong name, so it needs to be quarantined.
+        let misplaced_mdata_id = MutableDataId(rand::random(), 10_000);
+
+        let values_dir;
+        {
+            let backend = unwrap!(DiskBackend::new(Some(root), true));
+            values_dir = backend.path().join("values");
+            let values = unwrap!(backend.value_store());
+            let mut chunk_store = unwrap!(ChunkStore::<DataId>::new(
+                Box::new(backend),
+                None,
+                Compression::None,
+                None,
+            ));
+            chunk_store.set_value_store(Box::new(values), true);
+            unwrap!(chunk_store.put(&misplaced_mdata_id, &mdata));
+        }
+        assert!(!unwrap!(DiskBackend::open(values_dir.clone())).keys().is_empty());
+
+        {
+            let mut inspector = unwrap!(StoreInspector::with_config(None, config, false));
+            let reports = inspector.inspect();
+            assert_eq!(reports.len(), 1);
+            match reports[0].problem {
+                Some(ChunkProblem::NameMismatch) => (),
+                ref problem => panic!("Unexpected {:?}", problem),
+            }
+            let _ = unwrap!(inspector.quarantine(&reports[0]));
+        }
+        assert!(unwrap!(DiskBackend::open(values_dir)).keys().is_empty());
+    }
+}
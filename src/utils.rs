@@ -28,6 +28,7 @@ use std::collections::hash_map::DefaultHasher;
 use std::hash::BuildHasherDefault;
 #[cfg(not(any(test, feature = "use-mock-crust", feature = "use-mock-routing")))]
 pub use std::time::Instant;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tiny_keccak;
 
 #[derive(Clone, Copy, Debug, Default, Eq, Ord, PartialEq, PartialOrd, Hash, Serialize, Deserialize)]
@@ -81,3 +82,17 @@ pub fn verify_mdata_owner(data: &MutableData, client_name: &XorName) -> bool {
         .map(|owner_key| client_name_from_key(owner_key))
         .any(|name| name == *client_name)
 }
+
+/// Returns the number of bytes a `MutableData` entry is charged to its owner's account: its
+/// serialised size.
+pub fn mdata_entry_size(key: &[u8], value: &Value) -> u64 {
+    serialisation::serialised_size(&(key, value))
+}
+
+/// Returns the current time in seconds since the Unix epoch, as used for expiry timestamps.
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
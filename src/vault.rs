@@ -42,13 +42,22 @@ use routing::NodeBuilder;
 use routing::mock_crypto::rust_sodium;
 #[cfg(not(feature = "use-mock-crypto"))]
 use rust_sodium;
-use rust_sodium::crypto::sign;
+use snapshot::Snapshots;
+use std::cmp;
+use std::sync::mpsc::TryRecvError;
+use std::thread;
+use std::time::Duration;
+
+/// Time in milliseconds the event loop waits for chunk store writes before polling Routing again,
+/// while committed mutations are waiting for them.
+const WRITE_WAIT_INTERVAL_MS: u64 = 10;
 
 /// Main struct to hold all personas and Routing instance
 pub struct Vault {
     maid_manager: MaidManager,
     data_manager: DataManager,
     routing_node: RoutingNode,
+    snapshots: Option<Snapshots>,
 }
 
 impl Vault {
@@ -68,9 +77,6 @@ impl Vault {
         config: Config,
     ) -> Result<Self, InternalError> {
         let _ = rust_sodium::init();
-        let disable_mutation_limit = config.dev.as_ref().map_or(false, |dev_config| {
-            dev_config.disable_mutation_limit
-        });
         let routing_node = if use_cache {
             builder.cache(Box::new(Cache::new())).create()
         } else {
@@ -79,23 +85,57 @@ impl Vault {
         let group_size = routing_node.min_section_size();
 
         Ok(Vault {
-            maid_manager: MaidManager::new(
-                group_size,
-                config.invite_key.map(sign::PublicKey),
-                disable_mutation_limit,
-            ),
-            data_manager: DataManager::new(
-                group_size,
-                config.chunk_store_root,
-                config.max_capacity,
-            )?,
+            maid_manager: MaidManager::new(group_size, &config)?,
+            data_manager: DataManager::new(group_size, &config)?,
             routing_node: routing_node,
+            snapshots: Snapshots::new(&config),
         })
     }
 
     /// Run the event loop, processing events received from Routing.
+    ///
+    /// While committed mutations are waiting for their chunk store writes, Routing is polled in
+    /// between short waits for the writes, so the mutations are responded to as soon as the writes
+    /// complete rather than when the next event arrives.  Likewise, while chunk store writes are
+    /// waiting to be synced to disk, Routing is polled until the sync is due.
     pub fn run(&mut self) -> Result<bool, InternalError> {
-        while let Ok(event) = self.routing_node.next_ev() {
+        loop {
+            let event = if self.data_manager.has_pending_commits() {
+                match self.routing_node.try_next_ev() {
+                    Ok(event) => event,
+                    Err(TryRecvError::Empty) => {
+                        self.data_manager.wait_for_writes(
+                            Duration::from_millis(WRITE_WAIT_INTERVAL_MS),
+                        );
+                        if let Err(error) =
+                            self.data_manager.handle_completed_writes(&mut self.routing_node)
+                        {
+                            debug!("Failed to handle completed writes: {:?}", error);
+                        }
+                        continue;
+                    }
+                    Err(TryRecvError::Disconnected) => break,
+                }
+            } else if let Some(time_until_sync) = self.data_manager.time_until_sync() {
+                match self.routing_node.try_next_ev() {
+                    Ok(event) => event,
+                    Err(TryRecvError::Empty) => {
+                        thread::sleep(cmp::min(
+                            time_until_sync,
+                            Duration::from_millis(WRITE_WAIT_INTERVAL_MS),
+                        ));
+                        self.data_manager.sync_if_due();
+                        continue;
+                    }
+                    Err(TryRecvError::Disconnected) => break,
+                }
+            } else {
+                match self.routing_node.next_ev() {
+                    Ok(event) => event,
+                    Err(_) => break,
+                }
+            };
+
             match self.process_event(event) {
                 EventResult::Terminate => return Ok(true),
                 EventResult::Restart => return Ok(false),
@@ -130,10 +170,17 @@ impl Vault {
                 res = EventResult::Terminate;
                 Ok(())
             }
+            Event::Tick => {
+                res = EventResult::Ignored;
+                if let Some(ref mut snapshots) = self.snapshots {
+                    snapshots.handle_tick(&self.data_manager, &self.maid_manager);
+                }
+                let mm_res = self.maid_manager.handle_tick(&mut self.routing_node);
+                self.data_manager.handle_tick(&mut self.routing_node).and(mm_res)
+            }
             Event::SectionSplit(_) |
             Event::SectionMerge(_) |
-            Event::Connected |
-            Event::Tick => {
+            Event::Connected => {
                 res = EventResult::Ignored;
                 Ok(())
             }
@@ -143,7 +190,12 @@ impl Vault {
             debug!("Failed to handle event: {:?}", error);
         }
 
+        self.maid_manager.sync_db();
         self.data_manager.check_timeouts(&mut self.routing_node);
+        self.data_manager.sync_if_due();
+        if let Err(error) = self.data_manager.handle_completed_writes(&mut self.routing_node) {
+            debug!("Failed to handle completed writes: {:?}", error);
+        }
         res
     }
 
@@ -198,6 +250,11 @@ impl Vault {
                     &serialised_msg,
                 )
             }
+            (Authority::NaeManager(_),
+             Authority::ClientManager(name),
+             Request::Refresh(serialised_msg, _)) => {
+                self.maid_manager.handle_serialised_bytes_change(name, &serialised_msg)
+            }
             (Authority::NaeManager(_),
              Authority::NaeManager(_),
              Request::Refresh(serialised_msg, _)) => {
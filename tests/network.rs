@@ -42,11 +42,8 @@ fn fill_network() {
     let mut rng = network.new_rng();
 
     let config = Config {
-        wallet_address: None,
         max_capacity: Some(2000),
-        chunk_store_root: None,
-        invite_key: None,
-        dev: None,
+        ..Default::default()
     };
 
     let mut nodes = test_node::create_nodes(&network, 8, Some(config), true);